@@ -0,0 +1,237 @@
+//! Processing the audio files inside zip and tar archives, with
+//! [`ProcessOptions::archives`](crate::ProcessOptions::archives).
+//!
+//! Archives are unpacked into a temporary folder with `unzip` or `tar`, their
+//! audio files are processed there like any folder, and the archive is packed
+//! again with `zip` or `tar`. On systems without `unzip` and `zip` (e.g.,
+//! Windows), zips are handled by `tar`, which reads and writes them there.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+
+use log::{error, info};
+
+use crate::{
+    FileReport, FileStatus, NEXT_TEMP_ID, PlannedFile, ProcessOptions, backup, clean, exec,
+    move_file, process_audio_files_with_options, temp_path,
+};
+
+/// Suffixes of the archives whose entries can be processed, in lowercase.
+const ARCHIVE_SUFFIXES: [&str; 8] = [
+    ".zip", ".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tbz2", ".tar.xz", ".txz",
+];
+
+/// Returns whether `path` has the suffix of a zip or tar archive.
+pub(crate) fn is_archive(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .is_some_and(|name| ARCHIVE_SUFFIXES.iter().any(|s| name.ends_with(s)))
+}
+
+/// Returns whether `path` is a zip archive rather than a tar one.
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// Unpacks the archive `file`, processes its audio files and packs it again
+/// in place, or under [`ProcessOptions::output_dir`]. The archive is only
+/// rewritten if at least one of its files was processed.
+///
+/// # Returns
+///
+/// * `Vec<FileReport>` - One report per file of the archive, with paths such as
+///   `book.zip/01.mp3` made of the archive's path and the file's path inside it, or a
+///   single failed report for the archive if it could not be unpacked or packed.
+pub(crate) fn process_archive(file: &PlannedFile, options: &ProcessOptions) -> Vec<FileReport> {
+    let work = options
+        .local_temp_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!(
+            "{}archive-{}-{}",
+            clean::TEMP_PREFIX,
+            std::process::id(),
+            NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+    let result = std::fs::create_dir_all(&work)
+        .map_err(|e| format!("cannot create {}: {}", work.display(), e))
+        .and_then(|_| rewrite_archive(file, options, &work));
+    if let Err(e) = std::fs::remove_dir_all(&work) {
+        error!("Error removing temp folder {}: {}", work.display(), e);
+    }
+    result.unwrap_or_else(|e| {
+        error!("Error processing archive {}: {}", file.path.display(), e);
+        vec![FileReport {
+            status: FileStatus::Failed,
+            stderr: Some(e),
+            ..FileReport::from_planned(file)
+        }]
+    })
+}
+
+/// Does the work of [`process_archive`], unpacking into `work`.
+fn rewrite_archive(
+    file: &PlannedFile,
+    options: &ProcessOptions,
+    work: &Path,
+) -> Result<Vec<FileReport>, String> {
+    let path = &file.path;
+    unpack(path, work)?;
+    // The files of the archive are processed where they were unpacked
+    let entry_options = ProcessOptions {
+        archives: false,
+        output_dir: None,
+        backup_dir: None,
+        journal: None,
+        log_file: None,
+        pre_hook: None,
+        post_hook: None,
+        stage_local: false,
+        record_durations: false,
+        progress_bar: false,
+        ..options.clone()
+    };
+    let report =
+        process_audio_files_with_options(work, &entry_options).map_err(|e| e.to_string())?;
+    let final_path = match &options.output_dir {
+        Some(output_dir) => output_dir.join(&file.relative_path),
+        None => path.clone(),
+    };
+    let processed = report.processed_count();
+    let reports = report
+        .files
+        .into_iter()
+        .map(|mut report| {
+            let inner = report.path.strip_prefix(work).unwrap_or(&report.path);
+            report.path = path.join(inner);
+            report.output_path = report
+                .output_path
+                .map(|output| final_path.join(output.strip_prefix(work).unwrap_or(&output)));
+            report
+        })
+        .collect();
+    if processed == 0 {
+        return Ok(reports);
+    }
+
+    if let Some(parent) = final_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("cannot create {}: {}", parent.display(), e))?;
+    }
+    let packed = temp_path(&final_path)
+        .ok_or_else(|| format!("invalid archive name {}", final_path.display()))?;
+    _ = std::fs::remove_file(&packed);
+    if let Err(e) = pack(work, &packed) {
+        _ = std::fs::remove_file(&packed);
+        return Err(e);
+    }
+    if let Some(backup_dir) = &options.backup_dir
+        && final_path == *path
+        && let Err(e) = backup::backup_file(
+            path,
+            backup_dir,
+            &file.relative_path,
+            options.io_limit.as_ref(),
+        )
+    {
+        _ = std::fs::remove_file(&packed);
+        return Err(format!("cannot back up the archive: {}", e));
+    }
+    move_file(&packed, &final_path, options.io_limit.as_ref()).map_err(|e| {
+        _ = std::fs::remove_file(&packed);
+        format!("cannot replace {}: {}", final_path.display(), e)
+    })?;
+    info!(
+        "Rewrote {} with {} processed files",
+        final_path.display(),
+        processed
+    );
+    Ok(reports)
+}
+
+/// Unpacks `archive` into `dir`.
+fn unpack(archive: &Path, dir: &Path) -> Result<(), String> {
+    let with_tar = || {
+        run(
+            exec::command("tar")
+                .arg("-xf")
+                .arg(archive)
+                .arg("-C")
+                .arg(dir),
+            "tar",
+        )
+    };
+    if !is_zip(archive) {
+        return with_tar().map_err(|e| format!("cannot unpack: {}", e));
+    }
+    match run(
+        exec::command("unzip")
+            .arg("-qo")
+            .arg(archive)
+            .arg("-d")
+            .arg(dir),
+        "unzip",
+    ) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => with_tar(),
+        result => result,
+    }
+    .map_err(|e| format!("cannot unpack: {}", e))
+}
+
+/// Packs the contents of `dir` into the new archive `archive`, whose format
+/// follows its suffix.
+fn pack(dir: &Path, archive: &Path) -> Result<(), String> {
+    let entries: Vec<OsString> = std::fs::read_dir(dir)
+        .and_then(|entries| entries.map(|e| e.map(|e| e.file_name())).collect())
+        .map_err(|e| format!("cannot list {}: {}", dir.display(), e))?;
+    let archive = std::path::absolute(archive).map_err(|e| e.to_string())?;
+    // `-a` picks the format (or compression) from the suffix
+    let with_tar = || {
+        run(
+            exec::command("tar")
+                .arg("-a")
+                .arg("-cf")
+                .arg(&archive)
+                .arg("-C")
+                .arg(dir)
+                .args(&entries),
+            "tar",
+        )
+    };
+    if !is_zip(&archive) {
+        return with_tar().map_err(|e| format!("cannot pack: {}", e));
+    }
+    match run(
+        exec::command("zip")
+            .args(["-qrX"])
+            .arg(&archive)
+            .args(&entries)
+            .current_dir(dir),
+        "zip",
+    ) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => with_tar(),
+        result => result,
+    }
+    .map_err(|e| format!("cannot pack: {}", e))
+}
+
+/// Runs `command`, an archiving `tool`, and fails with its error output if it
+/// does not succeed.
+fn run(command: &mut Command, tool: &str) -> io::Result<()> {
+    let output = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+    Ok(())
+}
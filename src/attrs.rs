@@ -0,0 +1,131 @@
+//! Copying the permissions and extended attributes of a file onto its
+//! replacement.
+
+use std::io;
+use std::path::Path;
+
+/// Copies the extended attributes (which also hold ACLs on Linux) and then
+/// the permissions of `from` onto `to`. Attributes the destination cannot
+/// hold, or that the current user may not set (e.g., `security.*`), are left
+/// out.
+pub(crate) fn copy_attrs(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+    xattr::copy_xattrs(from, to)?;
+    std::fs::set_permissions(to, std::fs::metadata(from)?.permissions())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+mod xattr {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use libc::{c_char, c_void};
+    use log::debug;
+
+    pub(super) fn copy_xattrs(from: &Path, to: &Path) -> io::Result<()> {
+        let from = c_path(from)?;
+        let to = c_path(to)?;
+        let names = match read_buffer(|buf, len| list(&from, buf, len)) {
+            Ok(names) => names,
+            Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+            let name = CString::new(name).expect("Internal Error: nul in an attribute name");
+            let value = read_buffer(|buf, len| get(&from, &name, buf.cast(), len))?;
+            if set(&to, &name, &value) == 0 {
+                continue;
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::ENOTSUP) => return Ok(()),
+                Some(libc::EPERM | libc::EACCES) => {
+                    debug!("Not allowed to copy the attribute {:?}: {}", name, e)
+                }
+                _ => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Calls `call` once to learn the size of the data, then again to read
+    /// it, starting over if it grew in between.
+    fn read_buffer(mut call: impl FnMut(*mut c_char, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = call(std::ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer = vec![0u8; size as usize];
+            let read = call(buffer.as_mut_ptr().cast(), buffer.len());
+            if read >= 0 {
+                buffer.truncate(read as usize);
+                return Ok(buffer);
+            }
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ERANGE) {
+                return Err(e);
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn list(path: &CString, buf: *mut c_char, len: usize) -> isize {
+        // SAFETY: `path` is nul-terminated and `buf` is null or valid for `len` bytes.
+        unsafe { libc::listxattr(path.as_ptr(), buf, len) }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn get(path: &CString, name: &CString, buf: *mut c_void, len: usize) -> isize {
+        // SAFETY: as in `list`.
+        unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), buf, len) }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn set(path: &CString, name: &CString, value: &[u8]) -> i32 {
+        // SAFETY: as in `list`, with `value` valid for its length.
+        unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn list(path: &CString, buf: *mut c_char, len: usize) -> isize {
+        // SAFETY: `path` is nul-terminated and `buf` is null or valid for `len` bytes.
+        unsafe { libc::listxattr(path.as_ptr(), buf, len, 0) }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn get(path: &CString, name: &CString, buf: *mut c_void, len: usize) -> isize {
+        // SAFETY: as in `list`.
+        unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), buf, len, 0, 0) }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn set(path: &CString, name: &CString, value: &[u8]) -> i32 {
+        // SAFETY: as in `list`, with `value` valid for its length.
+        unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+                0,
+            )
+        }
+    }
+}
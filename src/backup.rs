@@ -0,0 +1,59 @@
+//! Backups of original files and restoring from them.
+
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+use crate::{IoLimit, Result, throttle};
+
+/// Copies `original` to `backup_dir/relative_path` within `io_limit`,
+/// creating parent directories as needed.
+pub(crate) fn backup_file(
+    original: &Path,
+    backup_dir: &Path,
+    relative_path: &Path,
+    io_limit: Option<&IoLimit>,
+) -> std::io::Result<()> {
+    let backup = backup_dir.join(relative_path);
+    if let Some(parent) = backup.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    throttle::copy_file(original, &backup, io_limit)?;
+    Ok(())
+}
+
+/// Restores originals from a backup folder made with
+/// [`ProcessOptions::backup_dir`](crate::ProcessOptions::backup_dir), copying
+/// every file in `backup_dir` back to the same relative path under `target`.
+///
+/// # Arguments
+///
+/// * `backup_dir` - The folder containing the backups.
+/// * `target` - The folder that was processed.
+///
+/// # Returns
+///
+/// * `Result<usize>` - The number of files restored.
+pub fn restore_backups(backup_dir: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<usize> {
+    let backup_dir = backup_dir.as_ref();
+    let target = target.as_ref();
+    let mut restored = 0;
+    for entry in WalkDir::new(backup_dir) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(backup_dir)
+            .map_err(std::io::Error::other)?;
+        let destination = target.join(relative);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(entry.path(), &destination)?;
+        log::debug!("Restored {}", destination.display());
+        restored += 1;
+    }
+    Ok(restored)
+}
@@ -0,0 +1,122 @@
+//! Tempo (BPM) detection for normalizing tracks to a target tempo.
+//!
+//! The audio is decoded to mono at a low sample rate, reduced to an onset
+//! envelope (the rises in short-term energy), and the beat period is the lag
+//! at which that envelope best correlates with itself.
+
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::{CommandRunner, Error, Result, exec};
+
+/// Sample rate the audio is decoded at for analysis.
+const SAMPLE_RATE: usize = 11025;
+/// Number of samples per energy frame.
+const HOP: usize = 64;
+/// Only this many seconds from the start of the file are analyzed.
+const MAX_ANALYZED_SECS: u32 = 300;
+/// Number of frames the onset envelope is smoothed over.
+const SMOOTHING: usize = 5;
+/// Range of tempos considered, in beats per minute.
+const BPM_RANGE: (f64, f64) = (60.0, 200.0);
+
+/// Detects the tempo of the audio file at `path`.
+///
+/// # Returns
+///
+/// * `Result<Option<f32>>` - The tempo in beats per minute, `None` if the file has no
+///   discernible beat, or an error if ffmpeg could not be run or failed.
+pub(crate) fn detect_bpm(runner: &dyn CommandRunner, path: &Path) -> Result<Option<f32>> {
+    let output = runner
+        .output(
+            exec::command("ffmpeg")
+                .arg("-i")
+                .arg(path)
+                .args([
+                    "-t",
+                    &MAX_ANALYZED_SECS.to_string(),
+                    "-vn",
+                    "-ac",
+                    "1",
+                    "-ar",
+                    &SAMPLE_RATE.to_string(),
+                    "-f",
+                    "f32le",
+                    "-",
+                    "-hide_banner",
+                    "-loglevel",
+                    "error",
+                    "-nostats",
+                ])
+                .stdin(Stdio::null()),
+        )
+        .map_err(Error::from_spawn)?;
+    if !output.status.success() {
+        return Err(Error::FfmpegFailed {
+            path: path.to_path_buf(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    // Energy of each frame; the decoded samples are at most a few megabytes
+    let energies: Vec<f64> = output
+        .stdout
+        .chunks_exact(HOP * 4)
+        .map(|frame| {
+            frame
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .map(|sample| sample * sample)
+                .sum()
+        })
+        .collect();
+    Ok(bpm_from_energies(&energies))
+}
+
+/// Estimates the tempo from per-frame energies.
+fn bpm_from_energies(energies: &[f64]) -> Option<f32> {
+    let frame_rate = SAMPLE_RATE as f64 / HOP as f64;
+    let rises: Vec<f64> = energies
+        .windows(2)
+        .map(|w| ((w[1] + 1e-9).ln() - (w[0] + 1e-9).ln()).max(0.0))
+        .collect();
+    // Smooth the onsets, so beats still line up at periods that fall between
+    // frames
+    let mut onsets: Vec<f64> = rises
+        .windows(SMOOTHING)
+        .map(|w| w.iter().sum::<f64>())
+        .collect();
+    let mean = onsets.iter().sum::<f64>() / onsets.len().max(1) as f64;
+    onsets.iter_mut().for_each(|o| *o -= mean);
+
+    let min_lag = (60.0 * frame_rate / BPM_RANGE.1).floor() as usize;
+    let max_lag = (60.0 * frame_rate / BPM_RANGE.0).ceil() as usize;
+    if onsets.len() < max_lag * 4 {
+        return None;
+    }
+    let correlation =
+        |lag: usize| -> f64 { onsets.iter().zip(&onsets[lag..]).map(|(a, b)| a * b).sum() };
+    let scores: Vec<f64> = (min_lag - 1..=max_lag + 1).map(correlation).collect();
+    let max = scores[1..scores.len() - 1]
+        .iter()
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+    if max <= 0.0 {
+        return None;
+    }
+    // A beat also correlates at multiples of its period, so prefer the
+    // shortest period that scores nearly as well as the best one.
+    let best = (1..scores.len() - 1).find(|&i| {
+        scores[i] >= 0.8 * max && scores[i] >= scores[i - 1] && scores[i] >= scores[i + 1]
+    })? - 1;
+    let peak = scores[best + 1];
+    // Refine the lag between frames with a parabola through the neighbours
+    let (before, after) = (scores[best], scores[best + 2]);
+    let denominator = before - 2.0 * peak + after;
+    let offset = if denominator != 0.0 {
+        (0.5 * (before - after) / denominator).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    let lag = (min_lag + best) as f64 + offset;
+    Some((60.0 * frame_rate / lag) as f32)
+}
@@ -0,0 +1,98 @@
+//! Rescaling of chapter marks, which ffmpeg copies with their original times.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Error, ProcessOptions, Result, exec};
+
+/// Distinguishes the chapter files of files processed concurrently.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads the chapters of `path` and writes them, with their times mapped by
+/// `map_time`, into an FFMETADATA file in [`ProcessOptions::temp_dir`] (or the
+/// system temp folder). Pass it to ffmpeg as an extra input with
+/// `-map_chapters`, and remove it afterwards.
+///
+/// # Returns
+///
+/// * `Result<Option<PathBuf>>` - The FFMETADATA file, or `None` if `path` has no chapters.
+pub(crate) fn rescaled_chapters(
+    path: &Path,
+    options: &ProcessOptions,
+    map_time: &dyn Fn(f64) -> f64,
+) -> Result<Option<PathBuf>> {
+    let output = options
+        .runner
+        .output(
+            exec::command("ffmpeg")
+                .arg("-i")
+                .arg(path)
+                .args(["-f", "ffmetadata", "-loglevel", "error", "-"])
+                .stdin(Stdio::null()),
+        )
+        .map_err(Error::from_spawn)?;
+    if !output.status.success() {
+        return Err(Error::FfmpegFailed {
+            path: path.to_path_buf(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    let Some(metadata) = rescale(&String::from_utf8_lossy(&output.stdout), map_time) else {
+        return Ok(None);
+    };
+
+    let dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let chapters_path = dir.join(format!(
+        "abs-chapters-{}-{}.txt",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&chapters_path, metadata)?;
+    Ok(Some(chapters_path))
+}
+
+/// Keeps only the `[CHAPTER]` sections of an FFMETADATA dump, with their
+/// `START` and `END` mapped by `map_time`. Returns `None` if there are no
+/// chapters.
+fn rescale(metadata: &str, map_time: &dyn Fn(f64) -> f64) -> Option<String> {
+    let mut rescaled = String::from(";FFMETADATA1\n");
+    let mut in_chapter = false;
+    let mut has_chapters = false;
+    // Chapter times are counted in TIMEBASE units, 1/1000 s by default
+    let mut timebase = (1.0, 1000.0);
+    for line in metadata.lines() {
+        if line.starts_with('[') {
+            in_chapter = line == "[CHAPTER]";
+            has_chapters |= in_chapter;
+            timebase = (1.0, 1000.0);
+        }
+        if !in_chapter {
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("TIMEBASE=")
+            && let Some((num, den)) = value.split_once('/')
+            && let (Ok(num), Ok(den)) = (num.parse::<f64>(), den.parse::<f64>())
+            && num > 0.0
+            && den > 0.0
+        {
+            timebase = (num, den);
+        }
+        let time = line
+            .split_once('=')
+            .filter(|(key, _)| matches!(*key, "START" | "END"))
+            .and_then(|(key, value)| Some((key, value.parse::<f64>().ok()?)));
+        match time {
+            Some((key, value)) => {
+                let (num, den) = timebase;
+                let mapped = map_time(value * num / den) * den / num;
+                rescaled.push_str(&format!("{}={}\n", key, mapped.round() as i64));
+            }
+            None => {
+                rescaled.push_str(line);
+                rescaled.push('\n');
+            }
+        }
+    }
+    has_chapters.then_some(rescaled)
+}
@@ -0,0 +1,84 @@
+//! Removal of temporary files left behind by interrupted runs.
+
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::Result;
+
+/// Prefix of the temporary files written next to the files being processed.
+pub(crate) const TEMP_PREFIX: &str = "temp_";
+
+/// Returns whether `path` is a temporary file left by a previous run, i.e. it
+/// is named `temp_<pid>_<id>_<name>` as the temporary outputs are, or it is a
+/// part written with [`ProcessOptions::segment`](crate::ProcessOptions::segment).
+pub(crate) fn is_orphaned_temp_file(path: &Path) -> bool {
+    is_temp_segment(path)
+        || path
+            .file_name()
+            .and_then(|name| name.as_encoded_bytes().strip_prefix(TEMP_PREFIX.as_bytes()))
+            .and_then(|rest| strip_number(rest))
+            .and_then(|rest| strip_number(rest))
+            .is_some_and(|original| !original.is_empty())
+}
+
+/// Strips a decimal number followed by `_` from the start of `bytes`.
+fn strip_number(bytes: &[u8]) -> Option<&[u8]> {
+    let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    bytes[digits..].strip_prefix(b"_")
+}
+
+/// Returns whether `path` is named like a temporary segment part,
+/// `temp_segment_<pid>_<id>_<part>.<ext>`.
+fn is_temp_segment(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.strip_prefix(TEMP_PREFIX))
+        .and_then(|stem| stem.strip_prefix("segment_"))
+        .is_some_and(|numbers| {
+            let numbers: Vec<_> = numbers.split('_').collect();
+            numbers.len() == 3
+                && numbers
+                    .iter()
+                    .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+/// Finds the temporary files left behind by interrupted runs under `folder`,
+/// without removing them.
+///
+/// A file is considered a leftover if it is named `temp_<pid>_<id>_<name>`,
+/// as the temporary outputs of this tool are, or if it is a temporary part of
+/// a segmented output.
+///
+/// # Returns
+///
+/// * `Result<Vec<PathBuf>>` - The paths of the leftover files.
+pub fn find_temp_files(folder: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in WalkDir::new(folder.as_ref()) {
+        let entry = entry?;
+        if entry.file_type().is_file() && is_orphaned_temp_file(entry.path()) {
+            found.push(entry.into_path());
+        }
+    }
+    Ok(found)
+}
+
+/// Removes temporary files left behind by interrupted runs under `folder`, as
+/// found by [`find_temp_files`].
+///
+/// # Returns
+///
+/// * `Result<Vec<PathBuf>>` - The paths of the removed files.
+pub fn clean_temp_files(folder: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let found = find_temp_files(folder)?;
+    for path in &found {
+        std::fs::remove_file(path)?;
+        log::debug!("Removed {}", path.display());
+    }
+    Ok(found)
+}
@@ -0,0 +1,125 @@
+//! The [`RunControl`] handle through which a run is paused, resumed or
+//! stopped while it is in progress.
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// The state of a run as set through its [`RunControl`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Running,
+    Paused,
+    Stopped,
+}
+
+/// A handle to pause, resume or stop a run from another thread, as stored
+/// in [`ProcessOptions::control`](crate::ProcessOptions::control).
+///
+/// Pausing or stopping never interrupts ffmpeg: files in progress are
+/// finished, and only starting new files is held back. Files not started
+/// because the run was stopped are reported as skipped and not recorded in
+/// the [`journal`](crate::ProcessOptions::journal), so the run can be
+/// resumed later. Clones control the same run and compare equal.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use audio_batch_speedup::{AudioFormat, ProcessOptions, RunControl};
+///
+/// let control = RunControl::new();
+/// let options = ProcessOptions {
+///     control: control.clone(),
+///     ..ProcessOptions::new(1.5, AudioFormat::ALL)
+/// };
+/// std::thread::spawn(move || {
+///     // Reclaim the CPU for a minute
+///     control.pause();
+///     std::thread::sleep(Duration::from_secs(60));
+///     control.resume();
+/// });
+/// audio_batch_speedup::process_audio_files_with_options("path/to/audio/files", &options)
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct RunControl(Arc<(Mutex<State>, Condvar)>);
+
+impl RunControl {
+    /// Creates a handle to a run that is not paused.
+    pub fn new() -> Self {
+        Self(Arc::new((Mutex::new(State::Running), Condvar::new())))
+    }
+
+    /// Holds back starting new files until [`resume`](Self::resume) is
+    /// called. Has no effect once stopped.
+    pub fn pause(&self) {
+        self.set(State::Running, State::Paused);
+    }
+
+    /// Lets a paused run start new files again.
+    pub fn resume(&self) {
+        self.set(State::Paused, State::Running);
+    }
+
+    /// Stops starting new files for good. The files in progress are finished.
+    pub fn stop(&self) {
+        *self.state() = State::Stopped;
+        self.0.1.notify_all();
+    }
+
+    /// Returns whether the run is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.state() == State::Paused
+    }
+
+    /// Returns whether the run was stopped.
+    pub fn is_stopped(&self) -> bool {
+        *self.state() == State::Stopped
+    }
+
+    /// Blocks while the run is paused.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the next file may be started, i.e. the run was not stopped.
+    pub(crate) fn wait_while_paused(&self) -> bool {
+        let state = self
+            .0
+            .1
+            .wait_while(self.state(), |state| *state == State::Paused)
+            .unwrap_or_else(|e| e.into_inner());
+        *state == State::Running
+    }
+
+    /// Changes the state to `to` if it is `from`.
+    fn set(&self, from: State, to: State) {
+        let mut state = self.state();
+        if *state == from {
+            *state = to;
+            self.0.1.notify_all();
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for RunControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for RunControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RunControl").field(&*self.state()).finish()
+    }
+}
+
+impl PartialEq for RunControl {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
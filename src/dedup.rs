@@ -0,0 +1,155 @@
+//! Grouping of hard links and byte-identical files, so each unique content is
+//! processed once.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hasher};
+use std::io::Read;
+use std::path::Path;
+
+use log::debug;
+
+use crate::PlannedFile;
+use crate::par::*;
+
+/// A unique file content: the file that is processed, and the files with the
+/// same content that receive its output.
+pub(crate) struct Job {
+    pub(crate) file: PlannedFile,
+    pub(crate) duplicates: Vec<Duplicate>,
+}
+
+/// A file that receives the output of the [`Job::file`] it duplicates.
+pub(crate) struct Duplicate {
+    pub(crate) file: PlannedFile,
+    /// Whether the file is a hard link of the previous file of the job that is
+    /// not, so its output is linked to that file's output rather than copied.
+    pub(crate) hardlink: bool,
+}
+
+impl From<PlannedFile> for Job {
+    fn from(file: PlannedFile) -> Self {
+        Self {
+            file,
+            duplicates: Vec::new(),
+        }
+    }
+}
+
+/// Groups `files` that are hard links of the same file, so it is processed
+/// once and the links are kept. Only supported on Unix; elsewhere each file
+/// gets its own job.
+pub(crate) fn group_hardlinks(files: Vec<PlannedFile>) -> Vec<Job> {
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut by_inode: HashMap<(u64, u64), usize> = HashMap::new();
+    for file in files {
+        let Some(id) = file_id(&file.path) else {
+            jobs.push(Job::from(file));
+            continue;
+        };
+        match by_inode.get(&id) {
+            Some(&index) => {
+                debug!(
+                    "{} is a hard link of {}",
+                    file.path.display(),
+                    jobs[index].file.path.display()
+                );
+                jobs[index].duplicates.push(Duplicate {
+                    file,
+                    hardlink: true,
+                });
+            }
+            None => {
+                by_inode.insert(id, jobs.len());
+                jobs.push(Job::from(file));
+            }
+        }
+    }
+    jobs
+}
+
+/// Returns the device and inode numbers of the file at `path`, if it has more
+/// than one link.
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Groups `jobs` by content, as returned by [`group_hardlinks`]. Only files of
+/// equal size are hashed, and files that cannot be read are kept on their own.
+pub(crate) fn group_duplicates(jobs: Vec<Job>) -> Vec<Job> {
+    let mut by_size: HashMap<u64, Vec<Job>> = HashMap::new();
+    for job in jobs {
+        by_size.entry(job.file.size).or_default().push(job);
+    }
+
+    let candidates: Vec<(Job, Option<u64>)> = by_size
+        .into_values()
+        .flat_map(|group| {
+            let single = group.len() == 1;
+            group.into_iter().map(move |job| (job, single))
+        })
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(job, single)| {
+            let hash = if single {
+                None
+            } else {
+                hash_file(&job.file.path)
+            };
+            (job, hash)
+        })
+        .collect();
+
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut by_content: HashMap<(u64, u64), usize> = HashMap::new();
+    for (job, hash) in candidates {
+        let Some(hash) = hash else {
+            jobs.push(job);
+            continue;
+        };
+        match by_content.get(&(job.file.size, hash)) {
+            Some(&index) => {
+                debug!(
+                    "{} is a duplicate of {}",
+                    job.file.path.display(),
+                    jobs[index].file.path.display()
+                );
+                // The hard links of the duplicate follow it, so they are
+                // linked to its copy
+                let duplicates = &mut jobs[index].duplicates;
+                duplicates.push(Duplicate {
+                    file: job.file,
+                    hardlink: false,
+                });
+                duplicates.extend(job.duplicates);
+            }
+            None => {
+                by_content.insert((job.file.size, hash), jobs.len());
+                jobs.push(job);
+            }
+        }
+    }
+    jobs
+}
+
+/// Hashes the content of the file at `path`.
+fn hash_file(path: &Path) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer).ok()? {
+            0 => return Some(hasher.finish()),
+            n => hasher.write(&buffer[..n]),
+        }
+    }
+}
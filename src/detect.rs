@@ -0,0 +1,363 @@
+//! Audio format detection.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::Stdio;
+
+use log::debug;
+
+use crate::{AudioFormat, CommandRunner, exec};
+
+/// Detects the audio format of a file based on its magic bytes or file extension.
+/// Ogg files are told apart by codec: [`AudioFormat::OPUS`] for Opus, and
+/// [`AudioFormat::OGG`] for Vorbis and other codecs. Videos are not audio
+/// files, even if they have an audio track: files with the extension of a
+/// video container, and MP4 files with a video track other than cover art.
+///
+/// # Arguments
+///
+/// * `path` - The path to the audio file.
+///
+/// # Returns
+///
+/// * `Option<AudioFormat>` - The detected audio format, or `None` if it cannot be determined.
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{detect_audio_format, AudioFormat};
+///
+/// if detect_audio_format("song.m4a") == Some(AudioFormat::ALAC) {
+///     println!("Lossless!");
+/// }
+/// ```
+pub fn detect_audio_format(path: impl AsRef<Path>) -> Option<AudioFormat> {
+    match detect(path.as_ref()) {
+        Detected::Audio(format) => Some(format),
+        Detected::Video | Detected::Unknown => None,
+    }
+}
+
+/// What [`detect`] finds out about a file.
+enum Detected {
+    Audio(AudioFormat),
+    /// A video, which may have an audio track but is not an audio file.
+    Video,
+    Unknown,
+}
+
+/// Detects the format of the file at `path`, see [`detect_audio_format`].
+fn detect(path: &Path) -> Detected {
+    if is_video_file(path) {
+        return Detected::Video;
+    }
+    // Try to detect by magic bytes first
+    let Ok(mut file) = File::open(path) else {
+        return Detected::Unknown;
+    };
+    let mut buffer = [0; 12]; // Read enough bytes for common headers
+    if file.read_exact(&mut buffer).is_err() {
+        return Detected::Unknown;
+    }
+
+    // Ogg (OggS), holding Opus or (most often) Vorbis
+    if &buffer[0..4] == b"OggS" {
+        return Detected::Audio(ogg_audio_format(&mut file).unwrap_or(AudioFormat::OGG));
+    }
+    // MP3 (ID3 tag or starts with 0xFF FB/FA)
+    if &buffer[0..3] == b"ID3" || (buffer[0] == 0xFF && (buffer[1] & 0xF6) == 0xF2) {
+        return Detected::Audio(AudioFormat::MP3);
+    }
+    // WAV (RIFF header with WAVE)
+    if &buffer[0..4] == b"RIFF" && &buffer[8..12] == b"WAVE" {
+        return Detected::Audio(AudioFormat::WAV);
+    }
+    // FLAC (fLaC)
+    if &buffer[0..4] == b"fLaC" {
+        return Detected::Audio(AudioFormat::FLAC);
+    }
+    // AAC and ALAC (in MP4/M4A containers, which start with an 'ftyp' box).
+    // The codec is found in the sample description of the audio track.
+    if &buffer[4..8] == b"ftyp" {
+        match mp4_content(&mut file) {
+            Detected::Unknown => {}
+            detected => return detected,
+        }
+    }
+    // WMA (ASF header)
+    if buffer[0..4] == [0x30, 0x26, 0xB2, 0x75] {
+        // GUID for ASF header
+        return Detected::Audio(AudioFormat::WMA);
+    }
+
+    // Fallback to file extension
+    format_from_extension(path).map_or(Detected::Unknown, Detected::Audio)
+}
+
+/// Returns whether `path` has the extension of a video container.
+pub(crate) fn is_video_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|extension| {
+            matches!(
+                extension.to_lowercase().as_str(),
+                "mp4" | "m4v" | "mkv" | "webm" | "mov"
+            )
+        })
+}
+
+/// Returns the audio format usually stored in files with the extension of
+/// `path`.
+fn format_from_extension(path: &Path) -> Option<AudioFormat> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "ogg" => Some(AudioFormat::OGG),
+        "mp3" => Some(AudioFormat::MP3),
+        "wav" => Some(AudioFormat::WAV),
+        "flac" => Some(AudioFormat::FLAC),
+        "m4a" | "m4b" | "aac" => Some(AudioFormat::AAC),
+        "opus" => Some(AudioFormat::OPUS),
+        "alac" => Some(AudioFormat::ALAC),
+        "wma" => Some(AudioFormat::WMA),
+        _ => None,
+    }
+}
+
+/// Detects the audio format of a file like [`detect_audio_format`], but asks
+/// ffprobe when neither the magic bytes nor the extension are recognized, or
+/// when they disagree. Videos are never probed. See
+/// [`ProcessOptions::probe_formats`](crate::ProcessOptions::probe_formats).
+pub(crate) fn detect_audio_format_probing(
+    runner: &dyn CommandRunner,
+    path: &Path,
+) -> Option<AudioFormat> {
+    let detected = match detect(path) {
+        Detected::Audio(format) => Some(format),
+        Detected::Video => return None,
+        Detected::Unknown => None,
+    };
+    let by_extension = format_from_extension(path);
+    if detected.is_some() && by_extension.is_none_or(|format| Some(format) == detected) {
+        return detected;
+    }
+    probe_audio_format(runner, path).or(detected)
+}
+
+/// Asks ffprobe for the codec of the first audio stream of `path`.
+fn probe_audio_format(runner: &dyn CommandRunner, path: &Path) -> Option<AudioFormat> {
+    let output = runner
+        .output(
+            exec::command("ffprobe")
+                .args([
+                    "-v",
+                    "error",
+                    "-select_streams",
+                    "a:0",
+                    "-show_entries",
+                    "stream=codec_name",
+                    "-of",
+                    "default=noprint_wrappers=1:nokey=1",
+                ])
+                .arg(path)
+                .stdin(Stdio::null()),
+        )
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let codec = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    debug!("ffprobe reports codec {:?} for {}", codec, path.display());
+    match codec.as_str() {
+        "vorbis" => Some(AudioFormat::OGG),
+        "mp3" => Some(AudioFormat::MP3),
+        "flac" => Some(AudioFormat::FLAC),
+        "aac" => Some(AudioFormat::AAC),
+        "opus" => Some(AudioFormat::OPUS),
+        "alac" => Some(AudioFormat::ALAC),
+        "wmav1" | "wmav2" | "wmapro" | "wmalossless" => Some(AudioFormat::WMA),
+        codec if codec.starts_with("pcm_") => Some(AudioFormat::WAV),
+        _ => None,
+    }
+}
+
+/// Length of an Ogg page header, up to its segment count.
+const OGG_PAGE_HEADER_LEN: u64 = 27;
+
+/// Finds the codec of an Ogg file from the identification header in the
+/// first packet of its first page.
+///
+/// # Returns
+///
+/// * `Option<AudioFormat>` - [`AudioFormat::OPUS`] or [`AudioFormat::OGG`] (Vorbis), or `None`
+///   for other codecs or truncated files.
+fn ogg_audio_format(file: &mut File) -> Option<AudioFormat> {
+    // The segment count is the last byte of the page header, and the segment
+    // table is followed by the packet data
+    file.seek(SeekFrom::Start(OGG_PAGE_HEADER_LEN - 1)).ok()?;
+    let mut segment_count = [0; 1];
+    file.read_exact(&mut segment_count).ok()?;
+    file.seek(SeekFrom::Current(segment_count[0] as i64)).ok()?;
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic).ok()?;
+    match &magic {
+        b"OpusHead" => Some(AudioFormat::OPUS),
+        [1, b'v', b'o', b'r', b'b', b'i', b's', _] => Some(AudioFormat::OGG),
+        _ => None,
+    }
+}
+
+/// Path of boxes from a `mdia` box down to its sample description box.
+const MDIA_TO_STSD: [&[u8; 4]; 3] = [b"minf", b"stbl", b"stsd"];
+
+/// Finds the codec of the first audio track of an MP4 file by parsing the
+/// `moov/trak/mdia/minf/stbl/stsd` boxes, unless the file has a video track
+/// other than cover art.
+///
+/// # Returns
+///
+/// * `Detected` - [`Detected::Audio`] with [`AudioFormat::AAC`] or [`AudioFormat::ALAC`],
+///   [`Detected::Video`], or [`Detected::Unknown`] for other codecs or broken files.
+fn mp4_content(file: &mut File) -> Detected {
+    let Some((mut pos, moov_end)) = file
+        .metadata()
+        .ok()
+        .and_then(|metadata| find_box(file, 0, metadata.len(), b"moov"))
+    else {
+        return Detected::Unknown;
+    };
+    let mut audio = None;
+    while let Some((trak_start, trak_end)) = find_box(file, pos, moov_end, b"trak") {
+        if let Some((handler, codec)) = trak_codec(file, trak_start, trak_end) {
+            match (&handler, &codec) {
+                // Cover art may be stored as a video track of still images
+                (b"vide", b"jpeg" | b"png ") => {}
+                (b"vide", _) => return Detected::Video,
+                (b"soun", b"mp4a") => _ = audio.get_or_insert(AudioFormat::AAC),
+                (b"soun", b"alac") => _ = audio.get_or_insert(AudioFormat::ALAC),
+                _ => {}
+            }
+        }
+        pos = trak_end;
+    }
+    audio.map_or(Detected::Unknown, Detected::Audio)
+}
+
+/// Reads the kind and codec of the track whose `trak` box content spans
+/// `start..end`.
+///
+/// # Returns
+///
+/// * `Option<([u8; 4], [u8; 4])>` - The handler type of the track (e.g., `soun` or `vide`) and
+///   the name of its first sample entry (e.g., `mp4a`).
+fn trak_codec(file: &mut File, start: u64, end: u64) -> Option<([u8; 4], [u8; 4])> {
+    let (mut start, mut end) = find_box(file, start, end, b"mdia")?;
+    // The `hdlr` box has a version/flags word and a predefined word, followed
+    // by the handler type.
+    let (hdlr_start, _) = find_box(file, start, end, b"hdlr")?;
+    let mut handler = [0; 4];
+    file.seek(SeekFrom::Start(hdlr_start + 8)).ok()?;
+    file.read_exact(&mut handler).ok()?;
+    for name in MDIA_TO_STSD {
+        (start, end) = find_box(file, start, end, name)?;
+    }
+    // The `stsd` box has a version/flags word and an entry count, followed by
+    // the sample entries, each a box named after its codec.
+    let (_, _, codec) = read_box_header(file, start + 8, end)?;
+    Some((handler, codec))
+}
+
+/// Finds the first box named `name` among the boxes in `start..end`.
+///
+/// # Returns
+///
+/// * `Option<(u64, u64)>` - The range of the box's content.
+pub(crate) fn find_box(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    name: &[u8; 4],
+) -> Option<(u64, u64)> {
+    let mut pos = start;
+    while pos < end {
+        let (content_start, box_end, box_name) = read_box_header(file, pos, end)?;
+        if &box_name == name {
+            return Some((content_start, box_end));
+        }
+        pos = box_end;
+    }
+    None
+}
+
+/// Reads the header of the box at `pos`, within a parent ending at `end`.
+///
+/// # Returns
+///
+/// * `Option<(u64, u64, [u8; 4])>` - The start of the box's content, the end of the box, and its
+///   name.
+pub(crate) fn read_box_header(file: &mut File, pos: u64, end: u64) -> Option<(u64, u64, [u8; 4])> {
+    let mut header = [0; 8];
+    file.seek(SeekFrom::Start(pos)).ok()?;
+    file.read_exact(&mut header).ok()?;
+    let name = header[4..8].try_into().ok()?;
+    let (size, header_len) = match u32::from_be_bytes(header[0..4].try_into().ok()?) {
+        // The box extends to the end of its parent
+        0 => (end - pos, 8),
+        // A 64-bit size follows the name
+        1 => {
+            let mut large = [0; 8];
+            file.read_exact(&mut large).ok()?;
+            (u64::from_be_bytes(large), 16)
+        }
+        size => (size as u64, 8),
+    };
+    if size < header_len || pos.checked_add(size)? > end {
+        return None;
+    }
+    Some((pos + header_len, pos + size, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect_audio_format;
+    use crate::AudioFormat;
+
+    fn mp4_box(name: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let size = (8 + content.len()) as u32;
+        [&size.to_be_bytes()[..], name, content].concat()
+    }
+
+    fn trak(handler: &[u8; 4], codec: &[u8; 4]) -> Vec<u8> {
+        let hdlr = mp4_box(b"hdlr", &[&[0; 8][..], handler, &[0; 12]].concat());
+        let stsd = mp4_box(b"stsd", &[&[0; 8][..], &mp4_box(codec, &[0; 8])].concat());
+        let minf = mp4_box(b"minf", &mp4_box(b"stbl", &stsd));
+        mp4_box(b"trak", &mp4_box(b"mdia", &[hdlr, minf].concat()))
+    }
+
+    fn detect_mp4(name: &str, traks: &[Vec<u8>]) -> Option<AudioFormat> {
+        let path =
+            std::env::temp_dir().join(format!("absu-detect-{}-{}", std::process::id(), name));
+        let content = [
+            mp4_box(b"ftyp", b"isom\0\0\0\0"),
+            mp4_box(b"moov", &traks.concat()),
+        ];
+        std::fs::write(&path, content.concat()).unwrap();
+        let format = detect_audio_format(&path);
+        std::fs::remove_file(&path).unwrap();
+        format
+    }
+
+    #[test]
+    fn videos_are_not_audio_files() {
+        let video = [trak(b"vide", b"avc1"), trak(b"soun", b"mp4a")];
+        assert_eq!(detect_mp4("video.m4a", &video), None);
+        assert_eq!(detect_mp4("audio.mp4", &video[1..]), None);
+
+        let with_cover = [trak(b"vide", b"jpeg"), trak(b"soun", b"alac")];
+        assert_eq!(
+            detect_mp4("cover.m4a", &with_cover),
+            Some(AudioFormat::ALAC)
+        );
+        assert_eq!(detect_mp4("audio.m4a", &video[1..]), Some(AudioFormat::AAC));
+    }
+}
@@ -0,0 +1,153 @@
+//! Fetching a static ffmpeg build for users who cannot install it themselves.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use walkdir::WalkDir;
+
+use crate::{Error, Result, exec, move_file};
+
+/// Archives containing static builds of `ffmpeg` and `ffprobe` for the
+/// current platform.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const ARCHIVES: &[&str] = &[
+    "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/ffmpeg-master-latest-linux64-gpl.tar.xz",
+];
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const ARCHIVES: &[&str] = &[
+    "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/ffmpeg-master-latest-linuxarm64-gpl.tar.xz",
+];
+#[cfg(all(target_os = "windows", target_arch = "x86_64"))]
+const ARCHIVES: &[&str] = &[
+    "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/ffmpeg-master-latest-win64-gpl.zip",
+];
+#[cfg(all(target_os = "windows", target_arch = "aarch64"))]
+const ARCHIVES: &[&str] = &[
+    "https://github.com/BtbN/FFmpeg-Builds/releases/download/latest/ffmpeg-master-latest-winarm64-gpl.zip",
+];
+#[cfg(target_os = "macos")]
+const ARCHIVES: &[&str] = &[
+    "https://evermeet.cx/ffmpeg/getrelease/zip",
+    "https://evermeet.cx/ffmpeg/getrelease/ffprobe/zip",
+];
+#[cfg(not(any(
+    all(
+        any(target_os = "linux", target_os = "windows"),
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ),
+    target_os = "macos"
+)))]
+const ARCHIVES: &[&str] = &[];
+
+/// The programs a build must provide.
+const PROGRAMS: [&str; 2] = ["ffmpeg", "ffprobe"];
+
+/// Returns the folder [`download_ffmpeg`] installs ffmpeg into, inside the
+/// user's cache folder. ffmpeg and ffprobe found there are used instead of
+/// the ones in the `PATH`.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The folder, or `None` if the cache folder is unknown.
+pub fn ffmpeg_dir() -> Option<PathBuf> {
+    let cache = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| Path::new(&home).join("Library/Caches"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    Some(cache?.join("audio-batch-speedup").join("ffmpeg"))
+}
+
+/// Returns the path of `program` in [`ffmpeg_dir`], if it was downloaded.
+pub(crate) fn downloaded_program(program: &str) -> Option<PathBuf> {
+    let path = ffmpeg_dir()?
+        .join(program)
+        .with_extension(std::env::consts::EXE_EXTENSION);
+    path.is_file().then_some(path)
+}
+
+/// Downloads a static build of ffmpeg and ffprobe for the current platform
+/// into [`ffmpeg_dir`], from where they are used by all later runs. `curl` and
+/// `tar` must be available, as they are on current Windows, macOS and Linux
+/// systems.
+///
+/// # Returns
+///
+/// * `Result<PathBuf>` - The folder ffmpeg was installed into.
+pub fn download_ffmpeg() -> Result<PathBuf> {
+    if ARCHIVES.is_empty() {
+        return Err(Error::DownloadFailed(format!(
+            "no static build is known for {}-{}",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        )));
+    }
+    let dir = ffmpeg_dir()
+        .ok_or_else(|| Error::DownloadFailed("the cache folder is unknown".to_string()))?;
+    let staging = dir.join(format!(".download-{}", std::process::id()));
+    std::fs::create_dir_all(&staging)?;
+    let result = download_into(&staging, &dir);
+    _ = std::fs::remove_dir_all(&staging);
+    result.map(|_| dir)
+}
+
+/// Downloads and extracts the archives into `staging`, then moves the
+/// programs into `dir`.
+fn download_into(staging: &Path, dir: &Path) -> Result<()> {
+    for (i, url) in ARCHIVES.iter().enumerate() {
+        let archive = staging.join(format!("archive-{}", i));
+        log::info!("Downloading {}", url);
+        run(
+            exec::command("curl")
+                .args(["-fsSL", "--retry", "2", "-o"])
+                .arg(&archive)
+                .arg(url),
+            "curl",
+        )?;
+        run(
+            exec::command("tar")
+                .arg("-xf")
+                .arg(&archive)
+                .arg("-C")
+                .arg(staging),
+            "tar",
+        )?;
+    }
+
+    for program in PROGRAMS {
+        let name = Path::new(program).with_extension(std::env::consts::EXE_EXTENSION);
+        let found = WalkDir::new(staging)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_type().is_file() && e.file_name() == name.as_os_str())
+            .ok_or_else(|| {
+                Error::DownloadFailed(format!("the archive does not contain {}", program))
+            })?;
+        move_file(found.path(), &dir.join(&name), None)?;
+    }
+    Ok(())
+}
+
+/// Runs `command`, a `tool` used for downloading, and fails with its error
+/// output if it does not succeed.
+fn run(command: &mut std::process::Command, tool: &str) -> Result<()> {
+    let output = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| Error::DownloadFailed(format!("cannot run {}: {}", tool, e)))?;
+    if !output.status.success() {
+        return Err(Error::DownloadFailed(format!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+//! The choice of the encoder used for each output format.
+
+use log::debug;
+
+use crate::{AudioFormat, CommandRunner, Error, Result, ffmpeg};
+
+/// Encoders preferred over ffmpeg's own choice when no encoder is set for a
+/// format, best first. The first one the local ffmpeg build has is used.
+const PREFERRED: &[(AudioFormat, &[&str])] = &[
+    (AudioFormat::AAC, &["libfdk_aac", "aac_at", "aac"]),
+    // ffmpeg would encode .m4a files to AAC
+    (AudioFormat::ALAC, &["alac"]),
+    (AudioFormat::MP3, &["libmp3lame"]),
+    (AudioFormat::OGG, &["libvorbis"]),
+    (AudioFormat::OPUS, &["libopus"]),
+];
+
+/// The ffmpeg encoder to use for each output format, set with
+/// [`ProcessOptions::encoders`](crate::ProcessOptions::encoders).
+///
+/// Formats without an encoder get the best one the local ffmpeg build has
+/// (e.g., `libfdk_aac` or `aac_at` for AAC, when available), or ffmpeg's
+/// default. Setting an encoder the build does not have fails the run before
+/// any file is processed. Video files keep ffmpeg's default encoders.
+///
+/// # Example
+///
+/// ```
+/// use audio_batch_speedup::{AudioFormat, EncoderMap, ProcessOptions};
+///
+/// let mut encoders = EncoderMap::new();
+/// encoders.set(AudioFormat::MP3, "libmp3lame");
+/// encoders.set(AudioFormat::AAC, "aac_at");
+/// assert_eq!(encoders.get(AudioFormat::AAC), Some("aac_at"));
+/// assert_eq!(encoders.get(AudioFormat::OPUS), None);
+/// let options = ProcessOptions {
+///     encoders,
+///     ..ProcessOptions::new(1.5, AudioFormat::ALL)
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EncoderMap {
+    encoders: Vec<(AudioFormat, String)>,
+}
+
+impl EncoderMap {
+    /// Creates a map without encoders, leaving the choice to the defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the encoder of `format`, replacing the one set before.
+    pub fn set(&mut self, format: AudioFormat, encoder: impl Into<String>) {
+        let encoder = encoder.into();
+        match self.encoders.iter_mut().find(|(f, _)| *f == format) {
+            Some((_, existing)) => *existing = encoder,
+            None => self.encoders.push((format, encoder)),
+        }
+    }
+
+    /// Returns the encoder set for `format`, if any.
+    pub fn get(&self, format: AudioFormat) -> Option<&str> {
+        self.encoders
+            .iter()
+            .find(|(f, _)| *f == format)
+            .map(|(_, encoder)| encoder.as_str())
+    }
+
+    /// Returns whether no encoder is set.
+    pub fn is_empty(&self) -> bool {
+        self.encoders.is_empty()
+    }
+
+    /// Checks the encoders that are set against those of the local ffmpeg
+    /// build, and completes the map with the preferred encoders it has.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<EncoderMap>` - The completed map, or [`Error::InvalidOptions`] if an encoder that
+    ///   is set is missing. If the encoders cannot be listed, the map is returned as it is.
+    pub(crate) fn resolve(&self, runner: &dyn CommandRunner) -> Result<EncoderMap> {
+        let Some(available) = ffmpeg::encoders(runner) else {
+            return Ok(self.clone());
+        };
+        for (format, encoder) in &self.encoders {
+            if !available.iter().any(|a| a == encoder) {
+                return Err(Error::InvalidOptions(format!(
+                    "ffmpeg has no encoder named {} (for {})",
+                    encoder,
+                    format.name()
+                )));
+            }
+        }
+        let mut resolved = self.clone();
+        for (format, preferred) in PREFERRED {
+            if resolved.get(*format).is_some() {
+                continue;
+            }
+            if let Some(encoder) = preferred
+                .iter()
+                .find(|encoder| available.iter().any(|a| a == *encoder))
+            {
+                debug!("Encoding {} with {}", format.name(), encoder);
+                resolved.set(*format, *encoder);
+            }
+        }
+        Ok(resolved)
+    }
+}
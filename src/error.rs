@@ -0,0 +1,80 @@
+//! The error type of the library.
+
+use std::path::PathBuf;
+
+/// Errors returned by the library.
+///
+/// Failures of individual files during a batch run do not abort the run;
+/// they are recorded in the [`ProcessReport`](crate::ProcessReport) instead.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// `ffmpeg` could not be found in the `PATH`. Batch runs check for it
+    /// before processing any file.
+    #[error("ffmpeg was not found in the PATH")]
+    FfmpegNotFound,
+    /// ffmpeg exited with an error while working on `path`.
+    #[error("ffmpeg failed for {}: {}", .path.display(), .stderr.trim_end())]
+    FfmpegFailed {
+        /// The file ffmpeg was working on.
+        path: PathBuf,
+        /// What ffmpeg wrote to its standard error.
+        stderr: String,
+    },
+    /// ffmpeg ran on `path`, but a measurement (e.g. its loudness or
+    /// ReplayGain) could not be read from its output.
+    #[error("cannot detect the {what} of {}", .path.display())]
+    Detection {
+        /// The measured file.
+        path: PathBuf,
+        /// What was measured, e.g. `loudness`.
+        what: &'static str,
+    },
+    /// The checksum manifest at `path`, read by
+    /// [`verify_manifest`](crate::verify_manifest), is malformed.
+    #[error("cannot verify against {}: {message}", .path.display())]
+    Verification {
+        /// The manifest.
+        path: PathBuf,
+        /// What is wrong with it.
+        message: String,
+    },
+    /// The options are invalid, e.g. a malformed
+    /// [`name_template`](crate::ProcessOptions::name_template).
+    #[error("invalid options: {0}")]
+    InvalidOptions(String),
+    /// A [`Speed`](crate::Speed) could not be parsed or is not positive.
+    #[error("invalid speed: {0}")]
+    InvalidSpeed(String),
+    /// The path given to [`process_single_file`](crate::process_single_file)
+    /// is not a file.
+    #[error("{} is not a file", .0.display())]
+    NotAFile(PathBuf),
+    /// Downloading a static ffmpeg build with
+    /// [`download_ffmpeg`](crate::download_ffmpeg) failed.
+    #[error("downloading ffmpeg failed: {0}")]
+    DownloadFailed(String),
+    /// An I/O error, e.g. the folder to process cannot be read.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A `Result` with [`Error`] as its error type.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts an error from spawning ffmpeg, telling a missing ffmpeg apart
+    /// from other I/O errors.
+    pub(crate) fn from_spawn(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Self::FfmpegNotFound,
+            _ => Self::Io(e),
+        }
+    }
+}
+
+impl From<walkdir::Error> for Error {
+    fn from(e: walkdir::Error) -> Self {
+        Self::Io(e.into())
+    }
+}
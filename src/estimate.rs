@@ -0,0 +1,115 @@
+//! Projection of the cost of a batch run from a small sample.
+
+use std::time::{Duration, Instant};
+
+use crate::{FileStatus, PlannedFile, ProcessOptions, Result, par, plan, process_planned_files};
+
+/// The projected cost of processing a plan, measured on a sample of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimate {
+    /// Number of sample files that were processed successfully.
+    pub sample_files: usize,
+    /// Seconds of input audio processed per wall-clock second by a single
+    /// worker.
+    pub throughput: f64,
+    /// Number of workers the projection assumes.
+    pub workers: usize,
+    /// Projected wall-clock time for the whole plan.
+    pub wall_time: Duration,
+    /// Projected total size of the processed files, in bytes.
+    pub output_size: u64,
+}
+
+/// Processes a random sample of the files that `files` will process, one at a
+/// time and into a temporary folder so that no file is modified, and projects
+/// the wall time and output size of the whole plan from it.
+///
+/// The audio duration and output size of the whole plan are extrapolated from
+/// the sample in proportion to the input sizes.
+///
+/// # Arguments
+///
+/// * `files` - The planned files, as returned by [`plan`](crate::plan).
+/// * `options` - The options the plan will be processed with.
+/// * `sample_size` - The maximum number of files to process for the estimate.
+///
+/// # Returns
+///
+/// * `Result<Option<Estimate>>` - The estimate, `None` if no sample file could be processed, or
+///   an error if the temporary folder cannot be created
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{estimate, plan, AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// let files = plan("path/to/audio/files", &options).unwrap();
+/// if let Some(estimate) = estimate(&files, &options, 5).unwrap() {
+///     println!("About {:?} with {} workers", estimate.wall_time, estimate.workers);
+/// }
+/// ```
+pub fn estimate(
+    files: &[PlannedFile],
+    options: &ProcessOptions,
+    sample_size: usize,
+) -> Result<Option<Estimate>> {
+    let mut candidates: Vec<_> = files.iter().filter(|f| f.will_process()).collect();
+    let total_size: u64 = candidates.iter().map(|f| f.size).sum();
+    plan::shuffle(&mut candidates, |f| &f.path);
+    candidates.truncate(sample_size);
+
+    let temp_dir = std::env::temp_dir().join(format!("abs-estimate-{}", std::process::id()));
+    std::fs::create_dir_all(&temp_dir)?;
+    let sample_options = ProcessOptions {
+        output_dir: Some(temp_dir.clone()),
+        backup_dir: None,
+        log_file: None,
+        progress_bar: false,
+        preserve_times: false,
+        preserve_attrs: false,
+        rescale_sidecars: false,
+        dedup: false,
+        ..options.clone()
+    };
+
+    let mut sample_files = 0;
+    let mut wall_time = Duration::ZERO;
+    let (mut audio, mut input_size, mut output_size) = (Duration::ZERO, 0, 0);
+    for file in candidates {
+        let start = Instant::now();
+        let report = process_planned_files(vec![file.clone()], &sample_options)?;
+        let elapsed = start.elapsed();
+        for file_report in report.files {
+            if file_report.status == FileStatus::Processed
+                && let Some(duration) = file_report.input_duration
+                && let Some(size) = file_report.output_size
+            {
+                sample_files += 1;
+                wall_time += elapsed;
+                audio += duration;
+                input_size += file_report.input_size;
+                output_size += size;
+            }
+        }
+    }
+    _ = std::fs::remove_dir_all(&temp_dir);
+
+    if sample_files == 0 || input_size == 0 || wall_time.is_zero() {
+        return Ok(None);
+    }
+    let throughput = audio.as_secs_f64() / wall_time.as_secs_f64();
+    let workers = options
+        .max_ffmpeg
+        .map_or_else(par::current_num_threads, |n| n.get())
+        .max(1);
+    let scale = total_size as f64 / input_size as f64;
+    let total_audio = audio.as_secs_f64() * scale;
+    Ok(Some(Estimate {
+        sample_files,
+        throughput,
+        workers,
+        wall_time: Duration::from_secs_f64(total_audio / throughput / workers as f64),
+        output_size: (output_size as f64 * scale) as u64,
+    }))
+}
@@ -0,0 +1,40 @@
+//! Events emitted while processing, for applications that display their own
+//! progress.
+
+use std::path::PathBuf;
+
+use crate::FileReport;
+
+/// An event emitted by [`process_planned_files_with_events`](crate::process_planned_files_with_events).
+///
+/// Events for different files may be interleaved, since files are processed
+/// in parallel.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProcessEvent {
+    /// Processing of a file has started.
+    FileStarted {
+        /// Path of the file.
+        path: PathBuf,
+    },
+    /// ffmpeg reported progress on a file.
+    Progress {
+        /// Path of the file.
+        path: PathBuf,
+        /// Fraction of the file processed so far, between 0 and 1.
+        fraction: f32,
+    },
+    /// A file was processed successfully, or skipped after it was started
+    /// (e.g., because it is already shorter than the target duration).
+    FileFinished {
+        /// The report for the file.
+        report: FileReport,
+    },
+    /// Processing a file failed.
+    FileFailed {
+        /// Path of the file.
+        path: PathBuf,
+        /// What ffmpeg wrote to its standard error, which usually explains the
+        /// failure. Empty if the failure did not come from ffmpeg.
+        stderr: String,
+    },
+}
@@ -0,0 +1,72 @@
+//! Spawning of external tools, hiding the differences between platforms.
+
+use std::process::Command;
+
+use crate::download;
+
+/// Windows process creation flag that runs a console program without a
+/// console window.
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// Returns a command running `program`, preferring a copy fetched with
+/// [`download_ffmpeg`](crate::download_ffmpeg) over the `PATH`. On Windows,
+/// the process is created without a console window, so GUI applications
+/// don't flash one for every spawned ffmpeg.
+pub(crate) fn command(program: &str) -> Command {
+    #[allow(unused_mut)]
+    let mut command = match download::downloaded_program(program) {
+        Some(path) => Command::new(path),
+        None => Command::new(program),
+    };
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    command
+}
+
+/// Configures `command` so the spawned process runs at the given nice level.
+#[cfg(unix)]
+pub(crate) fn set_priority(command: &mut Command, nice: i32) {
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: `setpriority` is async-signal-safe and does not allocate.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Configures `command` so the spawned process runs at the priority class
+/// closest to the given nice level, still without a console window.
+#[cfg(windows)]
+pub(crate) fn set_priority(command: &mut Command, nice: i32) {
+    use std::os::windows::process::CommandExt;
+
+    const IDLE_PRIORITY_CLASS: u32 = 0x0000_0040;
+    const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+    const NORMAL_PRIORITY_CLASS: u32 = 0x0000_0020;
+    const ABOVE_NORMAL_PRIORITY_CLASS: u32 = 0x0000_8000;
+    const HIGH_PRIORITY_CLASS: u32 = 0x0000_0080;
+
+    let class = match nice {
+        15.. => IDLE_PRIORITY_CLASS,
+        1..=14 => BELOW_NORMAL_PRIORITY_CLASS,
+        0 => NORMAL_PRIORITY_CLASS,
+        -10..=-1 => ABOVE_NORMAL_PRIORITY_CLASS,
+        _ => HIGH_PRIORITY_CLASS,
+    };
+    // `creation_flags` replaces the flags set by `command`
+    command.creation_flags(CREATE_NO_WINDOW | class);
+}
+
+/// Process priority is not supported on this platform; does nothing.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn set_priority(_command: &mut Command, _nice: i32) {}
@@ -0,0 +1,117 @@
+//! Export of the final report of a run, as JSON or CSV.
+
+use std::path::Path;
+use std::time::Duration;
+
+use audio_batch_speedup::{FileReport, Loudness, ProcessReport};
+
+use crate::{AudioFormat, json_path, json_string, stderr_excerpt};
+
+/// Encodes the final report of a run over `input` that took `elapsed` as a
+/// JSON object, with the totals and one entry per file.
+pub(crate) fn report_json(input: &Path, report: &ProcessReport, elapsed: Duration) -> String {
+    let files: Vec<_> = report.files.iter().map(file_json).collect();
+    format!(
+        "{{\"folder\":{},\"processed\":{},\"skipped\":{},\"failed\":{},\"locked\":{},\"changing\":{},\"clipping\":{},\"input_duration_secs\":{:.1},\"output_duration_secs\":{:.1},\"bytes_before\":{},\"bytes_after\":{},\"elapsed_secs\":{:.1},\"files\":[{}]}}",
+        json_path(input),
+        report.processed_count(),
+        report.skipped_count(),
+        report.failed_count(),
+        report.locked_count(),
+        report.changing_count(),
+        report.clipping_count(),
+        report.total_input_duration().as_secs_f64(),
+        report.total_output_duration().as_secs_f64(),
+        report.bytes_before(),
+        report.bytes_after(),
+        elapsed.as_secs_f64(),
+        files.join(","),
+    )
+}
+
+/// Encodes a file of the report as a JSON object.
+fn file_json(file: &FileReport) -> String {
+    format!(
+        "{{\"path\":{},\"status\":{},\"locked\":{},\"changing\":{},\"speed\":{},\"input_loudness\":{},\"output_loudness\":{},\"output_path\":{},\"stderr\":{}}}",
+        json_path(&file.path),
+        json_string(&file.status.to_string()),
+        file.locked,
+        file.changing,
+        file.speed.map_or("null".to_string(), |s| s.to_string()),
+        loudness_json(file.input_loudness),
+        loudness_json(file.output_loudness),
+        file.output_path
+            .as_deref()
+            .map_or("null".to_string(), json_path),
+        file.stderr
+            .as_deref()
+            .map_or("null".to_string(), json_string),
+    )
+}
+
+/// Encodes a loudness measurement as a JSON object, or `null`. Levels of
+/// silence (negative infinity) are encoded as `null` too.
+fn loudness_json(loudness: Option<Loudness>) -> String {
+    let level = |level: f32| {
+        if level.is_finite() {
+            format!("{:.1}", level)
+        } else {
+            "null".to_string()
+        }
+    };
+    loudness.map_or("null".to_string(), |l| {
+        format!(
+            "{{\"integrated_lufs\":{},\"true_peak_dbtp\":{}}}",
+            level(l.integrated_lufs),
+            level(l.true_peak_dbtp)
+        )
+    })
+}
+
+/// Encodes the final report as CSV, with a header and one row per file.
+/// Durations are in seconds, sizes in bytes and levels in LUFS or dBTP; the
+/// error column holds an excerpt of ffmpeg's error output, or tells that the
+/// file was in use by another program.
+pub(crate) fn report_csv(report: &ProcessReport) -> String {
+    let mut csv = String::from(
+        "path,format,input_duration_secs,output_duration_secs,input_size,output_size,size_delta,input_lufs,input_true_peak_dbtp,output_lufs,output_true_peak_dbtp,status,error\n",
+    );
+    for file in &report.files {
+        let secs =
+            |d: Option<Duration>| d.map_or(String::new(), |d| format!("{:.3}", d.as_secs_f64()));
+        let level = |level: Option<f32>| level.map_or(String::new(), |l| format!("{:.1}", l));
+        let row = [
+            csv_field(&file.path.to_string_lossy()),
+            file.format.map_or("", AudioFormat::name).to_string(),
+            secs(file.input_duration),
+            secs(file.output_duration),
+            file.input_size.to_string(),
+            file.output_size.map_or(String::new(), |s| s.to_string()),
+            file.output_size.map_or(String::new(), |s| {
+                (s as i64 - file.input_size as i64).to_string()
+            }),
+            level(file.input_loudness.map(|l| l.integrated_lufs)),
+            level(file.input_loudness.map(|l| l.true_peak_dbtp)),
+            level(file.output_loudness.map(|l| l.integrated_lufs)),
+            level(file.output_loudness.map(|l| l.true_peak_dbtp)),
+            file.status.to_string(),
+            csv_field(&match file.stderr.as_deref() {
+                Some(stderr) => stderr_excerpt(stderr),
+                None if file.locked => "in use by another program".to_string(),
+                None => String::new(),
+            }),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
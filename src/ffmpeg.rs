@@ -0,0 +1,232 @@
+//! Helpers for invoking `ffmpeg` and `ffprobe`.
+
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::{CommandRunner, Error, Result, SystemRunner, exec};
+
+/// Probes the duration of a media file using `ffprobe`.
+///
+/// # Returns
+///
+/// * `Option<Duration>` - The duration of the file, or `None` if it could not be determined.
+pub(crate) fn probe_duration(runner: &dyn CommandRunner, path: &Path) -> Option<Duration> {
+    let output = runner
+        .output(
+            exec::command("ffprobe")
+                .args([
+                    "-v",
+                    "error",
+                    "-show_entries",
+                    "format=duration",
+                    "-of",
+                    "default=noprint_wrappers=1:nokey=1",
+                ])
+                .arg(path)
+                .stdin(Stdio::null()),
+        )
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let secs: f64 = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .ok()?;
+    (secs.is_finite() && secs > 0.0).then(|| Duration::from_secs_f64(secs))
+}
+
+/// Probes the bitrate of the first audio stream of a media file using
+/// `ffprobe`, falling back to the overall bitrate of the file when the stream
+/// does not declare one (e.g., in Ogg files).
+///
+/// # Returns
+///
+/// * `Option<u32>` - The bitrate in bits per second, or `None` if it could not be determined.
+pub(crate) fn probe_bitrate(runner: &dyn CommandRunner, path: &Path) -> Option<u32> {
+    let output = runner
+        .output(
+            exec::command("ffprobe")
+                .args([
+                    "-v",
+                    "error",
+                    "-select_streams",
+                    "a:0",
+                    "-show_entries",
+                    "stream=bit_rate:format=bit_rate",
+                    "-of",
+                    "default=noprint_wrappers=1:nokey=1",
+                ])
+                .arg(path)
+                .stdin(Stdio::null()),
+        )
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // The stream's bitrate comes first; unknown values are printed as "N/A"
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().parse().ok())
+        .filter(|&bitrate| bitrate > 0)
+}
+
+/// Checks that ffmpeg can be run, either from the `PATH` or from a build
+/// fetched with [`download_ffmpeg`](crate::download_ffmpeg).
+///
+/// # Returns
+///
+/// * `Result<()>` - [`Error::FfmpegNotFound`] if ffmpeg cannot be found.
+pub fn ensure_ffmpeg() -> Result<()> {
+    check_ffmpeg(&SystemRunner)
+}
+
+/// Checks that ffmpeg can be run with `runner`.
+///
+/// # Returns
+///
+/// * `Result<()>` - [`Error::FfmpegNotFound`] if ffmpeg cannot be found.
+pub(crate) fn check_ffmpeg(runner: &dyn CommandRunner) -> Result<()> {
+    runner
+        .output(exec::command("ffmpeg").arg("-version").stdin(Stdio::null()))
+        .map_err(Error::from_spawn)?;
+    Ok(())
+}
+
+/// Returns whether the installed ffmpeg provides the filter `name`.
+pub(crate) fn has_filter(runner: &dyn CommandRunner, name: &str) -> bool {
+    // Lines look like " ... rubberband        A->A       Apply time-stretching..."
+    listed_names(runner, "-filters").is_some_and(|names| names.iter().any(|n| n == name))
+}
+
+/// Returns the names of the encoders of the installed ffmpeg, or `None` if
+/// they cannot be listed.
+pub(crate) fn encoders(runner: &dyn CommandRunner) -> Option<Vec<String>> {
+    // Lines look like " A....D libmp3lame           libmp3lame MP3 (MPEG audio layer 3)"
+    listed_names(runner, "-encoders")
+}
+
+/// Returns the second column of what ffmpeg prints with `flag` (e.g.
+/// `-filters`), where the components it lists are named, or `None` if ffmpeg
+/// cannot be run or lists nothing.
+fn listed_names(runner: &dyn CommandRunner, flag: &str) -> Option<Vec<String>> {
+    let output = runner
+        .output(
+            exec::command("ffmpeg")
+                .args(["-hide_banner", flag])
+                .stdin(Stdio::null()),
+        )
+        .ok()?;
+    let names: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect();
+    (!names.is_empty()).then_some(names)
+}
+
+/// Runs an ffmpeg command that was given `-progress pipe:1`, reporting the
+/// output timestamp to `on_progress` each time ffmpeg emits a progress block.
+/// If ffmpeg is still running after `timeout`, it is killed.
+///
+/// # Returns
+///
+/// * `std::io::Result<(ExitStatus, String)>` - The exit status of the ffmpeg process and what it
+///   wrote to its standard error, or an error of kind [`ErrorKind::TimedOut`] if it was killed.
+///
+/// This is how [`SystemRunner`](crate::SystemRunner) runs commands.
+pub(crate) fn run_with_progress(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    on_progress: &mut dyn FnMut(Duration),
+) -> std::io::Result<(ExitStatus, String)> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Read stderr on another thread so neither pipe can fill up and block ffmpeg
+    let stderr_reader = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            _ = stderr.read_to_end(&mut buffer);
+            String::from_utf8_lossy(&buffer).into_owned()
+        })
+    });
+    // Progress is read on another thread too, so the deadline can be watched
+    // here while ffmpeg is silent
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if let Some(out_time) = parse_out_time(&line)
+                    && sender.send(out_time).is_err()
+                {
+                    break;
+                }
+            }
+        });
+    } else {
+        drop(sender);
+    }
+
+    let mut timed_out = false;
+    loop {
+        let received = match deadline {
+            Some(deadline) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(out_time) => on_progress(out_time),
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                timed_out = true;
+                break;
+            }
+        }
+    }
+    // ffmpeg may still be running after closing its output
+    let status = match deadline {
+        Some(deadline) if !timed_out => loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if Instant::now() >= deadline {
+                break None;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        },
+        Some(_) => None,
+        None => Some(child.wait()?),
+    };
+    let Some(status) = status else {
+        // The process may have exited in the meantime
+        _ = child.kill();
+        _ = child.wait();
+        return Err(std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!(
+                "ffmpeg did not finish within {}",
+                humantime::format_duration(timeout.unwrap_or_default())
+            ),
+        ));
+    };
+    let stderr = stderr_reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    Ok((status, stderr))
+}
+
+/// Parses an `out_time_ms=...` line of ffmpeg's `-progress` output.
+///
+/// Despite its name, ffmpeg reports this value in microseconds.
+fn parse_out_time(line: &str) -> Option<Duration> {
+    let value = line.strip_prefix("out_time_ms=")?;
+    value.trim().parse::<u64>().ok().map(Duration::from_micros)
+}
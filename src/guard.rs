@@ -0,0 +1,243 @@
+//! Holding back new files while the machine runs low on battery or hot, so
+//! long runs on a laptop do not drain or overheat it.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::RunControl;
+
+/// How far conditions must recover past the limits before new files are
+/// started again, so a run does not flap around a limit.
+const BATTERY_MARGIN: u8 = 5;
+const TEMPERATURE_MARGIN: f32 = 5.0;
+
+/// Limits on the battery and CPU temperature, checked before each file is
+/// started, set with
+/// [`ProcessOptions::resource_guard`](crate::ProcessOptions::resource_guard).
+///
+/// While a limit is exceeded, files in progress are finished but no new file
+/// is started. The sensors are polled every
+/// [`poll_interval`](Self::poll_interval), and the run resumes by itself
+/// once the battery is charging or has regained a few percent, and the CPU
+/// has cooled a few degrees below the limit. Sensors are read from
+/// `/sys/class` on Linux; on macOS only the battery is known (through
+/// `pmset`), and elsewhere nothing is, so the guard never pauses.
+///
+/// # Example
+///
+/// ```
+/// use audio_batch_speedup::{AudioFormat, ProcessOptions, ResourceGuard};
+///
+/// let options = ProcessOptions {
+///     resource_guard: Some(ResourceGuard {
+///         min_battery: Some(30),
+///         ..ResourceGuard::new()
+///     }),
+///     ..ProcessOptions::new(1.5, AudioFormat::ALL)
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceGuard {
+    /// Pause while running on a battery charged below this percentage.
+    pub min_battery: Option<u8>,
+    /// Pause while the hottest thermal sensor is above this temperature, in
+    /// degrees Celsius.
+    pub max_temperature: Option<f32>,
+    /// How often the sensors are read while paused.
+    pub poll_interval: Duration,
+}
+
+impl ResourceGuard {
+    /// Creates a guard pausing below 20% of battery or above 85 °C, polling
+    /// every 30 seconds.
+    pub fn new() -> Self {
+        Self {
+            min_battery: Some(20),
+            max_temperature: Some(85.0),
+            poll_interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Returns why new files should be held back, if a limit is exceeded.
+    /// With `recovering`, the limits are tightened by the margins.
+    fn exceeded(&self, recovering: bool) -> Option<String> {
+        if let Some(min) = self.min_battery
+            && let Some(battery) = sensors::battery()
+            && battery.discharging
+        {
+            let min = if recovering {
+                min.saturating_add(BATTERY_MARGIN)
+            } else {
+                min
+            };
+            if battery.percent < min {
+                return Some(format!("battery at {}%", battery.percent));
+            }
+        }
+        if let Some(max) = self.max_temperature
+            && let Some(temperature) = sensors::temperature()
+        {
+            let max = if recovering {
+                max - TEMPERATURE_MARGIN
+            } else {
+                max
+            };
+            if temperature > max {
+                return Some(format!("CPU at {:.0} °C", temperature));
+            }
+        }
+        None
+    }
+}
+
+impl Default for ResourceGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Applies a [`ResourceGuard`] during a run, remembering whether it holds
+/// back new files so that the workers share one pause.
+pub(crate) struct ResourceMonitor {
+    guard: ResourceGuard,
+    /// When the sensors were last read, and what they exceeded then.
+    state: Mutex<(Option<Instant>, Option<String>)>,
+}
+
+impl ResourceMonitor {
+    /// Creates a monitor for `guard`, warning if no sensor can be read.
+    pub(crate) fn new(guard: ResourceGuard) -> Self {
+        if (guard.min_battery.is_none() || sensors::battery().is_none())
+            && (guard.max_temperature.is_none() || sensors::temperature().is_none())
+        {
+            warn!("No battery or temperature sensor found, the resource guard will not pause");
+        }
+        Self {
+            guard,
+            state: Mutex::new((None, None)),
+        }
+    }
+
+    /// Blocks while a limit of the guard is exceeded, or until the run is
+    /// stopped through `control`.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the next file may be started, i.e. the run was not stopped.
+    pub(crate) fn wait_for_resources(&self, control: &RunControl) -> bool {
+        loop {
+            if control.is_stopped() {
+                return false;
+            }
+            if !self.poll() {
+                return true;
+            }
+            // Sleep in short steps so that stopping is not delayed
+            std::thread::sleep(self.guard.poll_interval.min(Duration::from_secs(1)));
+        }
+    }
+
+    /// Reads the sensors unless they were read less than a poll interval
+    /// ago, and returns whether new files are held back.
+    fn poll(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (last_read, exceeded) = &mut *state;
+        // Sensors are only read once per interval while paused; when not
+        // paused, they are read before every file.
+        if exceeded.is_some()
+            && last_read.is_some_and(|last| last.elapsed() < self.guard.poll_interval)
+        {
+            return true;
+        }
+        *last_read = Some(Instant::now());
+        let now = self.guard.exceeded(exceeded.is_some());
+        match (&*exceeded, &now) {
+            (None, Some(reason)) => info!("Pausing new files: {}", reason),
+            (Some(_), None) => info!("Resuming, resources have recovered"),
+            _ => {}
+        }
+        *exceeded = now;
+        exceeded.is_some()
+    }
+}
+
+/// Readings of the battery and thermal sensors of the machine.
+mod sensors {
+    /// The state of the battery.
+    pub(super) struct Battery {
+        pub(super) percent: u8,
+        pub(super) discharging: bool,
+    }
+
+    /// Returns the state of the first battery, if the machine has one.
+    #[cfg(target_os = "linux")]
+    pub(super) fn battery() -> Option<Battery> {
+        let read = |path: &std::path::Path, name: &str| {
+            std::fs::read_to_string(path.join(name))
+                .ok()
+                .map(|s| s.trim().to_string())
+        };
+        std::fs::read_dir("/sys/class/power_supply")
+            .ok()?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| read(path, "type").as_deref() == Some("Battery"))
+            .find_map(|path| {
+                Some(Battery {
+                    percent: read(&path, "capacity")?.parse().ok()?,
+                    discharging: read(&path, "status")? == "Discharging",
+                })
+            })
+    }
+
+    /// Returns the state of the first battery, if the machine has one.
+    #[cfg(target_os = "macos")]
+    pub(super) fn battery() -> Option<Battery> {
+        // Lines look like " -InternalBattery-0 (id=...)	85%; discharging; 4:10 remaining"
+        let output = std::process::Command::new("pmset")
+            .args(["-g", "batt"])
+            .output()
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| {
+                let (_, status) = line.split_once('\t')?;
+                let mut fields = status.split(';').map(str::trim);
+                Some(Battery {
+                    percent: fields.next()?.strip_suffix('%')?.parse().ok()?,
+                    discharging: fields.next()? == "discharging",
+                })
+            })
+    }
+
+    /// Returns the state of the first battery, if the machine has one.
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub(super) fn battery() -> Option<Battery> {
+        None
+    }
+
+    /// Returns the temperature of the hottest thermal zone, in degrees
+    /// Celsius.
+    #[cfg(target_os = "linux")]
+    pub(super) fn temperature() -> Option<f32> {
+        std::fs::read_dir("/sys/class/thermal")
+            .ok()?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with("thermal_zone"))
+            .filter_map(|e| std::fs::read_to_string(e.path().join("temp")).ok())
+            // In millidegrees; unused zones report zero or less
+            .filter_map(|temp| temp.trim().parse::<i64>().ok())
+            .filter(|&temp| temp > 0)
+            .max()
+            .map(|temp| temp as f32 / 1000.0)
+    }
+
+    /// Returns the temperature of the hottest thermal zone, in degrees
+    /// Celsius.
+    #[cfg(not(target_os = "linux"))]
+    pub(super) fn temperature() -> Option<f32> {
+        None
+    }
+}
@@ -0,0 +1,464 @@
+//! `absu-gui`: a graphical frontend for people who would rather not use a
+//! terminal. It serves a page on localhost and opens it in the default
+//! browser, where a folder, a speed and formats are picked and the progress
+//! of the run is shown live.
+
+#![cfg_attr(windows, windows_subsystem = "windows")]
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use audio_batch_speedup::{
+    AudioFormat, FileStatus, HISTORY_NAME, ProcessEvent, ProcessOptions, RunControl, Speed,
+};
+use log::{LevelFilter, error, info, warn};
+
+#[path = "../http.rs"]
+mod http;
+#[path = "../json.rs"]
+mod json;
+
+use http::Request;
+use json::{json_path, json_string};
+
+/// The page of the frontend.
+const PAGE: &str = include_str!("index.html");
+
+/// Number of finished files listed on the page, most recent first.
+const RECENT_FILES: usize = 200;
+
+/// What the current run is doing.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum RunState {
+    #[default]
+    Idle,
+    /// Fetching ffmpeg and planning the files.
+    Preparing,
+    Running,
+    Done,
+    Stopped,
+    Failed,
+}
+
+impl RunState {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Preparing => "preparing",
+            Self::Running => "running",
+            Self::Done => "done",
+            Self::Stopped => "stopped",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// The current run, as shown on the page.
+#[derive(Default)]
+struct Run {
+    state: RunState,
+    folder: PathBuf,
+    /// Number of files to process.
+    total: usize,
+    /// Number of files finished, successfully or not.
+    done: usize,
+    /// Files in progress, with the fraction processed so far.
+    active: BTreeMap<PathBuf, f32>,
+    /// Finished files with their status and error, oldest first.
+    finished: Vec<(PathBuf, FileStatus, Option<String>)>,
+    processed: usize,
+    skipped: usize,
+    failed: usize,
+    saved: Duration,
+    error: Option<String>,
+    control: RunControl,
+}
+
+/// The state shared by the request handlers.
+struct Gui {
+    run: Mutex<Run>,
+    /// The address the page is served on, which requests must be sent to.
+    listen: SocketAddr,
+    /// The secret of this launch, passed to the page in its URL, which the
+    /// API calls must carry.
+    token: String,
+}
+
+fn main() {
+    _ = pretty_env_logger::formatted_builder()
+        .filter_level(LevelFilter::Info)
+        .parse_default_env()
+        .try_init();
+    if let Err(e) = serve() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Serves the page on a free port of localhost and opens it, until the page
+/// asks to quit.
+fn serve() -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let gui = Arc::new(Gui {
+        run: Mutex::default(),
+        listen: listener.local_addr()?,
+        token: http::random_token(),
+    });
+    let url = format!("http://{}/?token={}", gui.listen, gui.token);
+    info!("Serving the interface on {}", url);
+    open_browser(&url);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        let gui = Arc::clone(&gui);
+        std::thread::spawn(move || {
+            if let Err(e) = gui.handle(stream) {
+                warn!("Error handling request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Gui {
+    /// Locks the run, even if a thread panicked while holding it.
+    fn run(&self) -> MutexGuard<'_, Run> {
+        self.run.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Answers a single HTTP request on `stream`. Every request must be sent
+    /// to the address of the page, and the API calls must carry the token.
+    fn handle(self: Arc<Self>, stream: TcpStream) -> io::Result<()> {
+        let request = http::read_request(&stream)?;
+        let (status, response) = if !request.is_same_origin(self.listen) {
+            error_response("403 Forbidden", "unexpected host or origin")
+        } else if request.path.starts_with("/api/") && !request.has_token(&self.token) {
+            error_response("401 Unauthorized", "missing or wrong token")
+        } else {
+            return self.route(&stream, &request);
+        };
+        http::write_response(&stream, status, "application/json", response.as_bytes())
+    }
+
+    /// Answers an authenticated request on `stream`.
+    fn route(self: Arc<Self>, stream: &TcpStream, request: &Request) -> io::Result<()> {
+        let (status, response) = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => {
+                return http::write_response(
+                    stream,
+                    "200 OK",
+                    "text/html; charset=utf-8",
+                    PAGE.as_bytes(),
+                );
+            }
+            ("GET", "/api/formats") => {
+                let names: Vec<_> = AudioFormat::ALL
+                    .iter_names()
+                    .map(|(name, _)| json_string(name))
+                    .collect();
+                ("200 OK", format!("[{}]", names.join(",")))
+            }
+            ("GET", "/api/dirs") => dirs_json(request.param("path")),
+            ("GET", "/api/status") => ("200 OK", status_json(&self.run())),
+            ("POST", "/api/start") => self.start(request),
+            ("POST", "/api/pause") => {
+                self.run().control.pause();
+                ("200 OK", "{}".to_string())
+            }
+            ("POST", "/api/resume") => {
+                self.run().control.resume();
+                ("200 OK", "{}".to_string())
+            }
+            ("POST", "/api/stop") => {
+                self.run().control.stop();
+                ("200 OK", "{}".to_string())
+            }
+            ("POST", "/api/quit") => {
+                http::write_response(stream, "200 OK", "application/json", b"{}")?;
+                info!("Quitting at the request of the interface.");
+                self.run().control.stop();
+                std::process::exit(0);
+            }
+            _ => error_response("404 Not Found", "unknown endpoint"),
+        };
+        http::write_response(stream, status, "application/json", response.as_bytes())
+    }
+
+    /// Starts a run from the `folder`, `speed` and `formats` (names separated
+    /// by commas) parameters, unless one is in progress.
+    fn start(self: &Arc<Self>, request: &Request) -> (&'static str, String) {
+        let Some(folder) = request.param("folder").map(PathBuf::from) else {
+            return error_response("400 Bad Request", "missing folder");
+        };
+        if !folder.is_dir() {
+            return error_response("400 Bad Request", "the folder does not exist");
+        }
+        let speed = match request.param("speed").map(str::parse::<Speed>) {
+            Some(Ok(speed)) => speed.factor(),
+            Some(Err(e)) => return error_response("400 Bad Request", &e.to_string()),
+            None => return error_response("400 Bad Request", "missing speed"),
+        };
+        let formats = request
+            .param("formats")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(AudioFormat::from_name)
+            .fold(AudioFormat::empty(), |acc, format| acc | format);
+        if formats.is_empty() {
+            return error_response("400 Bad Request", "no format selected");
+        }
+
+        let options = {
+            let mut run = self.run();
+            if matches!(run.state, RunState::Preparing | RunState::Running) {
+                return error_response("409 Conflict", "a run is already in progress");
+            }
+            *run = Run {
+                state: RunState::Preparing,
+                folder: folder.clone(),
+                ..Run::default()
+            };
+            ProcessOptions {
+                progress_bar: false,
+                control: run.control.clone(),
+                ..ProcessOptions::new(speed, formats)
+            }
+        };
+        let gui = Arc::clone(self);
+        std::thread::spawn(move || gui.process(&folder, &options));
+        ("202 Accepted", "{}".to_string())
+    }
+
+    /// Processes `folder`, downloading ffmpeg first if needed, and records
+    /// the progress into the run.
+    fn process(&self, folder: &Path, options: &ProcessOptions) {
+        info!("Starting processing for: {}", folder.display());
+        if audio_batch_speedup::ensure_ffmpeg().is_err()
+            && let Some(dir) = audio_batch_speedup::ffmpeg_dir()
+        {
+            info!("Downloading ffmpeg into {}", dir.display());
+            if let Err(e) = audio_batch_speedup::download_ffmpeg() {
+                error!("Error downloading ffmpeg: {}", e);
+                let mut run = self.run();
+                run.state = RunState::Failed;
+                run.error = Some(e.to_string());
+                return;
+            }
+        }
+        let result = audio_batch_speedup::plan(folder, options).and_then(|files| {
+            {
+                let mut run = self.run();
+                run.total = files.iter().filter(|f| f.will_process()).count();
+                run.state = RunState::Running;
+            }
+            audio_batch_speedup::process_planned_files_with_events(files, options, |event| {
+                self.record_event(folder, event)
+            })
+        });
+
+        let mut run = self.run();
+        match result {
+            Ok(report) => {
+                if let Err(e) =
+                    audio_batch_speedup::record_history(folder.join(HISTORY_NAME), folder, &report)
+                {
+                    warn!("Error recording the history of {}: {}", folder.display(), e);
+                }
+                run.state = if options.control.is_stopped() {
+                    RunState::Stopped
+                } else {
+                    RunState::Done
+                };
+                run.processed = report.processed_count();
+                run.skipped = report.skipped_count();
+                run.failed = report.failed_count();
+                run.saved = report.time_saved();
+                info!(
+                    "Processed {} files, {} failed.",
+                    report.processed_count(),
+                    report.failed_count()
+                );
+            }
+            Err(e) => {
+                error!("Error processing {}: {}", folder.display(), e);
+                run.state = RunState::Failed;
+                run.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Updates the run with an event of the files under `folder`.
+    fn record_event(&self, folder: &Path, event: ProcessEvent) {
+        let relative = |path: &Path| path.strip_prefix(folder).unwrap_or(path).to_path_buf();
+        let mut run = self.run();
+        let finished = match event {
+            ProcessEvent::FileStarted { path } => {
+                run.active.insert(relative(&path), 0.0);
+                return;
+            }
+            ProcessEvent::Progress { path, fraction } => {
+                run.active.insert(relative(&path), fraction);
+                return;
+            }
+            ProcessEvent::FileFinished { report } => (relative(&report.path), report.status, None),
+            ProcessEvent::FileFailed { path, stderr } => {
+                let error = stderr.lines().map(str::trim).rfind(|line| !line.is_empty());
+                (
+                    relative(&path),
+                    FileStatus::Failed,
+                    Some(error.unwrap_or("processing failed").to_string()),
+                )
+            }
+        };
+        run.active.remove(&finished.0);
+        run.done += 1;
+        run.finished.push(finished);
+        if run.finished.len() > RECENT_FILES {
+            run.finished.remove(0);
+        }
+    }
+}
+
+/// Encodes the run as a JSON object.
+fn status_json(run: &Run) -> String {
+    let active: Vec<_> = run
+        .active
+        .iter()
+        .map(|(path, fraction)| {
+            format!(
+                "{{\"path\":{},\"fraction\":{:.3}}}",
+                json_path(path),
+                fraction
+            )
+        })
+        .collect();
+    let finished: Vec<_> = run
+        .finished
+        .iter()
+        .rev()
+        .map(|(path, status, error)| {
+            format!(
+                "{{\"path\":{},\"status\":{},\"error\":{}}}",
+                json_path(path),
+                json_string(&status.to_string()),
+                error.as_deref().map_or("null".to_string(), json_string)
+            )
+        })
+        .collect();
+    format!(
+        "{{\"state\":{},\"paused\":{},\"folder\":{},\"total\":{},\"done\":{},\"active\":[{}],\"finished\":[{}],\"processed\":{},\"skipped\":{},\"failed\":{},\"saved_secs\":{},\"error\":{}}}",
+        json_string(run.state.name()),
+        run.control.is_paused(),
+        json_path(&run.folder),
+        run.total,
+        run.done,
+        active.join(","),
+        finished.join(","),
+        run.processed,
+        run.skipped,
+        run.failed,
+        run.saved.as_secs(),
+        run.error.as_deref().map_or("null".to_string(), json_string),
+    )
+}
+
+/// Lists the subfolders of `path`, or of the home folder if not given, for
+/// the folder picker. Hidden folders are left out.
+fn dirs_json(path: Option<&str>) -> (&'static str, String) {
+    let path = match path.filter(|path| !path.is_empty()) {
+        Some(path) => PathBuf::from(path),
+        None => home_dir(),
+    };
+    let entries = match std::fs::read_dir(&path) {
+        Ok(entries) => entries,
+        Err(e) => return error_response("400 Bad Request", &e.to_string()),
+    };
+    let mut dirs: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    dirs.sort_by_key(|name| name.to_lowercase());
+    let dirs: Vec<_> = dirs.iter().map(|name| json_string(name)).collect();
+    (
+        "200 OK",
+        format!(
+            "{{\"path\":{},\"parent\":{},\"dirs\":[{}]}}",
+            json_path(&path),
+            path.parent().map_or("null".to_string(), json_path),
+            dirs.join(",")
+        ),
+    )
+}
+
+/// Returns the home folder of the user, or the current folder if unknown.
+fn home_dir() -> PathBuf {
+    std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .map(PathBuf::from)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default()
+}
+
+/// Returns an error status with a JSON body carrying `message`.
+fn error_response(status: &'static str, message: &str) -> (&'static str, String) {
+    (status, format!("{{\"error\":{}}}", json_string(message)))
+}
+
+/// Opens `url` in the default browser. Failures are logged, with the URL to
+/// open by hand.
+fn open_browser(url: &str) {
+    match browser_command(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(status) if status.success() => {}
+        _ => warn!("Could not open a browser; open {} by hand.", url),
+    }
+}
+
+/// Returns the command opening a URL: `xdg-open` on Linux and other Unix
+/// systems.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn browser_command(url: &str) -> Command {
+    let mut command = Command::new("xdg-open");
+    command.arg(url);
+    command
+}
+
+/// Returns the command opening a URL: `open` on macOS.
+#[cfg(target_os = "macos")]
+fn browser_command(url: &str) -> Command {
+    let mut command = Command::new("open");
+    command.arg(url);
+    command
+}
+
+/// Returns the command opening a URL on Windows, through the URL protocol
+/// handler so that no console window shows up.
+#[cfg(windows)]
+fn browser_command(url: &str) -> Command {
+    let mut command = Command::new("rundll32");
+    command.args(["url.dll,FileProtocolHandler", url]);
+    command
+}
+
+/// Returns a command that fails, as opening a browser is not supported on
+/// this platform.
+#[cfg(not(any(unix, windows)))]
+fn browser_command(_url: &str) -> Command {
+    Command::new("xdg-open")
+}
@@ -0,0 +1,156 @@
+//! A history of the files processed in a folder, kept across runs.
+
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::{Error, FileStatus, ProcessReport, Result};
+
+/// Name of the history file, kept at the root of the processed folder.
+pub const HISTORY_NAME: &str = ".absu-history.tsv";
+
+/// Header line of a history file, naming its columns.
+const HEADER: &str = "time\tstatus\tspeed\tinput_duration_secs\toutput_duration_secs\tpath";
+
+/// A file processed or failed in an earlier run, as recorded in a history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// When the run finished, to the second.
+    pub time: SystemTime,
+    /// Path of the file, relative to the processed folder.
+    pub path: PathBuf,
+    /// Whether the file was processed or failed.
+    pub status: FileStatus,
+    /// The speed applied to the file, if known.
+    pub speed: Option<f32>,
+    /// Duration of the original file, if it was probed.
+    pub input_duration: Option<Duration>,
+    /// Duration of the processed file, if it was probed.
+    pub output_duration: Option<Duration>,
+}
+
+/// Appends the processed and failed files of `report` to the history at
+/// `history`, creating it if needed. Paths under `root` are recorded
+/// relative to it.
+///
+/// # Arguments
+///
+/// * `history` - The path of the history, usually [`HISTORY_NAME`] in `root`.
+/// * `root` - The processed folder.
+/// * `report` - The report of the run.
+///
+/// # Returns
+///
+/// * `Result<usize>` - The number of recorded files.
+pub fn record_history(
+    history: impl AsRef<Path>,
+    root: impl AsRef<Path>,
+    report: &ProcessReport,
+) -> Result<usize> {
+    let history = history.as_ref();
+    let root = root.as_ref();
+    let time = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let secs = |d: Option<Duration>| d.map_or(String::new(), |d| format!("{:.3}", d.as_secs_f64()));
+    let mut content = String::new();
+    if std::fs::metadata(history).map_or(true, |m| m.len() == 0) {
+        writeln!(content, "{}", HEADER).expect("writing to a String cannot fail");
+    }
+    let mut count = 0;
+    for file in &report.files {
+        if file.status == FileStatus::Skipped {
+            continue;
+        }
+        let path = file.path.strip_prefix(root).unwrap_or(&file.path);
+        writeln!(
+            content,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            time,
+            file.status,
+            file.speed.map_or(String::new(), |s| s.to_string()),
+            secs(file.input_duration),
+            secs(file.output_duration),
+            path.display()
+        )
+        .expect("writing to a String cannot fail");
+        count += 1;
+    }
+    std::fs::File::options()
+        .create(true)
+        .append(true)
+        .open(history)?
+        .write_all(content.as_bytes())?;
+    Ok(count)
+}
+
+/// Reads the history at `history`, oldest entries first. A missing history
+/// is empty, and entries with a negative or non-finite duration are skipped.
+///
+/// # Returns
+///
+/// * `Result<Vec<HistoryEntry>>` - The recorded files, or [`Error::InvalidOptions`] if
+///   `history` is not a history.
+pub fn read_history(history: impl AsRef<Path>) -> Result<Vec<HistoryEntry>> {
+    let history = history.as_ref();
+    let content = match std::fs::read_to_string(history) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut lines = content.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(Error::InvalidOptions(format!(
+            "{} is not a processing history",
+            history.display()
+        )));
+    }
+    lines
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| match parse_entry(line) {
+            Some(Some(entry)) => Some(Ok(entry)),
+            Some(None) => {
+                log::warn!(
+                    "skipping an entry with an invalid duration in {}: {}",
+                    history.display(),
+                    line
+                );
+                None
+            }
+            None => Some(Err(Error::InvalidOptions(format!(
+                "malformed line in {}: {}",
+                history.display(),
+                line
+            )))),
+        })
+        .collect()
+}
+
+/// Parses a line of a history, giving `Some(None)` for an entry whose
+/// duration is negative or not finite, as only an edited history has.
+fn parse_entry(line: &str) -> Option<Option<HistoryEntry>> {
+    let mut columns = line.splitn(6, '\t');
+    let time = humantime::parse_rfc3339(columns.next()?).ok()?;
+    let status = match columns.next()? {
+        "processed" => FileStatus::Processed,
+        "failed" => FileStatus::Failed,
+        _ => return None,
+    };
+    let speed = columns.next()?.parse().ok();
+    let mut duration = || {
+        Some(match columns.next()?.parse() {
+            Ok(secs) => Duration::try_from_secs_f64(secs).map(Some).map_err(drop),
+            Err(_) => Ok(None),
+        })
+    };
+    let (Ok(input_duration), Ok(output_duration)) = (duration()?, duration()?) else {
+        return Some(None);
+    };
+    Some(Some(HistoryEntry {
+        time,
+        status,
+        speed,
+        input_duration,
+        output_duration,
+        path: PathBuf::from(columns.next()?),
+    }))
+}
@@ -0,0 +1,56 @@
+//! User commands run before and after each file is processed.
+
+use std::ffi::OsStr;
+use std::process::Stdio;
+
+use log::{debug, warn};
+
+use crate::exec;
+
+/// Runs the hook `command` through the shell, passing `args` as its
+/// arguments (`$1`, `$2`, ... in a shell script).
+///
+/// # Returns
+///
+/// * `bool` - Whether the command ran and exited successfully. Failures are logged.
+pub(crate) fn run_hook(command: &str, args: &[&OsStr]) -> bool {
+    let mut shell = shell_command(command);
+    let output = shell.args(args).stdin(Stdio::null()).output();
+    match output {
+        Ok(output) if output.status.success() => {
+            debug!("Hook `{}` succeeded for {:?}", command, args);
+            true
+        }
+        Ok(output) => {
+            warn!(
+                "Hook `{}` failed for {:?}. Exit code: {:?}\n{}",
+                command,
+                args,
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+            false
+        }
+        Err(e) => {
+            warn!("Error running hook `{}`: {}", command, e);
+            false
+        }
+    }
+}
+
+/// Returns a shell command running `command` with the arguments added to it.
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> std::process::Command {
+    let mut shell = exec::command("sh");
+    // The arguments become the positional parameters, after `$0`
+    shell.arg("-c").arg(format!("{} \"$@\"", command)).arg("sh");
+    shell
+}
+
+/// Returns a shell command running `command` with the arguments added to it.
+#[cfg(windows)]
+fn shell_command(command: &str) -> std::process::Command {
+    let mut shell = exec::command("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
@@ -0,0 +1,240 @@
+//! Just enough HTTP/1.1 to serve the local APIs: reading a request and
+//! answering it, one request per connection.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// A parsed HTTP request.
+pub(crate) struct Request {
+    pub(crate) method: String,
+    /// The path of the target, without the query string.
+    pub(crate) path: String,
+    /// The parameters of the query string, followed by those of a form body.
+    pub(crate) params: Vec<(String, String)>,
+    /// The headers, with lowercase names.
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Returns the last value of the parameter `name`, if any.
+    pub(crate) fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the value of the header `name` (in lowercase), if any.
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns whether the request was sent to `listen` by a page served
+    /// from it, or by a program other than a browser. Its `Host` must name
+    /// `listen` by address (or as `localhost`), so that a site resolving its
+    /// own name to `listen` (DNS rebinding) is rejected, and its `Origin`, if
+    /// any, must be the same host, so that other sites open in the browser
+    /// cannot send requests.
+    pub(crate) fn is_same_origin(&self, listen: SocketAddr) -> bool {
+        let Some(host) = self.header("host") else {
+            return false;
+        };
+        let names_listen = host.rsplit_once(':').is_some_and(|(name, port)| {
+            let name = name.trim_start_matches('[').trim_end_matches(']');
+            port.parse() == Ok(listen.port())
+                && match name.parse::<IpAddr>() {
+                    Ok(ip) => ip == listen.ip() || listen.ip().is_unspecified(),
+                    Err(_) => {
+                        name.eq_ignore_ascii_case("localhost")
+                            && (listen.ip().is_loopback() || listen.ip().is_unspecified())
+                    }
+                }
+        });
+        names_listen
+            && self
+                .header("origin")
+                .is_none_or(|origin| origin == format!("http://{}", host))
+    }
+
+    /// Returns whether the request carries `token` in an
+    /// `Authorization: Bearer` header. Browsers only send that header to
+    /// another site after asking it (a CORS preflight), which the local
+    /// servers never allow.
+    pub(crate) fn has_token(&self, token: &str) -> bool {
+        self.header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| {
+                // Compared in constant time, so the token cannot be guessed
+                // byte by byte
+                given.len() == token.len()
+                    && given
+                        .bytes()
+                        .zip(token.bytes())
+                        .fold(0, |diff, (a, b)| diff | (a ^ b))
+                        == 0
+            })
+    }
+}
+
+/// Returns a random token of 32 hex digits, for clients to authenticate with.
+pub(crate) fn random_token() -> String {
+    // The keys of `RandomState` are seeded by the operating system
+    (0..2)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect()
+}
+
+/// Reads a request from `stream`.
+pub(crate) fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let mut headers = Vec::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length.min(MAX_BODY_SIZE)];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut params = parse_form(query);
+    params.extend(parse_form(&String::from_utf8_lossy(&body)));
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        params,
+        headers,
+    })
+}
+
+/// Writes a response with `status` (e.g., `200 OK`) and `body`, and closes
+/// the connection.
+pub(crate) fn write_response(
+    mut stream: &TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Parses `application/x-www-form-urlencoded` parameters, as found in query
+/// strings.
+fn parse_form(form: &str) -> Vec<(String, String)> {
+    form.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes and `+` as a space.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if let Some(byte) = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()) =>
+            {
+                decoded.push(byte);
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Request;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "POST".to_string(),
+            path: "/jobs".to_string(),
+            params: Vec::new(),
+            headers: headers
+                .iter()
+                .map(|&(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn accepts_only_requests_to_the_listen_address() {
+        let listen = "127.0.0.1:7878".parse().unwrap();
+        for host in ["127.0.0.1:7878", "localhost:7878"] {
+            assert!(
+                request(&[("host", host)]).is_same_origin(listen),
+                "{}",
+                host
+            );
+        }
+        let same_page = request(&[
+            ("host", "127.0.0.1:7878"),
+            ("origin", "http://127.0.0.1:7878"),
+        ]);
+        assert!(same_page.is_same_origin(listen));
+        // Another site, directly or by resolving its name to the address
+        let other_page = request(&[
+            ("host", "127.0.0.1:7878"),
+            ("origin", "https://example.com"),
+        ]);
+        assert!(!other_page.is_same_origin(listen));
+        for host in ["example.com:7878", "127.0.0.1:80", "127.0.0.1"] {
+            assert!(
+                !request(&[("host", host)]).is_same_origin(listen),
+                "{}",
+                host
+            );
+        }
+        assert!(!request(&[]).is_same_origin(listen));
+    }
+
+    #[test]
+    fn checks_the_token() {
+        assert!(request(&[("authorization", "Bearer secret")]).has_token("secret"));
+        assert!(!request(&[("authorization", "Bearer secrets")]).has_token("secret"));
+        assert!(!request(&[("authorization", "secret")]).has_token("secret"));
+        assert!(!request(&[]).has_token("secret"));
+    }
+}
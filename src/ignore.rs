@@ -0,0 +1,230 @@
+//! Excluding files from a run with gitignore-style `.speedupignore` files,
+//! kept in the processed folder and its subfolders.
+//!
+//! Each line of an ignore file is a pattern, matched against paths relative
+//! to the folder of the ignore file:
+//!
+//! * Blank lines and lines starting with `#` are ignored.
+//! * `*` matches anything but `/`, `?` a single character, and `[a-z]` or
+//!   `[!0-9]` a set of characters.
+//! * A pattern without `/` matches a file or folder at any depth, e.g.
+//!   `*.wav`. Otherwise it is relative to the folder of the ignore file,
+//!   e.g. `/Music` or `Podcasts/old`, and `**` matches any number of folders.
+//! * A pattern ending with `/` only matches folders.
+//! * A pattern starting with `!` includes again the files excluded by an
+//!   earlier pattern, unless a parent folder is excluded.
+//!
+//! Patterns of deeper ignore files take precedence, and among the patterns
+//! of a file, the last matching one wins.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+/// Name of the ignore files, looked for in the processed folder and each of
+/// its subfolders.
+pub const IGNORE_FILE_NAME: &str = ".speedupignore";
+
+/// A segment of a pattern, between two slashes.
+#[derive(Debug)]
+enum Segment {
+    /// `**`, matching any number of folders.
+    AnyFolders,
+    /// A file or folder name, possibly with wildcards.
+    Name(Vec<char>),
+}
+
+/// A line of an ignore file.
+#[derive(Debug)]
+struct Rule {
+    segments: Vec<Segment>,
+    /// Whether the pattern only matches folders.
+    folders_only: bool,
+    /// Whether matching paths are included again, with `!`.
+    negated: bool,
+}
+
+impl Rule {
+    /// Parses a line of an ignore file, or returns `None` for blank lines and
+    /// comments.
+    fn parse(line: &str) -> Option<Self> {
+        let mut pattern = line.trim_end();
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return None;
+        }
+        let negated = pattern.starts_with('!');
+        // `\!` and `\#` start patterns with a literal `!` or `#`
+        if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+            pattern = &pattern[1..];
+        }
+        let folders_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let mut segments: Vec<Segment> = pattern
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| match segment {
+                "**" => Segment::AnyFolders,
+                name => Segment::Name(name.chars().collect()),
+            })
+            .collect();
+        if segments.is_empty() {
+            return None;
+        }
+        if !anchored {
+            segments.insert(0, Segment::AnyFolders);
+        }
+        Some(Self {
+            segments,
+            folders_only,
+            negated,
+        })
+    }
+
+    /// Returns whether the rule matches the path made of `components`.
+    fn matches(&self, components: &[String], is_folder: bool) -> bool {
+        (is_folder || !self.folders_only) && match_segments(&self.segments, components)
+    }
+}
+
+/// Returns whether `segments` match the path made of `components`.
+fn match_segments(segments: &[Segment], components: &[String]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        // A trailing `**` matches the contents of a folder, not the folder
+        Some((Segment::AnyFolders, [])) => !components.is_empty(),
+        Some((Segment::AnyFolders, rest)) => {
+            (0..=components.len()).any(|skipped| match_segments(rest, &components[skipped..]))
+        }
+        Some((Segment::Name(pattern), rest)) => {
+            components.split_first().is_some_and(|(name, components)| {
+                let name: Vec<char> = name.chars().collect();
+                match_name(pattern, &name) && match_segments(rest, components)
+            })
+        }
+    }
+}
+
+/// Returns whether the file or folder `name` matches `pattern`, with `*`,
+/// `?` and `[...]` wildcards and `\` escapes.
+fn match_name(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skipped| match_name(rest, &name[skipped..])),
+        Some(('?', rest)) => !name.is_empty() && match_name(rest, &name[1..]),
+        Some(('[', rest)) if let Some(end) = class_end(rest) => {
+            name.split_first().is_some_and(|(&c, name)| {
+                match_class(&rest[..end], c) && match_name(&rest[end + 1..], name)
+            })
+        }
+        Some(('\\', [escaped, rest @ ..])) => {
+            name.first() == Some(escaped) && match_name(rest, &name[1..])
+        }
+        Some((c, rest)) => name.first() == Some(c) && match_name(rest, &name[1..]),
+    }
+}
+
+/// Returns the index of the `]` closing a character class whose contents
+/// start `class`, if any. A `]` right after the opening bracket (and its
+/// negation) belongs to the class.
+fn class_end(class: &[char]) -> Option<usize> {
+    let start = match class.first() {
+        Some('!' | '^') => 2,
+        _ => 1,
+    };
+    class
+        .iter()
+        .skip(start)
+        .position(|&c| c == ']')
+        .map(|i| i + start)
+}
+
+/// Returns whether `c` is in the character class `class`, given without its
+/// brackets, e.g. `!a-z_`.
+fn match_class(class: &[char], c: char) -> bool {
+    let (negated, mut class) = match class.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut found = false;
+    while let Some((&first, rest)) = class.split_first() {
+        class = match rest {
+            ['-', last, rest @ ..] => {
+                found |= (first..=*last).contains(&c);
+                rest
+            }
+            _ => {
+                found |= first == c;
+                rest
+            }
+        };
+    }
+    found != negated
+}
+
+/// The ignore files of a folder being walked, read as their folders are
+/// reached.
+pub(crate) struct IgnoreFiles {
+    root: PathBuf,
+    /// The rules of the ignore file of each folder seen so far, empty if it
+    /// has none.
+    rules: HashMap<PathBuf, Vec<Rule>>,
+}
+
+impl IgnoreFiles {
+    /// Starts looking for ignore files in `root` and its subfolders.
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            rules: HashMap::new(),
+        }
+    }
+
+    /// Returns whether the file or folder at `path`, under the root, is
+    /// excluded by the ignore files of its parent folders. Folders must be
+    /// checked before their contents, which are not excluded again.
+    pub(crate) fn is_ignored(&mut self, path: &Path, is_folder: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let components: Vec<String> = relative
+            .iter()
+            .map(|component| component.to_string_lossy().into_owned())
+            .collect();
+        let mut parents = relative.iter();
+        let mut ignored = false;
+        let mut folder = self.root.clone();
+        // From the root down, so deeper ignore files come last and win
+        for depth in 0..components.len() {
+            if depth > 0
+                && let Some(parent) = parents.next()
+            {
+                folder.push(parent);
+            }
+            let rules = self
+                .rules
+                .entry(folder.clone())
+                .or_insert_with_key(|folder| read_rules(folder));
+            for rule in rules.iter() {
+                if rule.matches(&components[depth..], is_folder) {
+                    ignored = !rule.negated;
+                }
+            }
+        }
+        ignored
+    }
+}
+
+/// Reads the rules of the ignore file in `folder`, if it has one.
+fn read_rules(folder: &Path) -> Vec<Rule> {
+    let path = folder.join(IGNORE_FILE_NAME);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => content.lines().filter_map(Rule::parse).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            warn!("Error reading {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! A journal of the files completed during a run, so an interrupted run can be
+//! resumed.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A journal file to which completed files are appended as they finish.
+pub(crate) struct Journal {
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// Opens the journal at `path` for appending, creating it if needed.
+    pub(crate) fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            file: Mutex::new(File::options().create(true).append(true).open(path)?),
+        })
+    }
+
+    /// Records that the file at `relative_path` (relative to the processed
+    /// folder) is completed. The entry is synced to disk, so it survives a
+    /// crash of the machine.
+    pub(crate) fn record(&self, relative_path: &Path) {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(file, "{}", relative_path.display()).and_then(|_| file.sync_data())
+        {
+            log::error!("Error writing to journal: {}", e);
+        }
+    }
+}
+
+/// Reads the relative paths recorded in the journal at `path`. A missing
+/// journal is empty.
+pub(crate) fn read_journal(path: &Path) -> std::io::Result<HashSet<PathBuf>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,27 @@
+//! Encoding of JSON values, for the JSON lines, reports and HTTP APIs.
+
+use std::path::Path;
+
+/// Encodes a string as a JSON string literal.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len() + 2);
+    encoded.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => encoded.push_str("\\\""),
+            '\\' => encoded.push_str("\\\\"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\r"),
+            '\t' => encoded.push_str("\\t"),
+            c if (c as u32) < 0x20 => encoded.push_str(&format!("\\u{:04x}", c as u32)),
+            c => encoded.push(c),
+        }
+    }
+    encoded.push('"');
+    encoded
+}
+
+/// Encodes a path as a JSON string, replacing invalid UTF-8 sequences.
+pub(crate) fn json_path(path: &Path) -> String {
+    json_string(&path.to_string_lossy())
+}
@@ -0,0 +1,110 @@
+//! Keyboard controls pausing, resuming and stopping a run in progress.
+
+use std::io::Read;
+
+use audio_batch_speedup::RunControl;
+use log::info;
+
+/// Reads keys from the terminal on a background thread and applies them to
+/// `control`: `p` pauses, `r` resumes and `q` stops after the files in
+/// progress. The terminal is restored when the returned guard is dropped.
+pub(crate) fn spawn_key_controls(control: RunControl) -> KeyGuard {
+    let guard = KeyGuard::enter();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut key = [0];
+        while !control.is_stopped() && matches!(stdin.read(&mut key), Ok(1)) {
+            match key[0].to_ascii_lowercase() {
+                b'p' if !control.is_paused() => {
+                    control.pause();
+                    info!("Paused: no new files are started. Press r to resume.");
+                }
+                b'r' if control.is_paused() => {
+                    control.resume();
+                    info!("Resumed.");
+                }
+                b'q' => {
+                    control.stop();
+                    info!("Stopping after the files in progress...");
+                }
+                _ => {}
+            }
+        }
+    });
+    guard
+}
+
+/// Keeps the terminal in a mode where keys are read as soon as they are
+/// pressed and not echoed, until dropped.
+pub(crate) struct KeyGuard {
+    #[cfg(unix)]
+    original: Option<libc::termios>,
+}
+
+/// The terminal mode to restore if the process is interrupted.
+#[cfg(unix)]
+static ORIGINAL_TERMIOS: std::sync::OnceLock<libc::termios> = std::sync::OnceLock::new();
+
+#[cfg(unix)]
+impl KeyGuard {
+    fn enter() -> Self {
+        // SAFETY: `termios` is plain data, filled in by `tcgetattr`.
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: `termios` is a valid pointer for the duration of the call.
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Self { original: None };
+        }
+        let original = termios;
+        // Keep ISIG, so Ctrl-C still interrupts the run
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        _ = ORIGINAL_TERMIOS.set(original);
+        // SAFETY: The handler only calls async-signal-safe functions, and
+        // `termios` is a valid pointer for the duration of the call.
+        unsafe {
+            let handler = restore_and_reraise as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                return Self { original: None };
+            }
+        }
+        Self {
+            original: Some(original),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for KeyGuard {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            // SAFETY: `original` is a valid pointer for the duration of the call.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+        }
+    }
+}
+
+/// Restores the terminal mode before letting `signal` terminate the process.
+#[cfg(unix)]
+extern "C" fn restore_and_reraise(signal: libc::c_int) {
+    // SAFETY: `tcsetattr`, `signal` and `raise` are async-signal-safe, and the
+    // termios was set before this handler was installed.
+    unsafe {
+        if let Some(original) = ORIGINAL_TERMIOS.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Without a portable way to read single key presses, keys are read once
+/// Enter is pressed on other platforms.
+#[cfg(not(unix))]
+impl KeyGuard {
+    fn enter() -> Self {
+        Self {}
+    }
+}
@@ -1,15 +1,126 @@
 #![warn(clippy::cargo)]
 
 use bitflags::bitflags;
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use log::{debug, error};
-use rayon::prelude::*;
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-use std::process::Command;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use walkdir::WalkDir;
+use log::{debug, error, warn};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, FileTimes, Metadata};
+use std::io::IsTerminal;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::dedup::{Duplicate, Job};
+use crate::guard::ResourceMonitor;
+use crate::journal::Journal;
+use crate::par::*;
+use crate::progress::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use crate::run_log::RunLog;
+use crate::semaphore::Semaphore;
+use crate::spans::Span;
+use crate::stage::StagedFile;
+
+mod archive;
+mod attrs;
+mod backup;
+mod bpm;
+mod chapters;
+mod clean;
+mod control;
+mod dedup;
+mod detect;
+mod download;
+mod encoder;
+mod error;
+mod estimate;
+mod event;
+mod exec;
+mod ffmpeg;
+mod guard;
+mod history;
+mod hook;
+mod ignore;
+mod journal;
+mod loudness;
+mod manifest;
+mod marker;
+mod merge;
+mod naming;
+mod options;
+mod origin;
+mod par;
+mod pipe;
+mod plan;
+mod playlist;
+mod preview;
+mod progress;
+mod replaygain;
+mod report;
+mod run_log;
+mod runner;
+mod segment;
+mod semaphore;
+mod sha256;
+mod sidecar;
+mod silence;
+mod spans;
+mod speed;
+mod stage;
+mod stats;
+mod tags;
+mod throttle;
+mod variants;
+mod verify;
+mod wpm;
+
+pub use backup::restore_backups;
+pub use clean::{clean_temp_files, find_temp_files};
+pub use control::RunControl;
+pub use detect::detect_audio_format;
+pub use download::{download_ffmpeg, ffmpeg_dir};
+pub use encoder::EncoderMap;
+pub use error::{Error, Result};
+pub use estimate::{Estimate, estimate};
+pub use event::ProcessEvent;
+pub use ffmpeg::ensure_ffmpeg;
+pub use guard::ResourceGuard;
+pub use history::{HISTORY_NAME, HistoryEntry, read_history, record_history};
+pub use ignore::IGNORE_FILE_NAME;
+pub use loudness::Loudness;
+pub use manifest::{MANIFEST_NAME, verify_manifest, write_manifest};
+pub use merge::merge_planned_files;
+pub use options::{Order, OutputFormat, Preset, ProcessOptions, Quality};
+pub use pipe::process_stream;
+pub use plan::{PlannedFile, SkipReason, plan, sample};
+pub use playlist::{is_playlist, plan_playlist, rewrite_playlist};
+pub use preview::render_previews;
+pub use report::{FileReport, FileStatus, FormatSummary, ProcessReport};
+pub use runner::{CommandRunner, Runner, SystemRunner};
+pub use silence::{SilenceOptions, TrimOptions};
+pub use speed::Speed;
+pub use stats::{FormatStats, TreeStats, tree_stats};
+pub use tags::TagFilter;
+pub use throttle::IoLimit;
+pub use variants::process_speed_variants;
+pub use verify::{VerifiedFile, verify_against_originals, verify_files};
+
+/// Target of the structured records logged when a file is started and
+/// finished, which carry its details as key-value pairs.
+pub const FILE_TARGET: &str = "audio_batch_speedup::file";
+
+/// Number of progress bar units allotted to each file, so that a file's
+/// fractional progress can be reflected in the overall bar.
+const PROGRESS_UNITS_PER_FILE: u64 = 1000;
+
+/// How many files found by a streamed walk may wait for a worker.
+const STREAM_BUFFER: usize = 256;
+
+/// How long to wait before first retrying the files that were still changing
+/// or in use by another program, giving it a chance to finish with them.
+const BUSY_RETRY_DELAY: Duration = Duration::from_secs(2);
 
 bitflags! {
     /// Represents the supported audio formats for processing.
@@ -42,64 +153,75 @@ impl Default for AudioFormat {
     }
 }
 
-/// Detects the audio format of a file based on its magic bytes or file extension.
-///
-/// # Arguments
+impl AudioFormat {
+    /// Formats whose containers can carry embedded cover art that ffmpeg can
+    /// stream-copy.
+    const WITH_COVER_ART: Self = Self::MP3
+        .union(Self::FLAC)
+        .union(Self::AAC)
+        .union(Self::ALAC);
+
+    /// Lossy formats, whose encoders take a bitrate.
+    const LOSSY: Self = Self::OGG
+        .union(Self::MP3)
+        .union(Self::AAC)
+        .union(Self::OPUS)
+        .union(Self::WMA);
+
+    /// Returns the name of a single format, e.g. `MP3`.
+    ///
+    /// # Returns
+    ///
+    /// * `&'static str` - The name in capitals, or `?` if the value is empty.
+    pub fn name(self) -> &'static str {
+        self.iter_names().next().map_or("?", |(name, _)| name)
+    }
+}
+
+/// Parses a comma-separated list of format names (e.g. `ogg,mp3`), in any
+/// case, or `all`.
 ///
-/// * `path` - The path to the audio file.
+/// # Example
 ///
-/// # Returns
+/// ```
+/// use audio_batch_speedup::AudioFormat;
 ///
-/// * `Option<AudioFormat>` - The detected audio format, or `None` if it cannot be determined.
-fn detect_audio_format(path: &Path) -> Option<AudioFormat> {
-    // Try to detect by magic bytes first
-    let mut file = File::open(path).ok()?;
-    let mut buffer = [0; 12]; // Read enough bytes for common headers
-    file.read_exact(&mut buffer).ok()?;
+/// let formats: AudioFormat = "ogg,MP3".parse().unwrap();
+/// assert_eq!(formats, AudioFormat::OGG | AudioFormat::MP3);
+/// assert_eq!(formats.to_string(), "ogg,mp3");
+/// assert_eq!("all".parse::<AudioFormat>().unwrap(), AudioFormat::ALL);
+/// assert!("ogg,mp4".parse::<AudioFormat>().is_err());
+/// ```
+impl std::str::FromStr for AudioFormat {
+    type Err = Error;
 
-    // OGG (OggS)
-    if &buffer[0..4] == b"OggS" {
-        return Some(AudioFormat::OGG);
-    }
-    // MP3 (ID3 tag or starts with 0xFF FB/FA)
-    if &buffer[0..3] == b"ID3" || (buffer[0] == 0xFF && (buffer[1] & 0xF6) == 0xF2) {
-        return Some(AudioFormat::MP3);
-    }
-    // WAV (RIFF header with WAVE)
-    if &buffer[0..4] == b"RIFF" && &buffer[8..12] == b"WAVE" {
-        return Some(AudioFormat::WAV);
-    }
-    // FLAC (fLaC)
-    if &buffer[0..4] == b"fLaC" {
-        return Some(AudioFormat::FLAC);
-    }
-    // AAC (often in MP4/M4A containers, which start with 'ftyp' or 'moov')
-    // This is harder to detect purely by magic bytes without parsing the container.
-    // We'll rely more on extension for AAC/M4A.
-    // OPUS (often in Ogg containers, so OggS will catch it, or WebM)
-    // ALAC (often in MP4/M4A containers)
-    // WMA (ASF header)
-    if buffer[0..4] == [0x30, 0x26, 0xB2, 0x75] {
-        // GUID for ASF header
-        return Some(AudioFormat::WMA);
+    fn from_str(s: &str) -> Result<Self> {
+        let mut formats = Self::empty();
+        for name in s.split(',').map(str::trim) {
+            formats |= Self::from_name(&name.to_uppercase()).ok_or_else(|| {
+                Error::InvalidOptions(format!(
+                    "unsupported format: {}, expected one of ogg, mp3, wav, flac, aac, opus, alac, wma or all",
+                    name
+                ))
+            })?;
+        }
+        Ok(formats)
     }
+}
 
-    // Fallback to file extension
-    if let Some(extension) = path.extension().and_then(|s| s.to_str()) {
-        match extension.to_lowercase().as_str() {
-            "ogg" => return Some(AudioFormat::OGG),
-            "mp3" => return Some(AudioFormat::MP3),
-            "wav" => return Some(AudioFormat::WAV),
-            "flac" => return Some(AudioFormat::FLAC),
-            "m4a" | "aac" => return Some(AudioFormat::AAC),
-            "opus" => return Some(AudioFormat::OPUS),
-            "alac" => return Some(AudioFormat::ALAC),
-            "wma" => return Some(AudioFormat::WMA),
-            _ => {}
+/// Writes the lowercase names of the formats separated by commas, or `all`,
+/// as parsed back by [`FromStr`](std::str::FromStr).
+impl std::fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if *self == Self::ALL {
+            return f.write_str("all");
         }
+        let names: Vec<String> = self
+            .iter_names()
+            .map(|(name, _)| name.to_lowercase())
+            .collect();
+        f.write_str(&names.join(","))
     }
-
-    None
 }
 
 /// Process all audio files in the specified folder recursively with the given speed multiplier.
@@ -112,7 +234,8 @@ fn detect_audio_format(path: &Path) -> Option<AudioFormat> {
 ///
 /// # Returns
 ///
-/// * `Result<()>` - Ok(()) if successful, or an error if processing fails
+/// * `Result<ProcessReport>` - A report of every file encountered and aggregate statistics, or
+///   an error if processing fails, e.g. [`Error::FfmpegNotFound`] if ffmpeg is missing
 ///
 /// # Example
 ///
@@ -123,156 +246,1650 @@ fn detect_audio_format(path: &Path) -> Option<AudioFormat> {
 /// let folder = Path::new("path/to/audio/files");
 /// let speed = 1.5;
 /// let formats = AudioFormat::OGG | AudioFormat::MP3;
-/// process_audio_files(folder, speed, formats).unwrap();
+/// let report = process_audio_files(folder, speed, formats).unwrap();
+/// println!("Saved {:?} of listening time", report.time_saved());
 /// ```
 pub fn process_audio_files(
     folder: impl AsRef<Path>,
     speed: f32,
     formats: AudioFormat,
-) -> std::io::Result<()> {
-    let folder = folder.as_ref();
+) -> Result<ProcessReport> {
+    process_audio_files_with_options(folder, &ProcessOptions::new(speed, formats))
+}
+
+/// Process all audio files in the specified folder recursively, with full
+/// control over processing through [`ProcessOptions`].
+///
+/// # Arguments
+///
+/// * `folder` - Path to the folder containing audio files
+/// * `options` - Options controlling which files are processed and how.
+///
+/// # Returns
+///
+/// * `Result<ProcessReport>` - A report of every file encountered and aggregate statistics, or
+///   an error if processing fails, e.g. [`Error::FfmpegNotFound`] if ffmpeg is missing
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{process_audio_files_with_options, AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions {
+///     nice: Some(10),
+///     ..ProcessOptions::new(1.5, AudioFormat::ALL)
+/// };
+/// process_audio_files_with_options("path/to/audio/files", &options).unwrap();
+/// ```
+pub fn process_audio_files_with_options(
+    folder: impl AsRef<Path>,
+    options: &ProcessOptions,
+) -> Result<ProcessReport> {
+    process_planned_files(plan(folder, options)?, options)
+}
+
+/// Like [`process_audio_files_with_options`], but starts processing files
+/// while the folder is still being walked instead of planning every file
+/// first, so very large trees start at once and memory stays flat.
+///
+/// Files found by the walk are buffered up to a fixed number ahead of the
+/// workers, and planned by the workers themselves. The progress bar counts
+/// the files found so far (shown as e.g. `3/120+` while walking) and becomes
+/// exact once the walk is over. As no plan exists, [`ProcessOptions::order`]
+/// and [`ProcessOptions::dedup`] have no effect, files are started in name
+/// order, hardlinks are not grouped, and archives are
+/// processed after every other file.
+///
+/// # Arguments
+///
+/// * `folder` - Path to the folder containing audio files
+/// * `options` - Options controlling which files are processed and how.
+/// * `on_event` - Called as each file is started, makes progress, and finishes or fails, as with
+///   [`process_planned_files_with_events`].
+///
+/// # Returns
+///
+/// * `Result<ProcessReport>` - A report of every file encountered and aggregate statistics, or
+///   an error if processing fails, e.g. [`Error::FfmpegNotFound`] if ffmpeg is missing
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{process_audio_files_streaming, AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// let report = process_audio_files_streaming("path/to/a/huge/library", &options, |_| {}).unwrap();
+/// println!("Processed {} files", report.processed_count());
+/// ```
+pub fn process_audio_files_streaming(
+    folder: impl AsRef<Path>,
+    options: &ProcessOptions,
+    on_event: impl Fn(ProcessEvent) + Sync,
+) -> Result<ProcessReport> {
+    ffmpeg::check_ffmpeg(&*options.runner)?;
+    let entries = plan::walk(folder.as_ref(), options)?;
+    let completed = plan::completed_files(options)?;
+    let total = RunTotal::new(0, true);
+    let (tx, rx) = std::sync::mpsc::sync_channel(STREAM_BUFFER);
+    let (mut file_reports, skipped) = std::thread::scope(|s| {
+        s.spawn(|| {
+            for entry in entries {
+                total.files.fetch_add(1, Ordering::AcqRel);
+                // The workers are gone if the run failed
+                if tx.send(entry).is_err() {
+                    break;
+                }
+            }
+            total.walking.store(false, Ordering::Release);
+            drop(tx);
+        });
+        run_jobs(
+            rx.into_iter()
+                .map(|(path, relative_path, metadata)| Work::Entry(path, relative_path, metadata)),
+            Vec::new(),
+            &total,
+            &completed,
+            options,
+            on_event,
+        )
+    })?;
+    file_reports.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(finish_report(file_reports, skipped))
+}
 
-    // Collect all files that need to be processed
-    let files: Vec<_> = WalkDir::new(folder)
+/// Process a single audio file in place, the same way files are processed in
+/// a batch run. Backups made with [`ProcessOptions::backup_dir`] are placed
+/// directly in the backup folder.
+///
+/// # Arguments
+///
+/// * `path` - Path to the audio file
+/// * `options` - Options controlling whether the file is processed and how.
+///
+/// # Returns
+///
+/// * `Result<FileReport>` - A report telling whether the file was processed, skipped or failed,
+///   or an error if `path` is not a readable file
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{process_single_file, AudioFormat, FileStatus, ProcessOptions};
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// let report = process_single_file("path/to/audio.ogg", &options).unwrap();
+/// assert_eq!(report.status, FileStatus::Processed);
+/// ```
+pub fn process_single_file(path: impl AsRef<Path>, options: &ProcessOptions) -> Result<FileReport> {
+    let path = path.as_ref();
+    let metadata = std::fs::metadata(path)?;
+    if !metadata.is_file() {
+        return Err(Error::NotAFile(path.to_path_buf()));
+    }
+    let relative_path = path.file_name().map(PathBuf::from).unwrap_or_default();
+    let file = plan::plan_file(path.to_path_buf(), relative_path, &metadata, options);
+    let report = process_planned_files(vec![file], options)?;
+    Ok(report
+        .files
         .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.path().is_file()) // Only count files for the progress bar
+        .next()
+        .expect("Internal Error: A planned file is missing from the report"))
+}
+
+/// Processes the files of a plan previously made with [`plan`]. Files that
+/// the plan marks as skipped are reported as such without being touched.
+///
+/// # Arguments
+///
+/// * `files` - The planned files.
+/// * `options` - Options controlling how files are processed. These should be
+///   the options the plan was made with.
+///
+/// # Returns
+///
+/// * `Result<ProcessReport>` - A report of every planned file and aggregate statistics, or an
+///   error if processing fails, e.g. [`Error::FfmpegNotFound`] if ffmpeg is missing
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{plan, process_planned_files, AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// let files = plan("path/to/audio/files", &options).unwrap();
+/// let count = files.iter().filter(|f| f.will_process()).count();
+/// println!("About to process {} files", count);
+/// process_planned_files(files, &options).unwrap();
+/// ```
+pub fn process_planned_files(
+    files: Vec<PlannedFile>,
+    options: &ProcessOptions,
+) -> Result<ProcessReport> {
+    process_planned_files_with_events(files, options, |_| {})
+}
+
+/// Like [`process_planned_files`], but also calls `on_event` as each file is
+/// started, makes progress, and finishes or fails, so applications such as
+/// GUIs can display live per-file status.
+///
+/// `on_event` is called from the worker threads, so it must be `Sync`. To
+/// receive events on another thread, send them over a channel.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::mpsc;
+/// use audio_batch_speedup::{
+///     plan, process_planned_files_with_events, AudioFormat, ProcessEvent, ProcessOptions,
+/// };
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// let files = plan("path/to/audio/files", &options).unwrap();
+/// let (tx, rx) = mpsc::channel();
+/// std::thread::spawn(move || {
+///     process_planned_files_with_events(files, &options, |event| _ = tx.send(event))
+/// });
+/// for event in rx {
+///     if let ProcessEvent::FileFailed { path, stderr } = event {
+///         eprintln!("{} failed: {}", path.display(), stderr);
+///     }
+/// }
+/// ```
+pub fn process_planned_files_with_events(
+    files: Vec<PlannedFile>,
+    options: &ProcessOptions,
+    on_event: impl Fn(ProcessEvent) + Sync,
+) -> Result<ProcessReport> {
+    ffmpeg::check_ffmpeg(&*options.runner)?;
+    let (files, skipped): (Vec<_>, Vec<_>) = files.into_iter().partition(|f| f.will_process());
+    for file in &skipped {
+        log_skipped(file);
+    }
+    let total_files = files.len();
+    let (archives, files): (Vec<_>, Vec<_>) = files.into_iter().partition(|f| f.archive);
+    let mut jobs: Vec<Job> = if options.segment.is_some() {
+        files.into_iter().map(Job::from).collect()
+    } else if options.dedup {
+        dedup::group_duplicates(dedup::group_hardlinks(files))
+    } else {
+        dedup::group_hardlinks(files)
+    };
+    order_jobs(&mut jobs, options);
+    // The report lists the files in the order they were handed out
+    let dispatch_order: HashMap<PathBuf, usize> = jobs
+        .iter()
+        .flat_map(|job| std::iter::once(&job.file).chain(job.duplicates.iter().map(|d| &d.file)))
+        .enumerate()
+        .map(|(i, file)| (file.path.clone(), i))
         .collect();
+    let total = RunTotal::new(total_files, false);
+    let (mut file_reports, _) = run_jobs(
+        jobs.into_iter().map(Work::Job),
+        archives,
+        &total,
+        &HashSet::new(),
+        options,
+        on_event,
+    )?;
+    file_reports.sort_by_key(|report| {
+        dispatch_order
+            .get(&report.path)
+            .copied()
+            .unwrap_or(usize::MAX)
+    });
+    Ok(finish_report(file_reports, skipped))
+}
 
-    let process_pb = ProgressBar::new(files.len() as u64);
+/// Runs `work` on the worker threads, then retries the busy files and
+/// processes the `archives` (and those found in `work`) one at a time.
+/// Entries of `work` are planned as they come, skipping those `completed`.
+///
+/// # Returns
+///
+/// * `Result<(Vec<FileReport>, Vec<PlannedFile>)>` - The reports of the files handed to ffmpeg
+///   (or skipped once started), in no particular order, and the entries of `work` that were
+///   planned as skipped.
+fn run_jobs<F: Fn(ProcessEvent) + Sync>(
+    work: impl Iterator<Item = Work> + Send,
+    archives: Vec<PlannedFile>,
+    total: &RunTotal,
+    completed: &HashSet<PathBuf>,
+    options: &ProcessOptions,
+    on_event: F,
+) -> Result<(Vec<FileReport>, Vec<PlannedFile>)> {
+    if let Some(temp_dir) = options.local_temp_dir() {
+        std::fs::create_dir_all(temp_dir)?;
+    }
+    if let Some(template) = &options.name_template {
+        let render =
+            |part| naming::render_name(template, Path::new("file.ext"), options.speed, part);
+        let result = match options.segment {
+            Some(_) => render(Some(1)).and_then(|first| {
+                if first == render(Some(2))? {
+                    Err("{part} is required with segments".to_string())
+                } else {
+                    Ok(first)
+                }
+            }),
+            None => render(None),
+        };
+        result.map_err(|e| Error::InvalidOptions(format!("invalid name template: {}", e)))?;
+    }
+
+    let batch = Span::batch(options, total.walking.load(Ordering::Acquire));
+    let _entered = batch.enter();
+
+    // The overall bar stays at the bottom, with one line per active file
+    // above it.
+    let multi = if options.progress_bar && std::io::stdout().is_terminal() {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    };
+    let process_pb = multi.add(ProgressBar::new(
+        total.files() as u64 * PROGRESS_UNITS_PER_FILE,
+    ));
+    let file_style = ProgressStyle::default_bar()
+        .template("  [{bar:20.cyan/blue}] {percent:>3}% {wide_msg}")
+        .expect("Internal Error: Failed to set progress bar style")
+        .progress_chars("#>-");
     process_pb.set_style(
         ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent}% ({eta}) {msg}")
             .expect("Internal Error: Failed to set progress bar style")
             .progress_chars("#>-"),
     );
+    // The total grows while a streamed folder is walked
+    let show_done = |done: usize| {
+        process_pb.set_length(total.files() as u64 * PROGRESS_UNITS_PER_FILE);
+        process_pb.set_message(total.message(done));
+    };
+    show_done(0);
 
-    let error_count = AtomicUsize::new(0);
-    let skipped_count = AtomicUsize::new(0);
+    let tempo_filter = tempo_filter(options);
+    let encoders = options.encoders.resolve(&*options.runner)?;
 
-    // Process all files in parallel
-    files
-        .into_par_iter()
-        .progress_with(process_pb.clone())
-        .for_each(|entry| {
-            let path = entry.path();
-            if !path.is_file() {
-                return;
+    let done_count = AtomicUsize::new(0);
+    let context = RunContext {
+        options,
+        tempo_filter,
+        encoders,
+        resources: options.resource_guard.clone().map(ResourceMonitor::new),
+        ffmpeg_limit: options.max_ffmpeg.map(|n| Semaphore::new(n.get())),
+        pb: process_pb.clone(),
+        multi,
+        on_event,
+        run_log: options
+            .log_file
+            .as_deref()
+            .map(RunLog::create)
+            .transpose()?,
+    };
+
+    let journal = options.journal.as_deref().map(Journal::open).transpose()?;
+
+    let aborted = AtomicBool::new(false);
+
+    // Files still changing or locked by another program, to retry once the
+    // others are done
+    let busy_jobs = Mutex::new(Vec::new());
+    let run_job = |job: Job, defer_busy: bool| {
+        // Held back here while paused, before the file shows up as started
+        let stopped = !options.control.wait_while_paused()
+            || context
+                .resources
+                .as_ref()
+                .is_some_and(|resources| !resources.wait_for_resources(&options.control));
+        let file = &job.file;
+        let span = batch.file(file);
+        let _entered = span.enter();
+        let started = Instant::now();
+        debug!(
+            target: FILE_TARGET,
+            path:% = file.path.display(),
+            format = file.format.map(AudioFormat::name);
+            "Started {}",
+            file.path.display()
+        );
+        let mut reported_units = 0;
+        let mut file_report = FileReport::from_planned(file);
+        let mut stderr = String::new();
+        let mut duplicate_reports = Vec::new();
+        let file_pb = context
+            .multi
+            .insert_from_back(1, ProgressBar::new(PROGRESS_UNITS_PER_FILE));
+        file_pb.set_style(file_style.clone());
+        file_pb.set_message(file.relative_path.display().to_string());
+        (context.on_event)(ProcessEvent::FileStarted {
+            path: file.path.clone(),
+        });
+        // After a failure with `fail_fast` or a stop, files not started
+        // yet are skipped
+        file_report.status = if stopped || aborted.load(Ordering::Acquire) {
+            FileStatus::Skipped
+        } else if let Some(pre_hook) = &options.pre_hook
+            && !hook::run_hook(pre_hook, &[file.path.as_os_str()])
+        {
+            FileStatus::Skipped
+        } else {
+            process_file(
+                &context,
+                &job,
+                &file_pb,
+                &mut reported_units,
+                &mut file_report,
+                &mut stderr,
+                &mut duplicate_reports,
+            )
+        };
+        if file_report.status == FileStatus::Failed {
+            if !stderr.trim().is_empty() {
+                file_report.stderr = Some(stderr.clone());
+            }
+            if options.fail_fast && !aborted.swap(true, Ordering::AcqRel) {
+                error!("Stopping after the failure of {}", file.path.display());
+            }
+        }
+        // Duplicates share the fate of the file they copy from
+        if duplicate_reports.is_empty() {
+            duplicate_reports = job
+                .duplicates
+                .iter()
+                .map(|duplicate| FileReport {
+                    status: file_report.status,
+                    stderr: file_report.stderr.clone(),
+                    ..FileReport::from_planned(&duplicate.file)
+                })
+                .collect();
+        }
+        if let Some(journal) = &journal {
+            let completed = std::iter::once((file, &file_report))
+                .chain(
+                    job.duplicates
+                        .iter()
+                        .map(|duplicate| &duplicate.file)
+                        .zip(&duplicate_reports),
+                )
+                .filter(|(_, report)| report.status == FileStatus::Processed);
+            for (file, _) in completed {
+                journal.record(&file.relative_path);
             }
+        }
+        for report in std::iter::once(&file_report).chain(&duplicate_reports) {
+            record_file(report, started.elapsed());
+        }
+        span.record_file(&file_report, started.elapsed());
+        if let Some(post_hook) = &options.post_hook {
+            for report in std::iter::once(&file_report).chain(&duplicate_reports) {
+                let status = report.status.to_string();
+                let output_path = report.output_path.as_deref().unwrap_or(Path::new(""));
+                hook::run_hook(
+                    post_hook,
+                    &[
+                        report.path.as_os_str(),
+                        status.as_ref(),
+                        output_path.as_os_str(),
+                    ],
+                );
+            }
+        }
+        for duplicate_report in &duplicate_reports {
+            (context.on_event)(ProcessEvent::FileFinished {
+                report: duplicate_report.clone(),
+            });
+        }
+        (context.on_event)(match file_report.status {
+            FileStatus::Failed => ProcessEvent::FileFailed {
+                path: file.path.clone(),
+                stderr,
+            },
+            _ => ProcessEvent::FileFinished {
+                report: file_report.clone(),
+            },
+        });
+        file_pb.finish_and_clear();
+        context.multi.remove(&file_pb);
+        process_pb.inc(
+            PROGRESS_UNITS_PER_FILE.saturating_sub(reported_units)
+                + PROGRESS_UNITS_PER_FILE * job.duplicates.len() as u64,
+        );
+        let done = done_count.fetch_add(1 + job.duplicates.len(), Ordering::AcqRel)
+            + 1
+            + job.duplicates.len();
+        show_done(done);
+        let reports: Vec<_> = std::iter::once(file_report)
+            .chain(duplicate_reports)
+            .collect();
+        if defer_busy && (reports[0].locked || reports[0].changing) {
+            let mut job = job;
+            // Later attempts look for changes since this one
+            if let Ok(metadata) = std::fs::metadata(&job.file.path) {
+                job.file.size = metadata.len();
+                job.file.modified = metadata.modified().ok();
+            }
+            busy_jobs
+                .lock()
+                .expect("Internal Error: Mutex poisoned")
+                .push(job);
+        }
+        reports
+    };
 
-            let detected_format = detect_audio_format(path);
+    // Streamed entries not processed now are done as far as the progress is
+    // concerned, except archives, which come last
+    let streamed_skips = Mutex::new(Vec::new());
+    let streamed_archives = Mutex::new(Vec::new());
+    let run_work = |work: Work| {
+        let file = match work {
+            Work::Job(job) => return run_job(job, options.busy_retries > 0),
+            Work::Entry(path, relative_path, metadata) => {
+                plan::plan_entry(path, relative_path, &metadata, completed, options)
+            }
+        };
+        if file.will_process() && !file.archive {
+            return run_job(Job::from(file), options.busy_retries > 0);
+        }
+        if file.will_process() {
+            streamed_archives
+                .lock()
+                .expect("Internal Error: Mutex poisoned")
+                .push(file);
+        } else {
+            log_skipped(&file);
+            streamed_skips
+                .lock()
+                .expect("Internal Error: Mutex poisoned")
+                .push(file);
+            process_pb.inc(PROGRESS_UNITS_PER_FILE);
+            show_done(done_count.fetch_add(1, Ordering::AcqRel) + 1);
+        }
+        Vec::new()
+    };
 
-            let Some(detected_format) = detected_format else {
-                debug!("Skipping file (format not detected): {}", path.display());
-                skipped_count.fetch_add(1, Ordering::AcqRel);
-                return;
-            };
+    // Process all files in parallel
+    // Bridging hands out jobs in order, unlike splitting the vector
+    let mut file_reports: Vec<FileReport> = work.par_bridge().flat_map_iter(run_work).collect();
 
-            if !formats.contains(detected_format) {
-                debug!("Skipping file (format not selected): {}", path.display());
-                skipped_count.fetch_add(1, Ordering::AcqRel);
-                return;
+    let mut delay = BUSY_RETRY_DELAY;
+    for attempt in 1..=options.busy_retries {
+        let busy_jobs =
+            std::mem::take(&mut *busy_jobs.lock().expect("Internal Error: Mutex poisoned"));
+        if busy_jobs.is_empty() || options.control.is_stopped() || aborted.load(Ordering::Acquire) {
+            break;
+        }
+        log::info!(
+            "Retrying {} files that were still changing or in use by another program in {}s",
+            busy_jobs.len(),
+            delay.as_secs()
+        );
+        std::thread::sleep(delay);
+        delay *= 2;
+        let retried: HashSet<PathBuf> = busy_jobs
+            .iter()
+            .flat_map(|job| {
+                std::iter::once(&job.file).chain(job.duplicates.iter().map(|d| &d.file))
+            })
+            .map(|file| file.path.clone())
+            .collect();
+        // Rewind the progress made by the earlier attempts
+        let retried_files = retried.len();
+        process_pb.dec(retried_files as u64 * PROGRESS_UNITS_PER_FILE);
+        done_count.fetch_sub(retried_files, Ordering::AcqRel);
+        file_reports.retain(|report| !retried.contains(&report.path));
+        file_reports.extend(
+            busy_jobs
+                .into_iter()
+                .par_bridge()
+                .flat_map_iter(|job| run_job(job, attempt < options.busy_retries))
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    // Archives come last, one at a time, as their files are processed in
+    // parallel already
+    let streamed_archives = streamed_archives
+        .into_inner()
+        .expect("Internal Error: Mutex poisoned");
+    for file in archives.into_iter().chain(streamed_archives) {
+        if options.control.is_stopped() || aborted.load(Ordering::Acquire) {
+            file_reports.push(FileReport {
+                status: FileStatus::Skipped,
+                ..FileReport::from_planned(&file)
+            });
+            continue;
+        }
+        process_pb.set_message(format!(
+            "{} {}",
+            total.message(done_count.load(Ordering::Acquire)),
+            file.relative_path.display()
+        ));
+        let reports = archive::process_archive(&file, options);
+        let failed = reports.iter().any(|r| r.status == FileStatus::Failed);
+        if let Some(journal) = &journal
+            && !failed
+            && reports.iter().any(|r| r.status == FileStatus::Processed)
+        {
+            journal.record(&file.relative_path);
+        }
+        if failed && options.fail_fast && !aborted.swap(true, Ordering::AcqRel) {
+            error!("Stopping after the failure of {}", file.path.display());
+        }
+        for report in &reports {
+            (context.on_event)(ProcessEvent::FileStarted {
+                path: report.path.clone(),
+            });
+            (context.on_event)(match report.status {
+                FileStatus::Failed => ProcessEvent::FileFailed {
+                    path: report.path.clone(),
+                    stderr: report.stderr.clone().unwrap_or_default(),
+                },
+                _ => ProcessEvent::FileFinished {
+                    report: report.clone(),
+                },
+            });
+        }
+        file_reports.extend(reports);
+        process_pb.inc(PROGRESS_UNITS_PER_FILE);
+        show_done(done_count.fetch_add(1, Ordering::AcqRel) + 1);
+    }
+
+    process_pb.finish_with_message("Processing complete!");
+    batch.record_batch(&file_reports);
+    let streamed_skips = streamed_skips
+        .into_inner()
+        .expect("Internal Error: Mutex poisoned");
+    Ok((file_reports, streamed_skips))
+}
+
+/// Builds the report of a run from the reports of the processed files and
+/// the skipped files, logging the number of errors and skipped files.
+fn finish_report(file_reports: Vec<FileReport>, skipped: Vec<PlannedFile>) -> ProcessReport {
+    let report = ProcessReport {
+        files: file_reports
+            .into_iter()
+            .chain(skipped.iter().map(FileReport::from_planned))
+            .collect(),
+    };
+    let errors = report.failed_count();
+    let skipped = report.skipped_count();
+
+    if errors > 0 {
+        log::error!("Finished with {} errors.", errors);
+    }
+    if skipped > 0 {
+        log::info!("Skipped {} files.", skipped);
+    }
+    report
+}
+
+/// Logs why `file` is skipped, if it is.
+fn log_skipped(file: &PlannedFile) {
+    if let Some(reason) = file.skip_reason {
+        debug!("Skipping file ({}): {}", reason, file.path.display());
+    }
+}
+
+/// A unit of work handed to the workers of a run.
+enum Work {
+    /// A planned job.
+    Job(Job),
+    /// A file found while streaming a folder, still to be planned: its path,
+    /// its path relative to the folder and its metadata.
+    Entry(PathBuf, PathBuf, Metadata),
+}
+
+/// The number of files of a run, which grows while a streamed folder is
+/// still being walked.
+struct RunTotal {
+    files: AtomicUsize,
+    walking: AtomicBool,
+}
+
+impl RunTotal {
+    fn new(files: usize, walking: bool) -> Self {
+        Self {
+            files: AtomicUsize::new(files),
+            walking: AtomicBool::new(walking),
+        }
+    }
+
+    fn files(&self) -> usize {
+        self.files.load(Ordering::Acquire)
+    }
+
+    /// Returns the progress message for `done` files, e.g. `3/10`, or
+    /// `3/10+` while more files may be found.
+    fn message(&self, done: usize) -> String {
+        let more = if self.walking.load(Ordering::Acquire) {
+            "+"
+        } else {
+            ""
+        };
+        format!("{}/{}{}", done, self.files(), more)
+    }
+}
+
+/// Sorts `jobs` in the order of [`ProcessOptions::order`], breaking ties by
+/// path.
+fn order_jobs(jobs: &mut [Job], options: &ProcessOptions) {
+    jobs.sort_by(|a, b| a.file.path.cmp(&b.file.path));
+    match options.order {
+        Order::Size => jobs.sort_by_key(|job| std::cmp::Reverse(job.file.size)),
+        Order::Name => {}
+        Order::Duration => {
+            let durations: HashMap<PathBuf, Duration> = jobs
+                .par_iter()
+                .filter_map(|job| {
+                    let duration = ffmpeg::probe_duration(&*options.runner, &job.file.path)?;
+                    Some((job.file.path.clone(), duration))
+                })
+                .collect();
+            jobs.sort_by_key(|job| std::cmp::Reverse(durations.get(&job.file.path)));
+        }
+        Order::Random => plan::shuffle(jobs, |job| &job.file.path),
+    }
+}
+
+/// State shared by the workers of a batch run.
+struct RunContext<'a, F> {
+    options: &'a ProcessOptions,
+    /// The audio filter used to change the tempo.
+    tempo_filter: &'static str,
+    /// The encoders of the output formats, completed with the defaults.
+    encoders: EncoderMap,
+    /// Holds back new files on low battery or high temperature, if set.
+    resources: Option<ResourceMonitor>,
+    /// Limits the number of concurrent ffmpeg processes, if set.
+    ffmpeg_limit: Option<Semaphore>,
+    /// The overall progress bar.
+    pb: ProgressBar,
+    /// Holds the overall bar and the per-file bars of active workers.
+    multi: MultiProgress,
+    on_event: F,
+    run_log: Option<RunLog>,
+}
+
+/// Speeds up the file of a job, advancing its own progress bar `file_pb` and
+/// the overall one as ffmpeg reports progress. On success, the output is also
+/// copied over the job's duplicates.
+///
+/// `reported_units` is updated with the number of progress units already
+/// added to the bar for this file, so the caller can complete the remainder.
+/// Durations and sizes are recorded into `report` as they become known, and
+/// ffmpeg's standard error into `stderr`. If the output was copied over the
+/// duplicates, their reports are pushed to `duplicate_reports`.
+fn process_file(
+    context: &RunContext<impl Fn(ProcessEvent) + Sync>,
+    job: &Job,
+    file_pb: &ProgressBar,
+    reported_units: &mut u64,
+    report: &mut FileReport,
+    stderr: &mut String,
+    duplicate_reports: &mut Vec<FileReport>,
+) -> FileStatus {
+    let options = context.options;
+    let file = &job.file;
+    let path = file.path.as_path();
+    let is_video = file.video;
+    let original_times = std::fs::metadata(path)
+        .ok()
+        .filter(|_| options.preserve_times)
+        .map(|m| original_file_times(&m));
+
+    // Opening the original for writing fails early if another program has it
+    // open, rather than once it has been encoded
+    if options.replaces_originals()
+        && let Err(e) = File::options().write(true).open(path)
+        && is_locked(&e)
+    {
+        error!("{} is in use by another program: {}", path.display(), e);
+        report.locked = true;
+        return FileStatus::Failed;
+    }
+    if has_changed(file) {
+        debug!("Deferring {}, which is still changing", path.display());
+        report.changing = true;
+        return FileStatus::Skipped;
+    }
+    // ffmpeg and ffprobe read the local copy of a staged file
+    let staged = if options.stage_local {
+        let Some(staged) = temp_output_path(path, options) else {
+            error!("Failed to get file name for {}", path.display());
+            return FileStatus::Failed;
+        };
+        match StagedFile::copy(path, staged, options.io_limit.as_ref()) {
+            Ok(staged) => Some(staged),
+            Err(e) => {
+                error!("Error copying {} to a local folder: {}", path.display(), e);
+                return FileStatus::Failed;
             }
+        }
+    } else {
+        None
+    };
+    let source = staged.as_ref().map_or(path, StagedFile::path);
 
-            let file_name = match path.file_name().and_then(|s| s.to_str()) {
-                Some(name) => name,
-                None => {
-                    error!("Failed to get file name for {}", path.display());
-                    error_count.fetch_add(1, Ordering::AcqRel);
-                    return;
-                }
-            };
+    report.input_duration = ffmpeg::probe_duration(&*options.runner, source);
+    if options.analyze_loudness {
+        report.input_loudness = analyze_loudness(&*options.runner, source);
+    }
+    let rate = match (options.target_wpm, options.target_bpm) {
+        (Some(_), _) => match wpm::detect_wpm(&*options.runner, source) {
+            Ok(wpm) => {
+                debug!("Estimated speech rate of {}: {:?} WPM", path.display(), wpm);
+                wpm
+            }
+            Err(e) => {
+                error!("Error estimating speech rate of {}: {}", path.display(), e);
+                None
+            }
+        },
+        (None, Some(_)) => match bpm::detect_bpm(&*options.runner, source) {
+            Ok(bpm) => {
+                debug!("Detected tempo of {}: {:?} BPM", path.display(), bpm);
+                bpm
+            }
+            Err(e) => {
+                error!("Error detecting tempo of {}: {}", path.display(), e);
+                None
+            }
+        },
+        (None, None) => None,
+    };
+    let speed = match options.speed_for(report.input_duration, rate) {
+        Ok(Some(speed)) => speed,
+        Ok(None) => {
+            debug!("Skipping file (already short enough): {}", path.display());
+            return FileStatus::Skipped;
+        }
+        Err(e) => {
+            error!("Failed to compute speed for {}: {}", path.display(), e);
+            if let Some(run_log) = &context.run_log {
+                run_log.record_error(path, &e);
+            }
+            return FileStatus::Failed;
+        }
+    };
+    report.speed = Some(speed);
 
-            let output_file = path.with_file_name(format!("temp_{}", file_name));
+    // With segments, `final_path` is the first part and `output_file` the
+    // pattern of the temporary parts
+    let part = options.segment.map(|_| 1);
+    let Some((final_path, mut output_file)) = prepare_output(file, options, speed, part) else {
+        return FileStatus::Failed;
+    };
+    if options.segment.is_some() {
+        output_file = segment::temp_pattern(&final_path, options.local_temp_dir().as_deref());
+    }
+    let remove_temp_output = |output_file: &Path| match options.segment {
+        Some(_) => segment::remove_temp_parts(output_file),
+        None => remove_temp_file(output_file),
+    };
 
-            let input_path_str = match path.to_str() {
-                Some(s) => s,
-                None => {
-                    error!("Failed to convert input path to string: {}", path.display());
-                    error_count.fetch_add(1, Ordering::AcqRel);
-                    return;
+    // Variable tempo cannot be applied to video, whose frames are retimed
+    // uniformly.
+    let (silence_speed, silences) = match &options.silence {
+        Some(silence_options) if !is_video => {
+            match silence::detect_silence(
+                &*options.runner,
+                source,
+                silence_options.threshold_db,
+                silence_options.min_duration,
+            ) {
+                Ok(silences) => (silence_options.speed, silences),
+                Err(e) => {
+                    error!("Error detecting silence in {}: {}", path.display(), e);
+                    if let Some(run_log) = &context.run_log {
+                        run_log.record_error(path, &format!("silence detection failed: {}", e));
+                    }
+                    return FileStatus::Failed;
                 }
-            };
-
-            let output_file_str = match output_file.to_str() {
-                Some(s) => s,
-                None => {
-                    error!(
-                        "Failed to convert output path to string: {}",
-                        output_file.display()
-                    );
-                    error_count.fetch_add(1, Ordering::AcqRel);
-                    return;
+            }
+        }
+        _ => (speed, Vec::new()),
+    };
+    // Trimming only the audio would put video out of sync
+    let trim = match &options.trim_edges {
+        Some(trim_options) if !is_video => {
+            // Silences shorter than the padding would be kept whole anyway
+            let min_duration = trim_options.padding.max(Duration::from_millis(10));
+            match silence::detect_silence(
+                &*options.runner,
+                source,
+                trim_options.threshold_db,
+                min_duration,
+            ) {
+                Ok(edges) => silence::edge_trim(
+                    &edges,
+                    report.input_duration.map(|d| d.as_secs_f64()),
+                    trim_options.padding,
+                ),
+                Err(e) => {
+                    error!("Error detecting silence in {}: {}", path.display(), e);
+                    if let Some(run_log) = &context.run_log {
+                        run_log.record_error(path, &format!("silence detection failed: {}", e));
+                    }
+                    return FileStatus::Failed;
                 }
+            }
+        }
+        _ => None,
+    };
+    let filter =
+        silence::variable_tempo_filter(context.tempo_filter, speed, silence_speed, &silences, trim);
+    let map_time = |t: f64| silence::map_time(t, speed, silence_speed, &silences, trim);
+    let expected_duration = report
+        .input_duration
+        .map(|d| Duration::from_secs_f64(map_time(d.as_secs_f64())));
+
+    // Segments get no chapters, as each part would need its own
+    let chapters_file = if options.rescale_chapters && options.segment.is_none() {
+        chapters::rescaled_chapters(source, options, &map_time).unwrap_or_else(|e| {
+            warn!("Error reading chapters of {}: {}", path.display(), e);
+            None
+        })
+    } else {
+        None
+    };
+
+    let mut command = exec::command("ffmpeg");
+    command.arg("-i").arg(source);
+    if let Some(chapters_file) = &chapters_file {
+        command
+            .arg("-i")
+            .arg(chapters_file)
+            .args(["-map_chapters", "1"]);
+    }
+    command.args(["-filter:a", &filter]);
+    if is_video {
+        // Retime the video frames to match the audio; attached pictures are
+        // excluded from the mapping since they cannot be filtered.
+        command.args([
+            "-filter:v",
+            &format!("setpts=PTS/{}", speed),
+            "-map",
+            "0:V",
+            "-map",
+            "0:a?",
+        ]);
+    } else if !options.strip_art
+        && report
+            .format
+            .is_some_and(|format| AudioFormat::WITH_COVER_ART.contains(format))
+    {
+        // Keep the attached picture, if any, by stream-copying it
+        command.args([
+            "-map",
+            "0:a",
+            "-map",
+            "0:v?",
+            "-c:v",
+            "copy",
+            "-disposition:v",
+            "attached_pic",
+        ]);
+    } else {
+        command.arg("-vn");
+    }
+    let encoded_format = options
+        .output_format
+        .map_or(report.format, |f| Some(f.audio_format()));
+    if !is_video && let Some(encoder) = encoded_format.and_then(|f| context.encoders.get(f)) {
+        command.args(["-c:a", encoder]);
+    }
+    if let Some(sample_rate) = options.sample_rate {
+        command.arg("-ar").arg(sample_rate.to_string());
+    }
+    if let Some(channels) = options.channels {
+        command.arg("-ac").arg(channels.to_string());
+    }
+    let bitrate = options.bitrate.or_else(|| {
+        if options.match_bitrate && !is_video {
+            matched_bitrate(
+                &*options.runner,
+                report.format?,
+                options.output_format,
+                source,
+            )
+        } else {
+            None
+        }
+    });
+    if let Some(bitrate) = bitrate {
+        command.arg("-b:a").arg(bitrate.to_string());
+    }
+    marker::add_marker(&mut command, speed, &final_path);
+    if let Some(threads) = options.ffmpeg_threads {
+        command.arg("-threads").arg(threads.to_string());
+    }
+    if let Some(segment) = options.segment {
+        command.args([
+            "-f",
+            "segment",
+            "-segment_time",
+            &segment.as_secs_f64().to_string(),
+            "-segment_start_number",
+            "1",
+            "-reset_timestamps",
+            "1",
+        ]);
+    }
+    command
+        .arg(&output_file)
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-nostats",
+            "-progress",
+            "pipe:1",
+        ])
+        .stdin(Stdio::null());
+    if let Some(nice) = options.nice {
+        exec::set_priority(&mut command, nice);
+    }
+
+    let permit = context.ffmpeg_limit.as_ref().map(Semaphore::acquire);
+    // The expected output duration is used to turn ffmpeg's output timestamps
+    // into a fraction of the file.
+    let log_command = context.run_log.is_some().then(|| format!("{:?}", command));
+    let status = options
+        .runner
+        .run_with_progress(&mut command, options.timeout, &mut |out_time| {
+            let Some(expected) = expected_duration else {
+                return;
             };
+            let fraction = (out_time.as_secs_f64() / expected.as_secs_f64()).min(1.0);
+            let units = (fraction * PROGRESS_UNITS_PER_FILE as f64) as u64;
+            if units > *reported_units {
+                context.pb.inc(units - *reported_units);
+                file_pb.set_position(units);
+                *reported_units = units;
+            }
+            (context.on_event)(ProcessEvent::Progress {
+                path: path.to_path_buf(),
+                fraction: fraction as f32,
+            });
+        });
+    drop(permit);
+    if let Some(chapters_file) = &chapters_file {
+        remove_temp_file(chapters_file);
+    }
+    if let Some(run_log) = &context.run_log
+        && let Some(log_command) = &log_command
+    {
+        run_log.record(path, log_command, &status);
+    }
 
-            let status = Command::new("ffmpeg")
-                .args([
-                    "-i",
-                    input_path_str,
-                    "-filter:a",
-                    &format!("atempo={}", speed),
-                    "-vn",
-                    "-map_metadata",
-                    "0",
-                    output_file_str,
-                    "-y",
-                    "-loglevel",
-                    "error",
-                ])
-                .status();
-
-            match status {
-                Ok(exit_status) => {
-                    if exit_status.success() {
-                        if let Err(e) = std::fs::rename(&output_file, path) {
-                            error!(
-                                "Error renaming file from {} to {}: {}",
-                                output_file.display(),
-                                path.display(),
-                                e
-                            );
-                            error_count.fetch_add(1, Ordering::AcqRel);
-                        }
-                    } else {
-                        error!(
-                            "ffmpeg failed for {}. Exit code: {:?}",
+    match status {
+        // A file written to during encoding gives a truncated output, or none
+        Ok(_) if has_changed(file) => {
+            debug!("Deferring {}, which changed while encoding", path.display());
+            remove_temp_output(&output_file);
+            report.changing = true;
+            FileStatus::Skipped
+        }
+        Ok((exit_status, ffmpeg_stderr)) => {
+            *stderr = ffmpeg_stderr;
+            if exit_status.success() && options.segment.is_some() {
+                install_segments(options, file, &output_file, speed, original_times, report)
+            } else if exit_status.success() {
+                if options.replaygain {
+                    update_replaygain(&*options.runner, &output_file);
+                }
+                report.output_size = std::fs::metadata(&output_file).map(|m| m.len()).ok();
+                report.output_duration = ffmpeg::probe_duration(&*options.runner, &output_file);
+                if options.analyze_loudness {
+                    report.output_loudness = analyze_loudness(&*options.runner, &output_file);
+                    if let Some(loudness) = report.output_loudness
+                        && loudness.clips()
+                        && !report.input_loudness.is_some_and(|l| l.clips())
+                    {
+                        warn!(
+                            "Processing made {} clip: true peak of {:.1} dBTP",
                             path.display(),
-                            exit_status.code()
+                            loudness.true_peak_dbtp
                         );
-                        error_count.fetch_add(1, Ordering::AcqRel);
-                        // Ensure temp file is removed if ffmpeg failed
-                        if output_file.exists()
-                            && let Err(e) = std::fs::remove_file(&output_file)
-                        {
-                            error!("Error removing temp file {}: {}", output_file.display(), e);
-                        }
                     }
                 }
-                Err(e) => {
-                    error!("Error executing ffmpeg for {}: {}", path.display(), e);
-                    error_count.fetch_add(1, Ordering::AcqRel);
-                    // Ensure temp file is removed if ffmpeg execution failed
-                    if output_file.exists()
-                        && let Err(e) = std::fs::remove_file(&output_file)
-                    {
-                        error!("Error removing temp file {}: {}", output_file.display(), e);
-                    }
+                let status = install_output(
+                    options,
+                    file,
+                    &output_file,
+                    &final_path,
+                    original_times,
+                    &map_time,
+                    report,
+                );
+                if status != FileStatus::Processed {
+                    return status;
                 }
+                report.output_path = Some(final_path.clone());
+                // Hard links are linked to the output of the file before them
+                let mut source = final_path.clone();
+                *duplicate_reports = job
+                    .duplicates
+                    .iter()
+                    .map(|duplicate| {
+                        let duplicate_report =
+                            copy_to_duplicate(options, report, &source, duplicate, &map_time);
+                        if !duplicate.hardlink
+                            && let Some(output_path) = &duplicate_report.output_path
+                        {
+                            source = output_path.clone();
+                        }
+                        duplicate_report
+                    })
+                    .collect();
+                FileStatus::Processed
+            } else {
+                error!(
+                    "ffmpeg failed for {}. Exit code: {:?}\n{}",
+                    path.display(),
+                    exit_status.code(),
+                    stderr.trim_end()
+                );
+                // Ensure temp file is removed if ffmpeg failed
+                remove_temp_output(&output_file);
+                FileStatus::Failed
             }
-        });
+        }
+        Err(e) => {
+            error!("Error executing ffmpeg for {}: {}", path.display(), e);
+            // Ensure temp file is removed if ffmpeg execution failed
+            remove_temp_output(&output_file);
+            FileStatus::Failed
+        }
+    }
+}
 
-    process_pb.finish_with_message("Processing complete!");
+/// Returns the audio filter used to change the tempo with the given
+/// [`Quality`], falling back to `atempo` if `rubberband` is not available.
+fn tempo_filter(options: &ProcessOptions) -> &'static str {
+    match options.quality {
+        Quality::Standard => "atempo",
+        Quality::High if ffmpeg::has_filter(&*options.runner, "rubberband") => "rubberband",
+        Quality::High => {
+            log::warn!("ffmpeg was built without the rubberband filter, falling back to atempo");
+            "atempo"
+        }
+    }
+}
 
-    let errors = error_count.load(Ordering::Relaxed);
-    let skipped = skipped_count.load(Ordering::Relaxed);
+/// Computes the final and temporary output paths of `file`, or of its
+/// `part`-th segment, and creates the folder they are in. Errors are logged.
+fn prepare_output(
+    file: &PlannedFile,
+    options: &ProcessOptions,
+    speed: f32,
+    part: Option<usize>,
+) -> Option<(PathBuf, PathBuf)> {
+    let path = &file.path;
+    let final_path = match output_path(file, options, speed, part) {
+        Ok(final_path) => final_path,
+        Err(e) => {
+            error!("Failed to build output name for {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    if let Some(parent) = final_path.parent()
+        && let Err(e) = std::fs::create_dir_all(parent)
+    {
+        error!("Error creating output folder {}: {}", parent.display(), e);
+        return None;
+    }
+    let Some(output_file) = temp_output_path(&final_path, options) else {
+        error!("Failed to get file name for {}", path.display());
+        return None;
+    };
+    Some((final_path, output_file))
+}
 
-    if errors > 0 {
-        log::error!("Finished with {} errors.", errors);
+/// Moves the finished temporary `output_file` of `file` to `final_path`,
+/// backing up the original first if it is replaced, then restores
+/// `original_times` and rescales sidecars. Errors are logged, and a
+/// `final_path` in use by another program is flagged in `report`.
+fn install_output(
+    options: &ProcessOptions,
+    file: &PlannedFile,
+    output_file: &Path,
+    final_path: &Path,
+    original_times: Option<FileTimes>,
+    map_time: &dyn Fn(f64) -> f64,
+    report: &mut FileReport,
+) -> FileStatus {
+    let path = &file.path;
+    // Backups are only needed when the original is replaced
+    if let Some(backup_dir) = &options.backup_dir
+        && final_path == path
+        && let Err(e) = backup::backup_file(
+            path,
+            backup_dir,
+            &file.relative_path,
+            options.io_limit.as_ref(),
+        )
+    {
+        error!("Error backing up {}: {}", path.display(), e);
+        remove_temp_file(output_file);
+        return FileStatus::Failed;
     }
-    if skipped > 0 {
-        log::info!("Skipped {} files.", skipped);
+    if options.preserve_attrs
+        && let Err(e) = attrs::copy_attrs(path, output_file)
+    {
+        warn!("Error copying the attributes of {}: {}", path.display(), e);
+    }
+    if options.clear_readonly
+        && let Err(e) = clear_readonly(final_path)
+    {
+        warn!(
+            "Error clearing the read-only attribute of {}: {}",
+            final_path.display(),
+            e
+        );
+    }
+    if let Err(e) = move_file(output_file, final_path, options.io_limit.as_ref()) {
+        error!(
+            "Error renaming file from {} to {}: {}",
+            output_file.display(),
+            final_path.display(),
+            e
+        );
+        report.locked = is_locked(&e);
+        return FileStatus::Failed;
+    }
+    if let Some(times) = original_times
+        && let Err(e) = File::options()
+            .write(true)
+            .open(final_path)
+            .and_then(|f| f.set_times(times))
+    {
+        error!(
+            "Error restoring file times of {}: {}",
+            final_path.display(),
+            e
+        );
     }
+    if options.rescale_sidecars {
+        sidecar::rescale_sidecars(path, final_path, map_time);
+    }
+    if options.record_durations
+        && let Some(duration) = report.input_duration
+        && let Err(e) = origin::record_origin(final_path, duration)
+    {
+        warn!(
+            "Error recording the original duration of {}: {}",
+            final_path.display(),
+            e
+        );
+    }
+    FileStatus::Processed
+}
 
-    Ok(())
+/// Moves the temporary parts written for `pattern` to their final names. If
+/// [`ProcessOptions::output_dir`] is not set, the parts replace the original
+/// file, which is backed up first if requested. Errors are logged.
+fn install_segments(
+    options: &ProcessOptions,
+    file: &PlannedFile,
+    pattern: &Path,
+    speed: f32,
+    original_times: Option<FileTimes>,
+    report: &mut FileReport,
+) -> FileStatus {
+    let path = &file.path;
+    let parts = segment::temp_parts(pattern);
+    if parts.is_empty() {
+        error!("ffmpeg produced no segments for {}", path.display());
+        return FileStatus::Failed;
+    }
+    let replace_original = options.output_dir.is_none();
+    if replace_original
+        && let Some(backup_dir) = &options.backup_dir
+        && let Err(e) = backup::backup_file(
+            path,
+            backup_dir,
+            &file.relative_path,
+            options.io_limit.as_ref(),
+        )
+    {
+        error!("Error backing up {}: {}", path.display(), e);
+        segment::remove_temp_parts(pattern);
+        return FileStatus::Failed;
+    }
+
+    let mut finals = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        if options.replaygain {
+            update_replaygain(&*options.runner, part);
+        }
+        let Some((final_path, _)) = prepare_output(file, options, speed, Some(i + 1)) else {
+            segment::remove_temp_parts(pattern);
+            return FileStatus::Failed;
+        };
+        if options.preserve_attrs
+            && let Err(e) = attrs::copy_attrs(path, part)
+        {
+            warn!("Error copying the attributes of {}: {}", path.display(), e);
+        }
+        if let Err(e) = move_file(part, &final_path, options.io_limit.as_ref()) {
+            error!(
+                "Error renaming file from {} to {}: {}",
+                part.display(),
+                final_path.display(),
+                e
+            );
+            report.locked = is_locked(&e);
+            segment::remove_temp_parts(pattern);
+            return FileStatus::Failed;
+        }
+        if let Some(times) = original_times
+            && let Err(e) = File::options()
+                .write(true)
+                .open(&final_path)
+                .and_then(|f| f.set_times(times))
+        {
+            error!(
+                "Error restoring file times of {}: {}",
+                final_path.display(),
+                e
+            );
+        }
+        finals.push(final_path);
+    }
+    if replace_original
+        && !finals.contains(path)
+        && let Err(e) = std::fs::remove_file(path)
+    {
+        error!(
+            "Error removing {} after splitting it: {}",
+            path.display(),
+            e
+        );
+    }
+
+    report.output_size = Some(
+        finals
+            .iter()
+            .filter_map(|part| std::fs::metadata(part).ok())
+            .map(|m| m.len())
+            .sum(),
+    );
+    let durations: Vec<Option<Duration>> = finals
+        .iter()
+        .map(|part| ffmpeg::probe_duration(&*options.runner, part))
+        .collect();
+    report.output_duration = durations.iter().copied().sum();
+    report.output_path = finals.first().cloned();
+    report.output_parts = finals.into_iter().zip(durations).collect();
+    FileStatus::Processed
+}
+
+/// Returns the bitrate to encode the lossy file `path` of `format` at to keep
+/// its quality: its own bitrate, rounded to whole kb/s and clamped to the
+/// range of the output encoder. Lossless files are left to the encoder's
+/// defaults.
+fn matched_bitrate(
+    runner: &dyn CommandRunner,
+    format: AudioFormat,
+    output_format: Option<OutputFormat>,
+    path: &Path,
+) -> Option<NonZeroU32> {
+    if !AudioFormat::LOSSY.contains(format) {
+        return None;
+    }
+    let source = ffmpeg::probe_bitrate(runner, path)?;
+    let encoder = output_format.map_or(format, OutputFormat::audio_format);
+    let (min, max) = match encoder {
+        AudioFormat::OPUS => (6_000, 510_000),
+        AudioFormat::OGG => (32_000, 500_000),
+        AudioFormat::MP3 => (8_000, 320_000),
+        AudioFormat::WMA => (24_000, 320_000),
+        _ => (8_000, 512_000),
+    };
+    let bitrate = ((source + 500) / 1000 * 1000).clamp(min, max);
+    debug!(
+        "Matching the bitrate of {}: {} b/s",
+        path.display(),
+        bitrate
+    );
+    NonZeroU32::new(bitrate)
+}
+
+/// Logs the outcome of a file that took `elapsed`, as a structured record
+/// under [`FILE_TARGET`].
+fn record_file(report: &FileReport, elapsed: Duration) {
+    debug!(
+        target: FILE_TARGET,
+        path:% = report.path.display(),
+        format = report.format.map(AudioFormat::name),
+        speed = report.speed,
+        input_duration_secs = report.input_duration.map(|d| d.as_secs_f64()),
+        output_duration_secs = report.output_duration.map(|d| d.as_secs_f64()),
+        outcome:% = report.status,
+        elapsed_secs = elapsed.as_secs_f64();
+        "Finished {} ({}) in {:.1}s",
+        report.path.display(),
+        report.status,
+        elapsed.as_secs_f64()
+    );
+}
+
+/// Measures the loudness of `path`, logging errors.
+fn analyze_loudness(runner: &dyn CommandRunner, path: &Path) -> Option<Loudness> {
+    loudness::measure_loudness(runner, path)
+        .inspect_err(|e| warn!("Error measuring the loudness of {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Rewrites the ReplayGain tags of a processed file. Errors are logged, since
+/// the audio itself is still valid.
+fn update_replaygain(runner: &dyn CommandRunner, output_file: &Path) {
+    match replaygain::rewrite_replaygain(runner, output_file) {
+        Ok(()) => debug!("Updated ReplayGain tags of {}", output_file.display()),
+        Err(e) => error!(
+            "Error updating ReplayGain tags of {}: {}",
+            output_file.display(),
+            e
+        ),
+    }
+}
+
+/// Gives `duplicate` the output produced for the file described by `report`,
+/// which was written to `source`, instead of processing it again. Hard links
+/// are linked to `source` again, falling back to a copy if that fails.
+///
+/// # Returns
+///
+/// * `FileReport` - The report of the duplicate, sharing the results of the processed file.
+fn copy_to_duplicate(
+    options: &ProcessOptions,
+    report: &FileReport,
+    source: &Path,
+    duplicate: &Duplicate,
+    map_time: &dyn Fn(f64) -> f64,
+) -> FileReport {
+    let Duplicate {
+        file: duplicate,
+        hardlink,
+    } = duplicate;
+    let mut duplicate_report = FileReport {
+        status: FileStatus::Failed,
+        speed: report.speed,
+        input_duration: report.input_duration,
+        output_duration: report.output_duration,
+        input_loudness: report.input_loudness,
+        output_loudness: report.output_loudness,
+        output_size: report.output_size,
+        ..FileReport::from_planned(duplicate)
+    };
+    let path = &duplicate.path;
+    let original_times = std::fs::metadata(path)
+        .ok()
+        .filter(|_| options.preserve_times)
+        .map(|m| original_file_times(&m));
+    let speed = report.speed.unwrap_or(options.speed);
+    let Some((final_path, output_file)) = prepare_output(duplicate, options, speed, None) else {
+        return duplicate_report;
+    };
+    let output_file = match hardlink.then(|| link_output(source, &final_path)).flatten() {
+        Some(link) => link,
+        None => {
+            if let Err(e) = throttle::copy_file(source, &output_file, options.io_limit.as_ref()) {
+                error!(
+                    "Error copying {} to duplicate {}: {}",
+                    source.display(),
+                    path.display(),
+                    e
+                );
+                remove_temp_file(&output_file);
+                return duplicate_report;
+            }
+            output_file
+        }
+    };
+    duplicate_report.status = install_output(
+        options,
+        duplicate,
+        &output_file,
+        &final_path,
+        original_times,
+        map_time,
+        &mut duplicate_report,
+    );
+    if duplicate_report.status == FileStatus::Processed {
+        duplicate_report.output_path = Some(final_path);
+    }
+    duplicate_report
+}
+
+/// Hard links `source` next to `final_path`, as links cannot be made across
+/// file systems.
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The temporary path of the link, or `None` if it could not be created.
+fn link_output(source: &Path, final_path: &Path) -> Option<PathBuf> {
+    let link = temp_path(final_path)?;
+    match std::fs::hard_link(source, &link) {
+        Ok(()) => Some(link),
+        Err(e) => {
+            debug!(
+                "Failed to link {} to {}, copying it instead: {}",
+                final_path.display(),
+                source.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Returns the path the processed version of `file` (or its `part`-th
+/// segment) is written to, which is the file itself unless
+/// [`ProcessOptions::output_dir`], [`ProcessOptions::name_template`] or
+/// [`ProcessOptions::segment`] is set.
+fn output_path(
+    file: &PlannedFile,
+    options: &ProcessOptions,
+    speed: f32,
+    part: Option<usize>,
+) -> Result<PathBuf, String> {
+    let (path, relative_path) = match options.output_format {
+        Some(format) => (
+            file.path.with_extension(format.extension()),
+            file.relative_path.with_extension(format.extension()),
+        ),
+        None => (file.path.clone(), file.relative_path.clone()),
+    };
+    let default_template = match (part, &options.output_dir) {
+        (None, _) => None,
+        (Some(_), None) => Some("{stem}_{part}.{ext}"),
+        (Some(_), Some(_)) => Some("{dir}/{stem}_{part}.{ext}"),
+    };
+    let name = match options.name_template.as_deref().or(default_template) {
+        Some(template) => naming::render_name(template, &relative_path, speed, part)?,
+        None if options.output_dir.is_some() => relative_path,
+        None => return Ok(path),
+    };
+    Ok(match &options.output_dir {
+        Some(output_dir) => output_dir.join(name),
+        None => path.with_file_name(name),
+    })
+}
+
+/// Returns the path of the temporary file used while writing `path`.
+///
+/// The name is built from the original file name without any lossy
+/// conversion, so non-UTF-8 names are preserved.
+fn temp_path(path: &Path) -> Option<PathBuf> {
+    Some(path.with_file_name(temp_name(path.file_name()?)))
+}
+
+/// Distinguishes temporary outputs of files with the same name, so that
+/// their names, `temp_<pid>_<id>_<name>`, are owned by this tool.
+static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the name of a temporary file standing for `name`,
+/// `temp_<pid>_<id>_<name>`, which [`clean_temp_files`] recognizes.
+fn temp_name(name: &OsStr) -> OsString {
+    let mut temp = OsString::from(format!(
+        "{}{}_{}_",
+        clean::TEMP_PREFIX,
+        std::process::id(),
+        NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    temp.push(name);
+    temp
+}
+
+/// Returns the temporary path ffmpeg writes the output `final_path` to: next
+/// to it, or in [`ProcessOptions::temp_dir`] (or the system's temporary
+/// folder with [`ProcessOptions::stage_local`]).
+fn temp_output_path(final_path: &Path, options: &ProcessOptions) -> Option<PathBuf> {
+    let Some(temp_dir) = options.local_temp_dir() else {
+        return temp_path(final_path);
+    };
+    Some(temp_dir.join(temp_name(final_path.file_name()?)))
+}
+
+/// Moves `from` to `to`, replacing it. If they are on different file systems
+/// (e.g., with [`ProcessOptions::temp_dir`]), `from` is copied next to `to`
+/// within `io_limit` and renamed from there, so `to` is never left
+/// half-written.
+pub(crate) fn move_file(from: &Path, to: &Path, io_limit: Option<&IoLimit>) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let staged = temp_path(to).ok_or(e)?;
+            if let Err(e) = throttle::copy_file(from, &staged, io_limit)
+                .and_then(|_| std::fs::rename(&staged, to))
+            {
+                remove_temp_file(&staged);
+                return Err(e);
+            }
+            if let Err(e) = std::fs::remove_file(from) {
+                warn!("Error removing temp file {}: {}", from.display(), e);
+            }
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Returns whether the size or modification time of `file` differs from
+/// when it was planned, e.g. because a recorder is still writing it.
+fn has_changed(file: &PlannedFile) -> bool {
+    std::fs::metadata(&file.path).is_ok_and(|metadata| {
+        metadata.len() != file.size
+            || file
+                .modified
+                .is_some_and(|modified| metadata.modified().ok() != Some(modified))
+    })
+}
+
+/// Returns whether `e` means the file is open in another program that does
+/// not share it, e.g. a player on Windows. Other systems do not lock files.
+fn is_locked(e: &std::io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION and ERROR_USER_MAPPED_FILE
+    cfg!(windows) && matches!(e.raw_os_error(), Some(32 | 33 | 1224))
+}
+
+/// Makes `path` writable by its owner, if it exists and is read-only, so it
+/// can be replaced.
+fn clear_readonly(path: &Path) -> std::io::Result<()> {
+    let mut permissions = match std::fs::metadata(path) {
+        Ok(metadata) if metadata.permissions().readonly() => metadata.permissions(),
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Removes a temporary output file, if it exists.
+fn remove_temp_file(output_file: &Path) {
+    if output_file.exists()
+        && let Err(e) = std::fs::remove_file(output_file)
+    {
+        error!("Error removing temp file {}: {}", output_file.display(), e);
+    }
+}
+
+/// Captures the modification and access times of a file, so they can be
+/// restored after it has been replaced.
+fn original_file_times(metadata: &Metadata) -> FileTimes {
+    let mut times = FileTimes::new();
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    times
 }
@@ -0,0 +1,80 @@
+//! Loudness analysis with ffmpeg's `ebur128` filter.
+
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::{CommandRunner, Error, Result, exec};
+
+/// The loudness of a file, as measured according to EBU R 128.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness over the whole file, in LUFS.
+    pub integrated_lufs: f32,
+    /// The highest true peak, in dBTP. Above 0 dBTP, the audio clips once
+    /// decoded.
+    pub true_peak_dbtp: f32,
+}
+
+impl Loudness {
+    /// Returns whether the true peak exceeds full scale, i.e. the audio clips.
+    pub fn clips(&self) -> bool {
+        self.true_peak_dbtp > 0.0
+    }
+}
+
+/// Measures the integrated loudness and true peak of `path` with ffmpeg's
+/// `ebur128` filter.
+///
+/// # Returns
+///
+/// * `Result<Loudness>` - An error if ffmpeg could not be run or failed, or
+///   [`Error::Detection`] if it did not print a summary.
+pub(crate) fn measure_loudness(runner: &dyn CommandRunner, path: &Path) -> Result<Loudness> {
+    let output = runner
+        .output(
+            exec::command("ffmpeg")
+                .args(["-hide_banner", "-nostats", "-i"])
+                .arg(path)
+                .args(["-vn", "-af", "ebur128=peak=true", "-f", "null", "-"])
+                .stdin(Stdio::null()),
+        )
+        .map_err(Error::from_spawn)?;
+    if !output.status.success() {
+        return Err(Error::FfmpegFailed {
+            path: path.to_path_buf(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    parse_summary(&String::from_utf8_lossy(&output.stderr)).ok_or_else(|| Error::Detection {
+        path: path.to_path_buf(),
+        what: "loudness",
+    })
+}
+
+/// Extracts the loudness from the summary the `ebur128` filter prints at the
+/// end of its log:
+///
+/// ```text
+/// [Parsed_ebur128_0 @ 0x5581] Summary:
+///
+///   Integrated loudness:
+///     I:         -16.9 LUFS
+///     Threshold: -27.2 LUFS
+///   ...
+///   True peak:
+///     Peak:       -0.4 dBFS
+/// ```
+fn parse_summary(log: &str) -> Option<Loudness> {
+    let summary = &log[log.rfind("Summary:")?..];
+    let value = |key: &str| -> Option<f32> {
+        let line = summary
+            .lines()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix(key))?;
+        line.split_whitespace().next()?.parse().ok()
+    };
+    Some(Loudness {
+        integrated_lufs: value("I:")?,
+        true_peak_dbtp: value("Peak:")?,
+    })
+}
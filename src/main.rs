@@ -1,77 +1,1902 @@
 use anyhow::Result;
-use audio_batch_speedup::AudioFormat;
-use clap::Parser;
-use log::{LevelFilter, error, info};
-use std::path::PathBuf; // Import AudioFormat
+use audio_batch_speedup::{
+    AudioFormat, EncoderMap, FileStatus, FormatSummary, HISTORY_NAME, HistoryEntry, IoLimit,
+    MANIFEST_NAME, Order, PlannedFile, Preset, ProcessEvent, ProcessOptions, ProcessReport,
+    Quality, ResourceGuard, SilenceOptions, SkipReason, Speed, TagFilter, TrimOptions,
+};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use log::{LevelFilter, error, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+mod export;
+mod http;
+mod json;
+mod keys;
+mod notify;
+mod serve;
+
+use json::{json_path, json_string};
 
 #[derive(Parser)]
 #[command(author, version, about = "Batch speed up audio files")]
 struct Cli {
-    /// Path to the folder containing audio files
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+
+    /// Only print errors, and hide the progress bar
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more details; repeat for even more (-vv)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Format of the log on standard error; `json` prints one object per line, including a record
+    /// with the path, format, speed, durations and outcome of every file
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "pretty")]
+    log_format: LogFormat,
+
+    /// If ffmpeg is not found, download a static build into the cache folder without asking
+    #[arg(long, global = true)]
+    download_ffmpeg: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Speed up audio files in a folder, replacing them in place
+    Speedup(Box<SpeedupArgs>),
+    /// Restore original files from a backup folder made with `speedup --backup`
+    Restore {
+        /// The backup folder
+        backup: PathBuf,
+        /// The folder the backups were taken from
+        target: PathBuf,
+    },
+    /// Check that the audio files in a folder decode without errors
+    Verify {
+        /// Path to the folder containing audio files
+        input: PathBuf,
+        #[command(flatten)]
+        formats: FormatSelection,
+        /// Instead of decoding the files, check them against a manifest written with
+        /// `speedup --checksums`, with paths relative to the input folder
+        #[arg(long, value_name = "FILE", conflicts_with = "originals")]
+        manifest: Option<PathBuf>,
+        /// Also compare each file with its original at the same relative path in this folder
+        /// (e.g. a --backup folder): durations must match the speed and tags must be kept
+        #[arg(long, value_name = "DIR")]
+        originals: Option<PathBuf>,
+        /// The speed the durations must match with --originals (default: the speed recorded
+        /// in each processed file)
+        #[arg(short, long, value_name = "SPEED", requires = "originals")]
+        speed: Option<Speed>,
+    },
+    /// Remove temporary files left behind by interrupted runs
+    Clean {
+        /// Path to the folder containing audio files
+        input: PathBuf,
+
+        /// Do not ask for confirmation before removing the files
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Show the number, size and duration of audio files in a folder, per format
+    Stats {
+        /// Path to the folder containing audio files
+        input: PathBuf,
+        #[command(flatten)]
+        formats: FormatSelection,
+    },
+    /// Show the files processed in a folder by earlier runs, oldest first
+    History {
+        /// Path to the processed folder
+        input: PathBuf,
+        /// Only show the last N entries
+        #[arg(short = 'n', long, value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Keep running and accept speedup jobs over a local HTTP API
+    Serve {
+        /// Address to listen on. The API is plain HTTP, so keep it on localhost or a trusted
+        /// network.
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: SocketAddr,
+        /// Token requests must carry in an `Authorization: Bearer` header. Default: a random token,
+        /// logged at start
+        #[arg(long, value_name = "TOKEN")]
+        token: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct SpeedupArgs {
+    /// Path to the folder containing audio files, or to an .m3u/.m3u8 playlist of the files to
+    /// process
+    #[arg(required_unless_present = "pipe")]
+    input: Option<PathBuf>,
+
+    /// Read a single audio stream from stdin and write the sped-up stream to stdout, without
+    /// touching the disk. Requires --input-format and --output-format.
+    #[arg(
+        long,
+        conflicts_with = "input",
+        requires_all = ["input_format", "output_format"]
+    )]
+    pipe: bool,
+
+    /// Format of the audio read from stdin with --pipe (e.g., mp3)
+    #[arg(long, value_name = "FORMAT", value_parser = parse_single_format, requires = "pipe")]
+    input_format: Option<AudioFormat>,
+
+    /// Format to write to stdout with --pipe (e.g., ogg)
+    #[arg(long, value_name = "FORMAT", value_parser = parse_single_format, requires = "pipe")]
+    output_format: Option<AudioFormat>,
+
+    /// Audio speed multiplier, as a number (1.5), a fraction (3/2), a percentage (150%) or a
+    /// relative change (+50%)
+    #[arg(
+        short,
+        long,
+        allow_hyphen_values = true,
+        value_parser = parse_speed,
+        required_unless_present_any = ["target_duration", "target_bpm", "target_wpm", "speeds"]
+    )]
+    speed: Option<Speed>,
+
+    /// Produce one output per speed (separated by commas, e.g., 1.25,1.5,2), named after
+    /// --name-template, instead of replacing the originals
+    #[arg(
+        long,
+        value_name = "SPEEDS",
+        value_delimiter = ',',
+        value_parser = parse_speed,
+        conflicts_with_all = ["speed", "target_duration", "target_bpm", "target_wpm", "merge", "resume"]
+    )]
+    speeds: Vec<Speed>,
+
+    #[command(flatten)]
+    formats: FormatSelection,
+
+    /// Run ffmpeg at a lower (or higher) scheduling priority, as a nice level from -20 (highest)
+    /// to 19 (lowest). On Windows this selects the closest priority class.
+    #[arg(long, value_name = "LEVEL", allow_hyphen_values = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+    nice: Option<i32>,
+
+    /// Maximum number of ffmpeg processes to run concurrently (default: one per CPU core)
+    #[arg(long, value_name = "N")]
+    max_ffmpeg: Option<NonZeroUsize>,
+
+    /// The order files are processed and reported in: "size" (largest first), "name", "duration"
+    /// (longest first) or "random"
+    #[arg(long, value_name = "ORDER", default_value = "size", value_parser = parse_order)]
+    order: Order,
+
+    /// Number of threads each ffmpeg process encodes with, e.g. 1 on a shared machine
+    #[arg(long, value_name = "N")]
+    ffmpeg_threads: Option<NonZeroU32>,
+
+    /// Start no new file while running on a battery charged below this percentage, until it
+    /// recovers
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    min_battery: Option<u8>,
+
+    /// Start no new file while the CPU is hotter than this temperature (in °C), until it cools
+    #[arg(long, value_name = "CELSIUS")]
+    max_temp: Option<f32>,
+
+    /// Limit the bandwidth of the copies made by the run (staging, backups, moves across disks),
+    /// in bytes per second across all workers (e.g., 50M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_io_limit)]
+    io_limit: Option<IoLimit>,
+
+    /// Skip files smaller than this size (e.g., 500k, 10M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than this size (e.g., 2G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only process files modified after this date (e.g., 2024-01-01, in UTC) or within this age
+    /// (e.g., 7d)
+    #[arg(long, value_name = "DATE|AGE", value_parser = parse_newer_than)]
+    newer_than: Option<SystemTime>,
+
+    /// Instead of a fixed speed, speed up each file so that it lasts this long (e.g., 45m, 1h30m,
+    /// 1:30:00). Files that are already shorter are skipped.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "speed")]
+    target_duration: Option<Duration>,
+
+    /// Instead of a fixed speed, detect each file's tempo and speed it up (or slow it down) to
+    /// this many beats per minute
+    #[arg(long, value_name = "BPM", value_parser = parse_rate, conflicts_with_all = ["speed", "target_duration"])]
+    target_bpm: Option<f32>,
+
+    /// Experimental: instead of a fixed speed, estimate each file's speech rate and speed it up
+    /// (or slow it down) to this many words per minute
+    #[arg(long, value_name = "WPM", value_parser = parse_rate, conflicts_with_all = ["speed", "target_duration", "target_bpm"])]
+    target_wpm: Option<f32>,
+
+    /// Minimum speed used when computing per-file speeds with --target-bpm or --target-wpm
+    #[arg(long, value_name = "SPEED", value_parser = parse_speed)]
+    min_speed: Option<Speed>,
+
+    /// Maximum speed used when computing per-file speeds with --target-duration, --target-bpm or
+    /// --target-wpm
+    #[arg(long, value_name = "SPEED", value_parser = parse_speed)]
+    max_speed: Option<Speed>,
+
+    /// Time-stretching quality: "standard" (atempo) or "high" (rubberband, if ffmpeg has it)
+    #[arg(long, value_name = "QUALITY", value_parser = parse_quality, default_value = "standard")]
+    quality: Quality,
+
+    /// Detect silent segments and speed them up with this multiplier, while speech uses --speed
+    #[arg(long, value_name = "SPEED", value_parser = parse_speed)]
+    silence_speed: Option<Speed>,
+
+    /// Audio quieter than this level (in dB) counts as silence for --silence-speed
+    #[arg(long, value_name = "DB", default_value_t = -30.0, allow_hyphen_values = true, requires = "silence_speed")]
+    silence_threshold: f32,
+
+    /// Minimum length of a quiet stretch to count as silence for --silence-speed (e.g., 0.5s)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "silence_speed")]
+    silence_min_duration: Option<Duration>,
+
+    /// Trim the dead air at the start and end of each file, in the same pass as the speed change
+    #[arg(long)]
+    trim_edges: bool,
+
+    /// Audio quieter than this level (in dB) counts as dead air for --trim-edges
+    #[arg(long, value_name = "DB", default_value_t = -50.0, allow_hyphen_values = true, requires = "trim_edges")]
+    trim_threshold: f32,
+
+    /// Silence to keep before the first and after the last sound with --trim-edges (e.g., 0.2s)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "trim_edges")]
+    trim_padding: Option<Duration>,
+
+    /// Resample the output to this sample rate, in Hz (e.g., 44100)
+    #[arg(long, value_name = "HZ")]
+    sample_rate: Option<NonZeroU32>,
+
+    /// Remix the output to this number of channels (e.g., 1 to downmix to mono)
+    #[arg(long, value_name = "N")]
+    channels: Option<NonZeroU32>,
+
+    /// Encode the output at this bitrate (e.g., 64k). By default, lossy files keep the bitrate of
+    /// the original.
+    #[arg(long, value_name = "RATE", value_parser = parse_bitrate)]
+    bitrate: Option<NonZeroU32>,
+
+    /// Encode lossy files with the encoder's default bitrate instead of the bitrate of the
+    /// original
+    #[arg(long, conflicts_with = "bitrate")]
+    no_match_bitrate: bool,
+
+    /// Encoder of each output format, as format=encoder pairs (e.g.,
+    /// mp3=libmp3lame,aac=aac_at). Other formats get the best encoder ffmpeg has.
+    #[arg(long, value_name = "LIST", value_parser = parse_encoders)]
+    encoder: Option<EncoderMap>,
+
+    /// Apply a bundle of options for a use case. "audiobook": rescaled chapters, mono at 64k and
+    /// m4b output. Explicitly given options take precedence.
+    #[arg(long, value_name = "PRESET", value_parser = parse_preset)]
+    preset: Option<Preset>,
+
+    /// Write processed files into this folder, keeping their relative paths, instead of replacing
+    /// the originals
+    #[arg(short, long, value_name = "DIR")]
+    output: Option<PathBuf>,
+
+    /// Name processed files after this template instead of replacing the originals, e.g.
+    /// "{stem}_{speed}x.{ext}". Placeholders: {stem}, {ext}, {speed}, {dir}, {part} (with --segment).
+    #[arg(long, value_name = "TEMPLATE")]
+    name_template: Option<String>,
+
+    /// When the input is a playlist, point its entries at the processed files written with
+    /// --output or --name-template
+    #[arg(long, conflicts_with_all = ["merge", "speeds"])]
+    rewrite_playlist: bool,
+
+    /// Split each output into numbered parts of this length (e.g., 30m), named after
+    /// --name-template ("{stem}_{part}.{ext}" by default)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    segment: Option<Duration>,
+
+    /// Concatenate the files of each folder (in name order) into a single output named after the
+    /// folder, and speed it up once. The original files are kept.
+    #[arg(long, conflicts_with_all = ["target_bpm", "target_wpm", "silence_speed", "trim_edges", "segment"])]
+    merge: bool,
+
+    /// Recompute the ReplayGain track gain and peak tags of processed files
+    #[arg(long)]
+    replaygain: bool,
+
+    /// Measure the integrated loudness and true peak of every file before and after processing
+    /// (EBU R 128), include them in --report, and warn about outputs that clip
+    #[arg(long)]
+    loudness: bool,
+
+    /// Process byte-identical files only once, copying the result to the duplicates
+    #[arg(long)]
+    dedup: bool,
+
+    /// Start processing while the folder is still being walked instead of planning every file
+    /// first, for very large libraries. Files are started in name order, without a summary and
+    /// without grouping hardlinks.
+    #[arg(long, conflicts_with_all = ["merge", "speeds", "sample", "preview", "dry_run", "estimate", "dedup", "order"])]
+    stream: bool,
+
+    /// Keep the original modification and access times on processed files
+    #[arg(long)]
+    preserve_times: bool,
+
+    /// Keep the permissions and extended attributes (xattrs, ACLs) of the original files
+    #[arg(long)]
+    preserve_attrs: bool,
+
+    /// Remove embedded cover art instead of preserving it
+    #[arg(long)]
+    strip_art: bool,
+
+    /// Rescale timestamps in matching .lrc, .srt and .cue files next to each processed file
+    #[arg(long)]
+    rescale_sidecars: bool,
+
+    /// Only process files with this tag value (e.g., genre=Podcast, or genre=Podcast|Audiobook
+    /// for either), or, with `!=`, without it (genre!=Music). Repeat to require several tags.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_tag_filter)]
+    tag_filter: Vec<TagFilter>,
+
+    /// Ask ffprobe for the format of files whose content and extension are unrecognized or
+    /// disagree
+    #[arg(long)]
+    probe_formats: bool,
+
+    /// Also speed up video files (mp4, m4v, mkv, webm, mov), retiming both audio and video
+    #[arg(long)]
+    include_video: bool,
+
+    /// Also speed up the audio files inside zip and tar archives, repacking each archive
+    #[arg(long, conflicts_with = "merge")]
+    archives: bool,
+
+    /// Process the files excluded by .speedupignore files too
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Copy each original file into this folder before replacing it
+    #[arg(long, value_name = "DIR")]
+    backup: Option<PathBuf>,
+
+    /// Kill ffmpeg and count the file as failed if it takes longer than this (e.g., 10m)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Write temporary outputs to this folder (e.g. a fast local disk) instead of next to the
+    /// source files
+    #[arg(long, value_name = "DIR")]
+    temp_dir: Option<PathBuf>,
+
+    /// Copy each file to --temp-dir (or the system's temp folder) and process it there, copying
+    /// the result back, e.g. for files on a slow network share
+    #[arg(long, conflicts_with = "merge")]
+    stage_local: bool,
+
+    /// Write the ffmpeg command, exit status and error output of every file to this log file
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Also process files already sped up by an earlier run
+    #[arg(long)]
+    force: bool,
+
+    /// Clear the read-only attribute of read-only files and replace them, instead of skipping them
+    #[arg(long)]
+    replace_readonly: bool,
+
+    /// Continue an interrupted run, skipping the files it already completed
+    #[arg(long, conflicts_with = "merge")]
+    resume: bool,
+
+    /// Stop at the first failed file instead of processing the rest
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// How many times to retry, at the end of the run, files that are still being written or that
+    /// another program has open, waiting twice as long each time (starting at 2s, so 3 retries wait
+    /// 14s in all)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    busy_retries: u32,
+
+    /// Shell command run with the path of each file before processing it; the file is skipped if
+    /// the command fails
+    #[arg(long, value_name = "COMMAND", conflicts_with = "merge")]
+    pre_hook: Option<String>,
+
+    /// Shell command run after each file, with its path, outcome (processed, skipped or failed)
+    /// and output path as arguments
+    #[arg(long, value_name = "COMMAND", conflicts_with = "merge")]
+    post_hook: Option<String>,
+
+    /// Print the final report with one entry per file in this format
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Option<ReportFormat>,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long, value_name = "FILE", requires = "report")]
+    report_file: Option<PathBuf>,
+
+    /// Write a manifest with the checksum and duration of every produced file, to check them
+    /// later with `abs verify --manifest`
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    checksums: Option<ChecksumAlgorithm>,
+
+    /// Where to write the checksum manifest [default: abs-checksums.tsv in the output folder]
+    #[arg(long, value_name = "FILE", requires = "checksums")]
+    manifest: Option<PathBuf>,
+
+    /// Do not record the processed files in the history of the folder (.absu-history.tsv)
+    #[arg(long)]
+    no_history: bool,
+
+    /// Write a hidden sidecar (.<name>.absu) next to processed files recording the duration of
+    /// their original, so re-runs skip them even if their marker is lost
+    #[arg(long)]
+    record_durations: bool,
+
+    /// Show a desktop notification when the run finishes
+    #[arg(long)]
+    notify: bool,
 
-    /// Audio speed multiplier
+    /// POST the final report as JSON to this URL when the run finishes
+    #[arg(long, value_name = "URL", value_parser = parse_webhook)]
+    webhook: Option<String>,
+
+    /// Number of failed files tolerated before exiting with a non-zero code
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    max_failures: usize,
+
+    /// Process a small random sample into a temporary folder first, and print the projected time
+    /// and output size of the whole run
+    #[arg(long)]
+    estimate: bool,
+
+    /// Only process this many randomly chosen files, written next to the originals with a
+    /// "_sample" suffix unless --output or --name-template is given, to check the quality first
+    #[arg(long, value_name = "N", conflicts_with = "merge")]
+    sample: Option<usize>,
+
+    /// Instead of processing the files, cut a clip of this length (e.g., 20s) from the middle of
+    /// each and write it to --preview-dir before and after processing, to compare them
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "merge")]
+    preview: Option<Duration>,
+
+    /// Where --preview writes the clips
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "abs-preview",
+        requires = "preview"
+    )]
+    preview_dir: PathBuf,
+
+    /// How to report progress: a terminal progress bar, or one JSON object per line on stdout for
+    /// wrapping applications
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    progress: ProgressFormat,
+
+    /// Only show what would be processed, without modifying any file
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Do not ask for confirmation before processing
     #[arg(short, long)]
-    speed: f32,
+    yes: bool,
+}
+
+/// Parses a bandwidth for `--io-limit`, as a size per second.
+fn parse_io_limit(s: &str) -> Result<IoLimit, String> {
+    let bytes = NonZeroU64::new(parse_size(s)?).ok_or("the limit must be positive")?;
+    Ok(IoLimit::new(bytes))
+}
 
-    /// Audio formats to process (seperated by commas, e.g., ogg,mp3,wav). Use 'all' for all supported formats.
-    /// Supported formats: ogg, mp3, wav, flac, aac, opus, alac, wma.
-    #[arg(short, long, value_delimiter = ',', default_value = "all")]
-    formats: String,
+/// Parses a human-readable size such as `500k`, `2G` or `1.5MiB` into bytes.
+/// Units are binary (1k = 1024 bytes).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: {}", s))?;
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        other => return Err(format!("unknown size unit: {}", other)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a speed given as a factor (`1.5`), a fraction (`3/2`) or a
+/// percentage (`150%`, or `+50%` relative to the original speed).
+fn parse_speed(s: &str) -> Result<Speed, String> {
+    s.parse()
+        .map_err(|e: audio_batch_speedup::Error| e.to_string())
+}
+
+/// Parses a rate per minute, such as the beats per minute of `--target-bpm`
+/// or the words per minute of `--target-wpm`, which must be positive.
+fn parse_rate(s: &str) -> Result<f32, String> {
+    match s.trim().parse::<f32>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("invalid rate: {} (expected a positive number)", s)),
+    }
+}
+
+/// Parses a tag filter of `--tag-filter`, `key=value` or `key!=value`.
+fn parse_tag_filter(s: &str) -> Result<TagFilter, String> {
+    s.parse()
+        .map_err(|e: audio_batch_speedup::Error| e.to_string())
+}
+
+/// Parses a time-stretching quality name.
+fn parse_quality(s: &str) -> Result<Quality, String> {
+    match s.trim().to_lowercase().as_str() {
+        "standard" => Ok(Quality::Standard),
+        "high" => Ok(Quality::High),
+        other => Err(format!(
+            "unknown quality: {} (expected standard or high)",
+            other
+        )),
+    }
+}
+
+/// Parses the order of `--order`.
+fn parse_order(s: &str) -> Result<Order, String> {
+    match s.trim().to_lowercase().as_str() {
+        "size" => Ok(Order::Size),
+        "name" => Ok(Order::Name),
+        "duration" => Ok(Order::Duration),
+        "random" => Ok(Order::Random),
+        other => Err(format!(
+            "unknown order: {} (expected size, name, duration or random)",
+            other
+        )),
+    }
+}
+
+/// Parses a bitrate such as `64k`, `1.5M` or a plain number of bits per
+/// second. Units are decimal (1k = 1000 bits per second).
+fn parse_bitrate(s: &str) -> Result<NonZeroU32, String> {
+    let s = s.trim();
+    let invalid = || format!("invalid bitrate: {}", s);
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1e3),
+        Some((i, 'm' | 'M')) => (&s[..i], 1e6),
+        _ => (s, 1.0),
+    };
+    let bitrate = number.parse::<f64>().map_err(|_| invalid())? * multiplier;
+    NonZeroU32::new(bitrate as u32).ok_or_else(invalid)
+}
+
+/// Parses a preset name.
+fn parse_preset(s: &str) -> Result<Preset, String> {
+    match s.trim().to_lowercase().as_str() {
+        "audiobook" => Ok(Preset::Audiobook),
+        other => Err(format!("unknown preset: {} (expected audiobook)", other)),
+    }
+}
+
+/// Parses a cutoff date such as `2024-01-01` (midnight UTC) or an RFC 3339
+/// timestamp, or an age such as `7d` counted back from now.
+fn parse_newer_than(s: &str) -> Result<SystemTime, String> {
+    let s = s.trim();
+    if let Ok(time) = humantime::parse_rfc3339_weak(s)
+        .or_else(|_| humantime::parse_rfc3339_weak(&format!("{} 00:00:00", s)))
+    {
+        return Ok(time);
+    }
+    parse_duration(s)
+        .ok()
+        .and_then(|age| SystemTime::now().checked_sub(age))
+        .ok_or_else(|| format!("invalid date or age: {}", s))
+}
+
+/// Parses the URL of `--webhook`, which must be an http:// or https:// URL.
+fn parse_webhook(s: &str) -> Result<String, String> {
+    if s.starts_with("http://") || s.starts_with("https://") {
+        Ok(s.to_string())
+    } else {
+        Err(format!("'{}' is not an http:// or https:// URL", s))
+    }
+}
+
+/// Parses a duration such as `7d`, `45m`, `1h30m`, `90s`, `1:30:00` or a
+/// plain number of seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let invalid = || format!("invalid duration: {}", s);
+    let secs = if s.contains(':') {
+        s.split(':').try_fold(0.0, |acc, part| {
+            part.parse::<f64>()
+                .map(|v| acc * 60.0 + v)
+                .map_err(|_| invalid())
+        })?
+    } else if let Ok(secs) = s.parse::<f64>() {
+        secs
+    } else {
+        let mut secs = 0.0;
+        let mut number = String::new();
+        for c in s.chars() {
+            if c.is_ascii_digit() || c == '.' {
+                number.push(c);
+                continue;
+            }
+            let value: f64 = number.parse().map_err(|_| invalid())?;
+            number.clear();
+            secs += value
+                * match c.to_ascii_lowercase() {
+                    'd' => 86400.0,
+                    'h' => 3600.0,
+                    'm' => 60.0,
+                    's' => 1.0,
+                    _ => return Err(invalid()),
+                };
+        }
+        if !number.is_empty() {
+            return Err(invalid());
+        }
+        secs
+    };
+    if !secs.is_finite() || secs <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs_f64(secs))
 }
 
 fn main() -> Result<()> {
-    _ = pretty_env_logger::formatted_builder()
-        .filter_level(LevelFilter::Info)
-        .format_timestamp_secs()
-        .parse_default_env()
-        .try_init();
+    let cli = Cli::parse();
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let mut logger = pretty_env_logger::formatted_builder();
+    logger.filter_level(level).format_timestamp_secs();
+    if cli.log_format == LogFormat::Json {
+        logger.format(write_json_record);
+        if !cli.quiet {
+            logger.filter_module(
+                audio_batch_speedup::FILE_TARGET,
+                level.max(LevelFilter::Debug),
+            );
+        }
+    }
+    _ = logger.parse_default_env().try_init();
 
-    let args = Cli::parse();
+    if matches!(
+        cli.command,
+        Command::Speedup(_)
+            | Command::Verify { manifest: None, .. }
+            | Command::Stats { .. }
+            | Command::Serve { .. }
+    ) {
+        bootstrap_ffmpeg(cli.download_ffmpeg)?;
+    }
 
-    if !args.input.exists() {
+    match cli.command {
+        Command::Speedup(args) => speedup(*args, cli.quiet),
+        Command::Restore { backup, target } => {
+            check_folder(&backup);
+            check_folder(&target);
+            let restored = audio_batch_speedup::restore_backups(&backup, &target)?;
+            info!("Restored {} files.", restored);
+            Ok(())
+        }
+        Command::Verify {
+            input,
+            formats,
+            manifest,
+            originals,
+            speed,
+        } => {
+            check_folder(&input);
+            let options = ProcessOptions::new(1.0, formats.selected());
+            let files = match (manifest, originals) {
+                (Some(manifest), _) => audio_batch_speedup::verify_manifest(&manifest, &input)?,
+                (None, Some(originals)) => {
+                    check_folder(&originals);
+                    audio_batch_speedup::verify_against_originals(
+                        &input,
+                        &originals,
+                        speed.map(Speed::factor),
+                        &options,
+                    )?
+                }
+                (None, None) => audio_batch_speedup::verify_files(&input, &options)?,
+            };
+            let failed: Vec<_> = files.iter().filter(|f| !f.is_ok()).collect();
+            for file in &failed {
+                error!(
+                    "{}: {}",
+                    file.path.display(),
+                    file.error.as_deref().unwrap_or_default()
+                );
+            }
+            info!(
+                "Verified {} files, {} with errors.",
+                files.len(),
+                failed.len()
+            );
+            if !failed.is_empty() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Clean { input, yes } => {
+            check_folder(&input);
+            let found = audio_batch_speedup::find_temp_files(&input)?;
+            if found.is_empty() {
+                info!("No temporary files found.");
+                return Ok(());
+            }
+            for path in &found {
+                println!("{}", path.display());
+            }
+            if !yes && !confirm(&format!("Remove these {} files?", found.len()))? {
+                return Ok(());
+            }
+            for path in &found {
+                std::fs::remove_file(path)?;
+            }
+            info!("Removed {} temporary files.", found.len());
+            Ok(())
+        }
+        Command::Serve { listen, token } => serve::serve(listen, token),
+        Command::Stats { input, formats } => {
+            check_folder(&input);
+            let options = ProcessOptions::new(1.0, formats.selected());
+            let stats = audio_batch_speedup::tree_stats(&input, &options)?;
+            let total = stats.total();
+            let rows = stats
+                .per_format
+                .iter()
+                .map(|(format, s)| (format.name(), s))
+                .chain([("TOTAL", &total)]);
+            for (name, s) in rows {
+                println!(
+                    "{:<6} {:>8} files {:>12} {:>12}",
+                    name,
+                    s.count,
+                    format_bytes(s.size),
+                    format_duration(s.duration)
+                );
+            }
+            let history = audio_batch_speedup::read_history(input.join(HISTORY_NAME))?;
+            if let Some(last) = history.last() {
+                let processed: Vec<_> = history
+                    .iter()
+                    .filter(|entry| entry.status == FileStatus::Processed)
+                    .collect();
+                let saved: Duration = processed
+                    .iter()
+                    .filter_map(|entry| {
+                        Some(entry.input_duration?.saturating_sub(entry.output_duration?))
+                    })
+                    .sum();
+                println!(
+                    "History: {} files processed ({} failures), {} of listening time saved, last run {}",
+                    processed.len(),
+                    history.len() - processed.len(),
+                    format_duration(saved),
+                    humantime::format_rfc3339_seconds(last.time)
+                );
+            }
+            Ok(())
+        }
+        Command::History { input, limit } => {
+            check_folder(&input);
+            let history = audio_batch_speedup::read_history(input.join(HISTORY_NAME))?;
+            if history.is_empty() {
+                info!("No files were processed in this folder yet.");
+            }
+            let skip = limit.map_or(0, |limit| history.len().saturating_sub(limit));
+            for entry in &history[skip..] {
+                let duration = |d: Option<Duration>| d.map_or("?".to_string(), format_duration);
+                println!(
+                    "{}  {:<9} {:>6} {} -> {}  {}",
+                    humantime::format_rfc3339_seconds(entry.time),
+                    entry.status,
+                    entry.speed.map_or(String::new(), |s| format!("{}x", s)),
+                    duration(entry.input_duration),
+                    duration(entry.output_duration),
+                    entry.path.display()
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Exits with an error if `path` is not an existing folder.
+fn check_folder(path: &Path) {
+    if !path.exists() {
         error!("The specified folder does not exist.");
         std::process::exit(1);
     }
 
-    if !args.input.is_dir() {
+    if !path.is_dir() {
         error!("Please specify a folder path.");
         std::process::exit(1);
     }
+}
+
+/// Parses the name of a single audio format, e.g. `mp3`.
+fn parse_single_format(format: &str) -> Result<AudioFormat, String> {
+    match format.parse::<AudioFormat>().map_err(|e| e.to_string())? {
+        parsed if parsed.bits().count_ones() == 1 => Ok(parsed),
+        _ => Err("expected a single format, e.g. mp3".to_string()),
+    }
+}
+
+/// Parses an `--encoder` list of `format=encoder` pairs, e.g.
+/// `mp3=libmp3lame,aac=aac_at`.
+fn parse_encoders(s: &str) -> Result<EncoderMap, String> {
+    let mut encoders = EncoderMap::new();
+    for pair in s.split(',') {
+        let (format, encoder) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected format=encoder, got {:?}", pair))?;
+        if encoder.trim().is_empty() {
+            return Err(format!("no encoder given for {}", format));
+        }
+        encoders.set(parse_single_format(format.trim())?, encoder.trim());
+    }
+    Ok(encoders)
+}
+
+/// Runs the `speedup` subcommand. In `quiet` mode, the summary is only shown
+/// when confirmation is asked, and no progress bar is drawn.
+fn speedup(args: SpeedupArgs, quiet: bool) -> Result<()> {
+    if args.pipe {
+        return pipe(&args);
+    }
+    let input = args
+        .input
+        .clone()
+        .expect("Internal Error: the input is required without --pipe");
+    let playlist = audio_batch_speedup::is_playlist(&input).then(|| input.clone());
+    let folder = match &playlist {
+        Some(playlist) => {
+            if !playlist.is_file() {
+                error!("The specified playlist does not exist.");
+                std::process::exit(1);
+            }
+            playlist.parent().unwrap_or(Path::new("")).to_path_buf()
+        }
+        None => {
+            check_folder(&input);
+            input.clone()
+        }
+    };
+    if args.rewrite_playlist && playlist.is_none() {
+        error!("--rewrite-playlist requires a playlist as input.");
+        std::process::exit(1);
+    }
+    let selected_formats = args.formats.selected();
+    let speeds: Vec<f32> = args.speeds.iter().map(|speed| speed.factor()).collect();
+    let journal = folder.join(JOURNAL_NAME);
+    if !args.resume && !args.dry_run && journal.exists() {
+        warn!(
+            "Discarding the journal of an interrupted run; pass --resume to continue it instead."
+        );
+        std::fs::remove_file(&journal)?;
+    }
 
-    let mut selected_formats = AudioFormat::empty();
-    if args.formats.to_lowercase() == "all" {
-        selected_formats = AudioFormat::ALL;
+    let mut options = ProcessOptions {
+        nice: args.nice,
+        max_ffmpeg: args.max_ffmpeg,
+        ffmpeg_threads: args.ffmpeg_threads,
+        resource_guard: (args.min_battery.is_some() || args.max_temp.is_some()).then(|| {
+            ResourceGuard {
+                min_battery: args.min_battery,
+                max_temperature: args.max_temp,
+                ..ResourceGuard::new()
+            }
+        }),
+        order: args.order,
+        io_limit: args.io_limit,
+        min_size: args.min_size,
+        max_size: args.max_size,
+        modified_after: args.newer_than,
+        target_duration: args.target_duration,
+        target_bpm: args.target_bpm,
+        target_wpm: args.target_wpm,
+        min_speed: args.min_speed.map(Speed::factor),
+        max_speed: args.max_speed.map(Speed::factor),
+        quality: args.quality,
+        silence: args.silence_speed.map(|speed| {
+            let defaults = SilenceOptions::new(speed.factor());
+            SilenceOptions {
+                threshold_db: args.silence_threshold,
+                min_duration: args.silence_min_duration.unwrap_or(defaults.min_duration),
+                ..defaults
+            }
+        }),
+        trim_edges: args.trim_edges.then(|| {
+            let defaults = TrimOptions::new();
+            TrimOptions {
+                threshold_db: args.trim_threshold,
+                padding: args.trim_padding.unwrap_or(defaults.padding),
+            }
+        }),
+        sample_rate: args.sample_rate,
+        channels: args.channels,
+        bitrate: args.bitrate,
+        encoders: args.encoder.unwrap_or_default(),
+        match_bitrate: !args.no_match_bitrate,
+        output_dir: args.output,
+        name_template: args.name_template,
+        segment: args.segment,
+        replaygain: args.replaygain,
+        analyze_loudness: args.loudness,
+        dedup: args.dedup,
+        preserve_times: args.preserve_times,
+        preserve_attrs: args.preserve_attrs,
+        strip_art: args.strip_art,
+        rescale_sidecars: args.rescale_sidecars,
+        tag_filters: args.tag_filter,
+        probe_formats: args.probe_formats,
+        include_video: args.include_video,
+        archives: args.archives,
+        ignore_files: !args.no_ignore,
+        backup_dir: args.backup,
+        fail_fast: args.fail_fast,
+        busy_retries: args.busy_retries,
+        timeout: args.timeout,
+        temp_dir: args.temp_dir,
+        stage_local: args.stage_local,
+        pre_hook: args.pre_hook,
+        post_hook: args.post_hook,
+        log_file: args.log_file,
+        progress_bar: !quiet && args.progress == ProgressFormat::Bar,
+        skip_marked: !args.force,
+        record_durations: args.record_durations && args.sample.is_none(),
+        clear_readonly: args.replace_readonly,
+        journal: (!args.merge
+            && args.speeds.is_empty()
+            && args.sample.is_none()
+            && args.preview.is_none()
+            && (args.resume || !args.dry_run))
+            .then(|| journal.clone()),
+        ..ProcessOptions::new(args.speed.map_or(1.0, Speed::factor), selected_formats)
+    };
+    if let Some(preset) = args.preset {
+        preset.apply(&mut options);
+    }
+    // Samples never replace the originals
+    if args.sample.is_some() && options.output_dir.is_none() {
+        if options.segment.is_some() {
+            error!("--sample with --segment requires --output.");
+            std::process::exit(1);
+        }
+        options
+            .name_template
+            .get_or_insert_with(|| SAMPLE_TEMPLATE.to_string());
+    }
+    // Keep stdout for the JSON lines unless the summary is needed
+    let json = args.progress == ProgressFormat::Json;
+    // JSON lines and a report written to stdout must stay parseable, so the
+    // summaries go to stderr
+    let mut summary: Box<dyn Write> = if json || args.report.is_some() && args.report_file.is_none()
+    {
+        Box::new(io::stderr())
     } else {
-        for format_str in args.formats.split(',') {
-            match format_str.trim().to_lowercase().as_str() {
-                "ogg" => selected_formats |= AudioFormat::OGG,
-                "mp3" => selected_formats |= AudioFormat::MP3,
-                "wav" => selected_formats |= AudioFormat::WAV,
-                "flac" => selected_formats |= AudioFormat::FLAC,
-                "aac" => selected_formats |= AudioFormat::AAC,
-                "opus" => selected_formats |= AudioFormat::OPUS,
-                "alac" => selected_formats |= AudioFormat::ALAC,
-                "wma" => selected_formats |= AudioFormat::WMA,
-                _ => {
-                    error!(
-                        "Unsupported format specified: {}. Supported formats are: ogg, mp3, wav, flac, aac, opus, alac, wma, all.",
-                        format_str
-                    );
-                    std::process::exit(1);
-                }
+        Box::new(io::stdout())
+    };
+    let files = if args.stream {
+        if playlist.is_some() {
+            error!("--stream requires a folder.");
+            std::process::exit(1);
+        }
+        if !args.yes
+            && !confirm(&format!(
+                "Process the files in {} as they are found?",
+                input.display()
+            ))?
+        {
+            info!("Aborted.");
+            return Ok(());
+        }
+        Vec::new()
+    } else {
+        let mut files = match &playlist {
+            Some(playlist) => {
+                info!("Planning files of playlist: {}", playlist.display());
+                audio_batch_speedup::plan_playlist(playlist, &options)?
             }
+            None => {
+                info!("Planning files in folder: {}", input.display());
+                audio_batch_speedup::plan(&input, &options)?
+            }
+        };
+        let temp_files = files
+            .iter()
+            .filter(|f| f.skip_reason == Some(SkipReason::TempFile))
+            .count();
+        if temp_files > 0 {
+            warn!(
+                "Ignoring {} temporary files left by an interrupted run; remove them with `abs clean {}`.",
+                temp_files,
+                folder.display()
+            );
+        }
+        let readonly_files = files
+            .iter()
+            .filter(|f| f.skip_reason == Some(SkipReason::ReadOnly))
+            .count();
+        if readonly_files > 0 {
+            warn!(
+                "Skipping {} read-only files; use --replace-readonly to replace them.",
+                readonly_files
+            );
+        }
+        if let Some(count) = args.sample {
+            audio_batch_speedup::sample(&mut files, count);
+        }
+        warn_processed_before(&folder, &files);
+        if !(quiet || json) || !args.yes || args.dry_run {
+            print_plan_summary(&mut summary, &files, &options, &speeds)?;
+        }
+        if args.estimate {
+            print_estimate(&mut summary, &files, &options)?;
+        }
+        if args.dry_run {
+            for file in files.iter().filter(|f| f.will_process()) {
+                println!("{}", file.path.display());
+            }
+            return Ok(());
+        }
+        if let Some(length) = args.preview {
+            info!("Rendering previews into {}", args.preview_dir.display());
+            let report =
+                audio_batch_speedup::render_previews(&files, &options, length, &args.preview_dir)?;
+            print_failures(&report);
+            info!(
+                "Wrote {} previews: compare {} with {}.",
+                report.processed_count(),
+                args.preview_dir.join("original").display(),
+                args.preview_dir.join("processed").display()
+            );
+            return Ok(());
+        }
+        if !args.yes && !confirm("Proceed?")? {
+            info!("Aborted.");
+            return Ok(());
+        }
+        files
+    };
+
+    info!("Starting processing for: {}", input.display());
+    let _keys = io::stdin().is_terminal().then(|| {
+        if !quiet {
+            info!("Press p to pause, r to resume, q to stop after the files in progress.");
+        }
+        keys::spawn_key_controls(options.control.clone())
+    });
+    let start = Instant::now();
+    // The number of files of a streamed run is unknown until it ends
+    let json_progress = json.then(|| {
+        JsonProgress::start(
+            (!args.stream).then(|| files.iter().filter(|f| f.will_process()).count()),
+        )
+    });
+    let on_event = |event| {
+        if let Some(json_progress) = &json_progress {
+            json_progress.emit(event);
+        }
+    };
+    let report = if args.stream {
+        audio_batch_speedup::process_audio_files_streaming(&input, &options, on_event)?
+    } else if args.merge {
+        audio_batch_speedup::merge_planned_files(files, &options)?
+    } else if !speeds.is_empty() {
+        audio_batch_speedup::process_speed_variants(files, &options, &speeds)?
+    } else {
+        audio_batch_speedup::process_planned_files_with_events(files, &options, on_event)?
+    };
+    if let Some(json_progress) = &json_progress {
+        json_progress.finish(&report);
+    }
+    print_failures(&report);
+    if options.control.is_stopped() {
+        info!("Stopped before processing every file; run again with --resume to continue.");
+    }
+    // Keep the journal while files are left to retry with --resume
+    if report.failed_count() == 0 && !options.control.is_stopped() && journal.exists() {
+        std::fs::remove_file(&journal)?;
+    }
+    // Samples are auditions, not part of the history of the originals
+    if !args.no_history
+        && args.sample.is_none()
+        && let Err(e) =
+            audio_batch_speedup::record_history(folder.join(HISTORY_NAME), &folder, &report)
+    {
+        error!("Error recording the history of {}: {}", folder.display(), e);
+    }
+    if let Some(playlist) = playlist.as_ref().filter(|_| args.rewrite_playlist) {
+        match audio_batch_speedup::rewrite_playlist(playlist, &report) {
+            Ok(rewritten) => info!(
+                "Pointed {} entries of {} at the processed files.",
+                rewritten,
+                playlist.display()
+            ),
+            Err(e) => error!("Error rewriting {}: {}", playlist.display(), e),
+        }
+    }
+    info!("Processing complete.");
+    if quiet || json {
+        info!(
+            "Processed {} files: {} -> {} ({} of listening time saved), {} -> {}.",
+            report.processed_count(),
+            format_duration(report.total_input_duration()),
+            format_duration(report.total_output_duration()),
+            format_duration(report.time_saved()),
+            format_bytes(report.bytes_before()),
+            format_bytes(report.bytes_after()),
+        );
+    } else {
+        print_format_table(&mut summary, &report)?;
+    }
+    if report.clipping_count() > 0 {
+        warn!(
+            "{} processed files clip (true peak above 0 dBTP); see --report for their levels.",
+            report.clipping_count()
+        );
+    }
+    if report.locked_count() > 0 {
+        warn!(
+            "{} files were still in use by another program; close it and run again.",
+            report.locked_count()
+        );
+    }
+    if report.changing_count() > 0 {
+        warn!(
+            "Skipped {} files that were still being written; run again once they are complete.",
+            report.changing_count()
+        );
+    }
+
+    if args.checksums.is_some() {
+        let root = options.output_dir.clone().unwrap_or(folder);
+        let manifest = args.manifest.unwrap_or_else(|| root.join(MANIFEST_NAME));
+        match audio_batch_speedup::write_manifest(&manifest, &root, &report) {
+            Ok(count) => info!(
+                "Wrote the checksums of {} files to {}.",
+                count,
+                manifest.display()
+            ),
+            Err(e) => error!("Error writing {}: {}", manifest.display(), e),
+        }
+    }
+    if let Some(format) = args.report {
+        let exported = match format {
+            ReportFormat::Json => export::report_json(&input, &report, start.elapsed()) + "\n",
+            ReportFormat::Csv => export::report_csv(&report),
+        };
+        match &args.report_file {
+            Some(path) => std::fs::write(path, exported)?,
+            None => io::stdout().write_all(exported.as_bytes())?,
+        }
+    }
+    if args.notify {
+        notify::notify_desktop(&report, start.elapsed());
+    }
+    if let Some(webhook) = &args.webhook {
+        let json = export::report_json(&input, &report, start.elapsed());
+        if let Err(e) = notify::post_webhook(webhook, &json) {
+            warn!("Error sending the report to {}: {}", webhook, e);
         }
     }
 
-    if selected_formats.is_empty() {
-        error!("No valid audio formats selected for processing.");
+    if report.failed_count() > args.max_failures {
         std::process::exit(1);
     }
+    Ok(())
+}
 
-    info!("Starting processing for folder: {}", args.input.display());
-    audio_batch_speedup::process_audio_files(&args.input, args.speed, selected_formats)?;
-    info!("Processing complete.");
+/// Runs the `speedup` subcommand with `--pipe`, from stdin to stdout.
+fn pipe(args: &SpeedupArgs) -> Result<()> {
+    let options = ProcessOptions {
+        nice: args.nice,
+        quality: args.quality,
+        sample_rate: args.sample_rate,
+        channels: args.channels,
+        bitrate: args.bitrate,
+        encoders: args.encoder.clone().unwrap_or_default(),
+        ..ProcessOptions::new(args.speed.map_or(1.0, Speed::factor), AudioFormat::ALL)
+    };
+    audio_batch_speedup::process_stream(
+        io::stdin(),
+        io::stdout().lock(),
+        args.input_format
+            .expect("Internal Error: --pipe requires --input-format"),
+        args.output_format
+            .expect("Internal Error: --pipe requires --output-format"),
+        &options,
+    )?;
+    Ok(())
+}
+
+/// Number of lines of ffmpeg's error output shown per failed file.
+const STDERR_EXCERPT_LINES: usize = 3;
+
+/// Maximum length of the excerpt of ffmpeg's error output, in characters.
+const STDERR_EXCERPT_CHARS: usize = 300;
+
+/// Logs the failed files of `report`, each with an excerpt of what ffmpeg
+/// wrote to its standard error.
+fn print_failures(report: &ProcessReport) {
+    let failed: Vec<_> = report
+        .files
+        .iter()
+        .filter(|f| f.status == FileStatus::Failed)
+        .collect();
+    if failed.is_empty() {
+        return;
+    }
+    error!("{} files failed:", failed.len());
+    for file in failed {
+        match file.stderr.as_deref() {
+            Some(stderr) => error!("  {}: {}", file.path.display(), stderr_excerpt(stderr)),
+            None => error!("  {}", file.path.display()),
+        }
+    }
+}
+
+/// Returns the last lines of `stderr`, where ffmpeg usually explains the
+/// failure, on a single line and truncated.
+fn stderr_excerpt(stderr: &str) -> String {
+    let lines: Vec<_> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let excerpt = lines[lines.len().saturating_sub(STDERR_EXCERPT_LINES)..].join(" | ");
+    if excerpt.chars().count() > STDERR_EXCERPT_CHARS {
+        let truncated: String = excerpt.chars().take(STDERR_EXCERPT_CHARS).collect();
+        format!("{}...", truncated)
+    } else {
+        excerpt
+    }
+}
+
+/// Warns if some of the files to process were already processed by an
+/// earlier run, according to the history of `folder`.
+fn warn_processed_before(folder: &Path, files: &[PlannedFile]) {
+    let history = match audio_batch_speedup::read_history(folder.join(HISTORY_NAME)) {
+        Ok(history) => history,
+        Err(e) => {
+            warn!("Error reading the history of {}: {}", folder.display(), e);
+            return;
+        }
+    };
+    // Later entries replace earlier ones, keeping the last run of each file
+    let processed: HashMap<&Path, &HistoryEntry> = history
+        .iter()
+        .filter(|entry| entry.status == FileStatus::Processed)
+        .map(|entry| (entry.path.as_path(), entry))
+        .collect();
+    let again: Vec<&HistoryEntry> = files
+        .iter()
+        .filter(|file| file.will_process())
+        .filter_map(|file| {
+            let path = file.path.strip_prefix(folder).unwrap_or(&file.path);
+            processed.get(path).copied()
+        })
+        .collect();
+    if let Some(last) = again.iter().max_by_key(|entry| entry.time) {
+        warn!(
+            "{} of the files to process were already processed by an earlier run, last on {} at {}x; see `abs history {}`.",
+            again.len(),
+            humantime::format_rfc3339_seconds(last.time),
+            last.speed.map_or("?".to_string(), |s| s.to_string()),
+            folder.display()
+        );
+    }
+}
+
+/// Name of the journal of completed files, kept in the input folder until the
+/// run finishes without failures.
+const JOURNAL_NAME: &str = ".absu-journal";
 
+/// The audio formats selected with `-f, --format`.
+#[derive(Args)]
+struct FormatSelection {
+    /// Audio formats to process; repeat the flag or separate formats with commas (e.g.,
+    /// ogg,mp3). Prefix a format with ! to leave it out (e.g., --format all --format '!wav').
+    #[arg(
+        short = 'f',
+        long = "format",
+        visible_alias = "formats",
+        value_name = "FORMAT",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "all"
+    )]
+    formats: Vec<FormatArg>,
+}
+
+impl FormatSelection {
+    /// Returns the selected formats, applying the values in order. If all are
+    /// negated, they are left out of all formats. Exits with an error if no
+    /// format is left.
+    fn selected(&self) -> AudioFormat {
+        let start = if self.formats.iter().all(|f| f.selection().0) {
+            AudioFormat::ALL
+        } else {
+            AudioFormat::empty()
+        };
+        let selected = self
+            .formats
+            .iter()
+            .fold(start, |selected, f| match f.selection() {
+                (true, format) => selected.difference(format),
+                (false, format) => selected.union(format),
+            });
+        if selected.is_empty() {
+            error!("No audio formats selected for processing.");
+            std::process::exit(1);
+        }
+        selected
+    }
+}
+
+/// A value of `-f, --format`: a format, `all`, or a format to leave out.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FormatArg {
+    All,
+    Ogg,
+    Mp3,
+    Wav,
+    Flac,
+    Aac,
+    Opus,
+    Alac,
+    Wma,
+    #[value(name = "!ogg")]
+    NotOgg,
+    #[value(name = "!mp3")]
+    NotMp3,
+    #[value(name = "!wav")]
+    NotWav,
+    #[value(name = "!flac")]
+    NotFlac,
+    #[value(name = "!aac")]
+    NotAac,
+    #[value(name = "!opus")]
+    NotOpus,
+    #[value(name = "!alac")]
+    NotAlac,
+    #[value(name = "!wma")]
+    NotWma,
+}
+
+impl FormatArg {
+    /// Returns whether the value leaves its formats out, and the formats.
+    fn selection(self) -> (bool, AudioFormat) {
+        let value = self
+            .to_possible_value()
+            .expect("Internal Error: no format value is skipped");
+        let name = value.get_name();
+        let formats = name
+            .trim_start_matches('!')
+            .parse()
+            .expect("Internal Error: format values are format names");
+        (name.starts_with('!'), formats)
+    }
+}
+
+/// Output format of the progress of a run.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgressFormat {
+    /// A progress bar on the terminal
+    Bar,
+    /// JSON lines on stdout
+    Json,
+}
+
+/// Format of the log.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Colored lines for humans
+    Pretty,
+    /// JSON lines, with the structured fields of each record
+    Json,
+}
+
+/// Writes a log record as a JSON object on a single line, with its
+/// key-values as additional fields.
+fn write_json_record(
+    buf: &mut pretty_env_logger::env_logger::fmt::Formatter,
+    record: &log::Record,
+) -> io::Result<()> {
+    let mut fields = vec![
+        ("ts", json_string(&buf.timestamp_seconds().to_string())),
+        ("level", json_string(record.level().as_str())),
+        ("target", json_string(record.target())),
+        ("message", json_string(&record.args().to_string())),
+    ];
+    let mut visitor = JsonFields(Vec::new());
+    _ = record.key_values().visit(&mut visitor);
+    fields.extend(
+        visitor
+            .0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.clone())),
+    );
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), value))
+        .collect();
+    writeln!(buf, "{{{}}}", fields.join(","))
+}
+
+/// Collects the key-values of a log record as encoded JSON values.
+struct JsonFields(Vec<(String, String)>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonFields {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> std::result::Result<(), log::kv::Error> {
+        let mut encoded = JsonValue(String::new());
+        value.visit(&mut encoded)?;
+        self.0.push((key.as_str().to_string(), encoded.0));
+        Ok(())
+    }
+}
+
+/// Encodes a single log value as JSON.
+struct JsonValue(String);
+
+impl<'v> log::kv::VisitValue<'v> for JsonValue {
+    fn visit_any(&mut self, value: log::kv::Value) -> std::result::Result<(), log::kv::Error> {
+        self.0 = json_string(&value.to_string());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> std::result::Result<(), log::kv::Error> {
+        self.0 = "null".to_string();
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> std::result::Result<(), log::kv::Error> {
+        self.0 = value.to_string();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> std::result::Result<(), log::kv::Error> {
+        self.0 = value.to_string();
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> std::result::Result<(), log::kv::Error> {
+        self.0 = if value.is_finite() {
+            value.to_string()
+        } else {
+            "null".to_string()
+        };
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> std::result::Result<(), log::kv::Error> {
+        self.0 = value.to_string();
+        Ok(())
+    }
+}
+
+/// Hash algorithm of the checksum manifest.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ChecksumAlgorithm {
+    /// SHA-256
+    Sha256,
+}
+
+/// Format of the final report.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    /// A JSON object with the totals and a `files` array
+    Json,
+    /// One row per file
+    Csv,
+}
+
+/// Prints processing events as JSON lines on stdout, with the overall
+/// percentage and the estimated remaining time.
+struct JsonProgress {
+    /// The number of files to process, if known.
+    total: Option<usize>,
+    start: Instant,
+    /// Fractions of the files in progress, and the number of finished files.
+    state: Mutex<(HashMap<PathBuf, f32>, usize)>,
+}
+
+impl JsonProgress {
+    /// Prints the `start` event of a run of `total` files, or of an unknown
+    /// number of files.
+    fn start(total: Option<usize>) -> Self {
+        print_json_line(&[
+            ("event", json_string("start")),
+            ("total", total.map_or("null".to_string(), |t| t.to_string())),
+        ]);
+        Self {
+            total,
+            start: Instant::now(),
+            state: Mutex::new((HashMap::new(), 0)),
+        }
+    }
+
+    /// Prints `event` as a JSON line.
+    fn emit(&self, event: ProcessEvent) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (fractions, finished) = &mut *state;
+        let mut fields = match event {
+            ProcessEvent::FileStarted { path } => {
+                fractions.insert(path.clone(), 0.0);
+                vec![
+                    ("event", json_string("file_started")),
+                    ("path", json_path(&path)),
+                ]
+            }
+            ProcessEvent::Progress { path, fraction } => {
+                fractions.insert(path.clone(), fraction);
+                vec![
+                    ("event", json_string("progress")),
+                    ("path", json_path(&path)),
+                    ("fraction", fraction.to_string()),
+                ]
+            }
+            ProcessEvent::FileFinished { report } => {
+                fractions.remove(&report.path);
+                *finished += 1;
+                vec![
+                    ("event", json_string("file_done")),
+                    ("path", json_path(&report.path)),
+                    ("status", json_string(&report.status.to_string())),
+                    (
+                        "speed",
+                        report.speed.map_or("null".to_string(), |s| s.to_string()),
+                    ),
+                ]
+            }
+            ProcessEvent::FileFailed { path, stderr } => {
+                fractions.remove(&path);
+                *finished += 1;
+                vec![
+                    ("event", json_string("file_failed")),
+                    ("path", json_path(&path)),
+                    ("stderr", json_string(&stderr)),
+                ]
+            }
+        };
+
+        let done = *finished as f64 + fractions.values().map(|&f| f as f64).sum::<f64>();
+        let ratio = self
+            .total
+            .map(|total| if total == 0 { 1.0 } else { done / total as f64 });
+        let eta = ratio
+            .filter(|&ratio| ratio > 0.0)
+            .map(|ratio| self.start.elapsed().as_secs_f64() * (1.0 - ratio) / ratio);
+        fields.push((
+            "percent",
+            ratio.map_or("null".to_string(), |ratio| format!("{:.1}", ratio * 100.0)),
+        ));
+        fields.push((
+            "eta_secs",
+            eta.map_or("null".to_string(), |eta| format!("{:.1}", eta)),
+        ));
+        print_json_line(&fields);
+    }
+
+    /// Prints the `finish` event with the counts of `report`.
+    fn finish(&self, report: &ProcessReport) {
+        print_json_line(&[
+            ("event", json_string("finish")),
+            ("processed", report.processed_count().to_string()),
+            ("skipped", report.skipped_count().to_string()),
+            ("failed", report.failed_count().to_string()),
+            (
+                "elapsed_secs",
+                format!("{:.1}", self.start.elapsed().as_secs_f64()),
+            ),
+        ]);
+    }
+}
+
+/// Prints a JSON object made of already encoded values on a single line.
+fn print_json_line(fields: &[(&str, String)]) {
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{}", json_string(key), value))
+        .collect();
+    let mut stdout = io::stdout().lock();
+    _ = writeln!(stdout, "{{{}}}", fields.join(","));
+    _ = stdout.flush();
+}
+
+/// Names the outputs of `--sample` next to their originals.
+const SAMPLE_TEMPLATE: &str = "{stem}_sample_{speed}x.{ext}";
+
+/// Number of files processed by `--estimate`.
+const ESTIMATE_SAMPLE_SIZE: usize = 5;
+
+/// Processes a sample of the plan and prints the projected cost of the run
+/// to `out`.
+fn print_estimate(
+    out: &mut dyn Write,
+    files: &[PlannedFile],
+    options: &ProcessOptions,
+) -> Result<()> {
+    info!(
+        "Processing {} sample files for the estimate...",
+        ESTIMATE_SAMPLE_SIZE
+    );
+    match audio_batch_speedup::estimate(files, options, ESTIMATE_SAMPLE_SIZE)? {
+        Some(estimate) => writeln!(
+            out,
+            "Estimated time: {} with {} workers ({:.1}s of audio per second per worker), output size: {}",
+            format_duration(estimate.wall_time),
+            estimate.workers,
+            estimate.throughput,
+            format_bytes(estimate.output_size),
+        )?,
+        None => writeln!(
+            out,
+            "Could not estimate the run: no sample file was processed."
+        )?,
+    }
     Ok(())
 }
+
+/// Prints to `out` what a run is about to do: the number and size of files to
+/// be processed, a per-format breakdown, and the speed (or the `speeds` of the
+/// variants to produce).
+fn print_plan_summary(
+    out: &mut dyn Write,
+    files: &[PlannedFile],
+    options: &ProcessOptions,
+    speeds: &[f32],
+) -> io::Result<()> {
+    let to_process: Vec<_> = files.iter().filter(|f| f.will_process()).collect();
+    let total_size: u64 = to_process.iter().map(|f| f.size).sum();
+    writeln!(
+        out,
+        "{} files to process ({}), {} skipped.",
+        to_process.len(),
+        format_bytes(total_size),
+        files.len() - to_process.len()
+    )?;
+
+    let mut breakdown: BTreeMap<&str, usize> = BTreeMap::new();
+    for file in &to_process {
+        let name = match file.format {
+            _ if file.video => "VIDEO",
+            _ if file.archive => "ARCHIVE",
+            Some(format) => format.name(),
+            None => "?",
+        };
+        *breakdown.entry(name).or_default() += 1;
+    }
+    let breakdown: Vec<_> = breakdown
+        .iter()
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect();
+    if !breakdown.is_empty() {
+        writeln!(out, "Formats: {}", breakdown.join(", "))?;
+    }
+
+    match (
+        options.target_duration,
+        options.target_wpm,
+        options.target_bpm,
+    ) {
+        (Some(target), _, _) => writeln!(out, "Target duration: {}", format_duration(target)),
+        (None, Some(wpm), _) => writeln!(out, "Target speech rate: {} WPM", wpm),
+        (None, None, Some(bpm)) => writeln!(out, "Target tempo: {} BPM", bpm),
+        (None, None, None) if !speeds.is_empty() => {
+            let speeds: Vec<_> = speeds.iter().map(|speed| format!("{}x", speed)).collect();
+            writeln!(out, "Speeds: {}", speeds.join(", "))
+        }
+        (None, None, None) => writeln!(out, "Speed: {}x", options.speed),
+    }
+}
+
+/// Prints the counts, durations and sizes of `report` per format to `out`,
+/// followed by the totals of the run.
+fn print_format_table(out: &mut dyn Write, report: &ProcessReport) -> io::Result<()> {
+    let row = |summary: &FormatSummary| {
+        [
+            summary.processed.to_string(),
+            summary.skipped.to_string(),
+            summary.failed.to_string(),
+            format!(
+                "{} -> {}",
+                format_duration(summary.input_duration),
+                format_duration(summary.output_duration)
+            ),
+            format!(
+                "{} -> {}",
+                format_bytes(summary.bytes_before),
+                format_bytes(summary.bytes_after)
+            ),
+        ]
+    };
+    let total = FormatSummary {
+        format: None,
+        processed: report.processed_count(),
+        skipped: report.skipped_count(),
+        failed: report.failed_count(),
+        input_duration: report.total_input_duration(),
+        output_duration: report.total_output_duration(),
+        bytes_before: report.bytes_before(),
+        bytes_after: report.bytes_after(),
+    };
+    let mut rows: Vec<(&str, [String; 5])> = report
+        .by_format()
+        .iter()
+        .map(|summary| (summary.format.map_or("?", AudioFormat::name), row(summary)))
+        .collect();
+    rows.push(("Total", row(&total)));
+
+    let header = ["Processed", "Skipped", "Failed", "Duration", "Size"];
+    let mut widths = header.map(str::len);
+    for (_, cells) in &rows {
+        for (width, cell) in widths.iter_mut().zip(cells) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut print_row = |name: &str, cells: &[&str]| {
+        let cells: Vec<_> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:>width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{:<6}  {}", name, cells.join("  "))
+    };
+    print_row("Format", &header)?;
+    for (name, cells) in &rows {
+        print_row(name, &cells.each_ref().map(String::as_str))?;
+    }
+    writeln!(
+        out,
+        "{} of listening time saved.",
+        format_duration(report.time_saved())
+    )
+}
+
+/// Downloads ffmpeg if it cannot be found, when `download` is set or the user
+/// agrees to it. Without a terminal to ask on, nothing is done and the run
+/// fails with the missing ffmpeg.
+fn bootstrap_ffmpeg(download: bool) -> Result<()> {
+    if audio_batch_speedup::ensure_ffmpeg().is_ok() {
+        return Ok(());
+    }
+    let Some(dir) = audio_batch_speedup::ffmpeg_dir() else {
+        return Ok(());
+    };
+    let ask = || {
+        io::stdin().is_terminal()
+            && confirm(&format!(
+                "ffmpeg was not found. Download a static build into {}?",
+                dir.display()
+            ))
+            .unwrap_or(false)
+    };
+    if download || ask() {
+        info!("Downloading ffmpeg into {}", dir.display());
+        audio_batch_speedup::download_ffmpeg()?;
+        info!("ffmpeg is ready.");
+    }
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no. The question
+/// goes to stderr, so that it never mixes with a report piped from stdout.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Formats a duration as `H:MM:SS`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Formats a byte count with a binary unit suffix, e.g. `1.5 GiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1:30:00"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        for invalid in ["0", "-5s", "1x", ""] {
+            assert!(
+                parse_duration(invalid).is_err(),
+                "{:?} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("120"), Ok(120.0));
+        assert_eq!(parse_rate("92.5"), Ok(92.5));
+        for invalid in ["0", "-120", "NaN", "inf", "fast"] {
+            assert!(parse_rate(invalid).is_err(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_tag_filters() {
+        let filter = parse_tag_filter("genre=Podcast|Audiobook").unwrap();
+        assert_eq!(filter.key, "genre");
+        assert_eq!(filter.values, ["Podcast", "Audiobook"]);
+        assert!(!filter.negated);
+        let filter = parse_tag_filter("genre != Music").unwrap();
+        assert_eq!(filter.key, "genre");
+        assert_eq!(filter.values, ["Music"]);
+        assert!(filter.negated);
+        for invalid in ["genre", "=Podcast", "!=Music", ""] {
+            assert!(
+                parse_tag_filter(invalid).is_err(),
+                "{:?} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("10MiB"), Ok(10 << 20));
+        assert_eq!(parse_size("500k"), Ok(500 << 10));
+        assert_eq!(parse_size("1.5G"), Ok(3 << 29));
+        for invalid in ["-5k", "1x", ""] {
+            assert!(parse_size(invalid).is_err(), "{:?} was accepted", invalid);
+        }
+    }
+}
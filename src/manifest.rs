@@ -0,0 +1,128 @@
+//! Checksum manifests of the produced files, to verify their integrity
+//! later, e.g. after archiving them.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::par::*;
+use crate::{Error, FileStatus, ProcessReport, Result, VerifiedFile, detect_audio_format, sha256};
+
+/// Default name of a manifest, kept at the root of the output folder.
+pub const MANIFEST_NAME: &str = "abs-checksums.tsv";
+
+/// Header line of a manifest, naming its columns.
+const HEADER: &str = "sha256\tduration_secs\tpath";
+
+/// Writes a manifest of the files produced in `report` to `manifest`: one
+/// line per output with its SHA-256 digest, its duration in seconds (empty
+/// if unknown) and its path, separated by tabs. Paths under `root` are
+/// written relative to it, so the tree can be moved along with the manifest.
+///
+/// Every part of a segmented output is listed.
+///
+/// # Arguments
+///
+/// * `manifest` - The path of the manifest to write. An existing manifest is replaced.
+/// * `root` - The folder the listed paths are relative to, usually the output folder.
+/// * `report` - The report of the run that produced the files.
+///
+/// # Returns
+///
+/// * `Result<usize>` - The number of listed files.
+pub fn write_manifest(
+    manifest: impl AsRef<Path>,
+    root: impl AsRef<Path>,
+    report: &ProcessReport,
+) -> Result<usize> {
+    let root = root.as_ref();
+    let lines: Vec<String> = report
+        .files
+        .par_iter()
+        .filter(|file| file.status == FileStatus::Processed)
+        .flat_map_iter(|file| {
+            if file.output_parts.is_empty() {
+                let whole = file
+                    .output_path
+                    .clone()
+                    .map(|path| (path, file.output_duration));
+                whole.into_iter().collect()
+            } else {
+                file.output_parts.clone()
+            }
+        })
+        .map(|(path, duration)| {
+            let path = path.as_path();
+            let digest = sha256::sha256_file(path)?;
+            let path = path.strip_prefix(root).unwrap_or(path);
+            let duration = duration.map_or(String::new(), |d| format!("{:.3}", d.as_secs_f64()));
+            Ok(format!("{}\t{}\t{}", digest, duration, path.display()))
+        })
+        .collect::<std::io::Result<_>>()?;
+    let mut content = String::new();
+    writeln!(content, "{}", HEADER).expect("writing to a String cannot fail");
+    for line in &lines {
+        writeln!(content, "{}", line).expect("writing to a String cannot fail");
+    }
+    std::fs::write(manifest, content)?;
+    Ok(lines.len())
+}
+
+/// Checks the files listed in the manifest at `manifest`, with relative
+/// paths resolved against `root`, reporting files that are missing or whose
+/// content changed since the manifest was written. The durations are the
+/// ones recorded in the manifest.
+///
+/// # Returns
+///
+/// * `Result<Vec<VerifiedFile>>` - One entry per listed file, or [`Error::Verification`] if
+///   `manifest` is not a manifest.
+pub fn verify_manifest(
+    manifest: impl AsRef<Path>,
+    root: impl AsRef<Path>,
+) -> Result<Vec<VerifiedFile>> {
+    let manifest = manifest.as_ref();
+    let root = root.as_ref();
+    let content = std::fs::read_to_string(manifest)?;
+    let mut lines = content.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(Error::Verification {
+            path: manifest.to_path_buf(),
+            message: "not a checksum manifest".to_string(),
+        });
+    }
+    let entries = lines
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut columns = line.splitn(3, '\t');
+            match (columns.next(), columns.next(), columns.next()) {
+                (Some(digest), Some(duration), Some(path)) => Ok((
+                    digest,
+                    // Negative or non-finite durations of an edited manifest are ignored
+                    duration
+                        .parse()
+                        .ok()
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+                    root.join(path),
+                )),
+                _ => Err(Error::Verification {
+                    path: manifest.to_path_buf(),
+                    message: format!("malformed line: {}", line),
+                }),
+            }
+        })
+        .collect::<Result<Vec<(&str, Option<Duration>, PathBuf)>>>()?;
+    Ok(entries
+        .into_par_iter()
+        .map(|(digest, duration, path)| VerifiedFile {
+            error: match sha256::sha256_file(&path) {
+                Ok(actual) if actual == digest => None,
+                Ok(_) => Some("checksum mismatch".to_string()),
+                Err(e) => Some(format!("cannot read the file: {}", e)),
+            },
+            format: detect_audio_format(&path),
+            duration,
+            path,
+        })
+        .collect())
+}
@@ -0,0 +1,343 @@
+//! The marker tag written into processed files, so they are not sped up twice.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::Command;
+
+use crate::detect;
+
+/// Name of the tag recording the speed a file was sped up by, e.g.
+/// `ABSU_SPEED=1.5`. It is a tag of its own, so the user's tags are kept.
+pub(crate) const MARKER_TAG: &str = "ABSU_SPEED";
+
+/// Prefix of the marker earlier versions wrote into the `comment` tag, e.g.
+/// `absu:1.5x`, still recognized.
+const LEGACY_PREFIX: &str = "absu:";
+
+/// Adds the arguments writing the marker of `speed` to the ffmpeg `command`
+/// writing `output`, at the container level and on the audio streams, since
+/// Ogg-based formats keep their tags there. MP4 outputs only keep a tag of
+/// their own with `use_metadata_tags`.
+pub(crate) fn add_marker(command: &mut Command, speed: f32, output: &Path) {
+    let marker = format!("{}={}", MARKER_TAG, speed);
+    command
+        .args(["-map_metadata", "0"])
+        .arg("-metadata")
+        .arg(&marker)
+        .arg("-metadata:s:a")
+        .arg(&marker);
+    let mp4 = output
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| {
+            ["m4a", "m4b", "mp4", "mov"]
+                .iter()
+                .any(|mp4| e.eq_ignore_ascii_case(mp4))
+        });
+    if mp4 {
+        command.args(["-movflags", "use_metadata_tags"]);
+    }
+}
+
+/// Returns whether the file at `path` may carry the marker, i.e. its name
+/// appears in the tags of the file, so that the tags of the (many) files
+/// without it are not read with ffprobe. Only the part of the file holding
+/// its tags is read, see [`read_tag_region`]. Unreadable files, and files of
+/// unknown layout, may.
+pub(crate) fn may_be_marked(path: &Path) -> bool {
+    match File::open(path).and_then(|mut file| read_tag_region(&mut file)) {
+        Ok(Some(tags)) => {
+            contains(&tags, MARKER_TAG.as_bytes()) || contains(&tags, LEGACY_PREFIX.as_bytes())
+        }
+        Ok(None) | Err(_) => true,
+    }
+}
+
+/// Reads the parts of `file` that hold its tags, found from the layout its
+/// magic bytes tell: the text and comment frames of ID3 tags, the Vorbis
+/// comments of FLAC and Ogg files, the `udta` and `meta` boxes of MP4 files,
+/// the `LIST` chunks of WAV files and the header object of WMA files. Audio
+/// data, and cover art where it can be told apart, are skipped.
+///
+/// # Returns
+///
+/// * `io::Result<Option<Vec<u8>>>` - The tag regions one after the other, or `None` if the
+///   layout of the file is unknown.
+fn read_tag_region(file: &mut File) -> io::Result<Option<Vec<u8>>> {
+    let len = file.metadata()?.len();
+    let mut magic = [0; 12];
+    file.read_exact(&mut magic)?;
+    let mut tags = Vec::new();
+    if &magic[0..3] == b"ID3" || (magic[0] == 0xFF && (magic[1] & 0xF6) == 0xF2) {
+        if &magic[0..3] == b"ID3" {
+            read_id3v2(file, &magic, &mut tags)?;
+        }
+        // An ID3v1 tag, whose comment may hold the legacy marker
+        if len >= 128 + 12 {
+            file.seek(SeekFrom::End(-128))?;
+            file.take(128).read_to_end(&mut tags)?;
+        }
+    } else if &magic[0..4] == b"fLaC" {
+        read_flac_comments(file, len, &mut tags)?;
+    } else if &magic[0..4] == b"OggS" {
+        read_ogg_comments(file, &mut tags)?;
+    } else if &magic[4..8] == b"ftyp" {
+        read_mp4_metadata(file, len, &mut tags)?;
+    } else if &magic[0..4] == b"RIFF" && &magic[8..12] == b"WAVE" {
+        read_riff_lists(file, len, &mut tags)?;
+    } else if magic[0..4] == [0x30, 0x26, 0xB2, 0x75] {
+        // The ASF header object, holding the metadata, starts the file
+        let mut size = [0; 8];
+        read_at(file, 16, &mut size)?;
+        read_range(file, 0, u64::from_le_bytes(size), &mut tags)?;
+    } else {
+        return Ok(None);
+    }
+    Ok(Some(tags))
+}
+
+/// Appends the bytes of `file` in `start..end` (or up to its end) to `out`.
+fn read_range(file: &mut File, start: u64, end: u64, out: &mut Vec<u8>) -> io::Result<()> {
+    file.seek(SeekFrom::Start(start))?;
+    file.take(end.saturating_sub(start)).read_to_end(out)?;
+    Ok(())
+}
+
+/// Fills `buf` with the bytes of `file` from `start` on.
+fn read_at(file: &mut File, start: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(buf)
+}
+
+/// Appends the text (`T...`) and comment frames of the ID3v2 tag starting
+/// `file`, whose header is at the start of `magic`, skipping pictures.
+fn read_id3v2(file: &mut File, magic: &[u8; 12], out: &mut Vec<u8>) -> io::Result<()> {
+    let syncsafe = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .fold(0u64, |size, &b| size << 7 | (b & 0x7F) as u64)
+    };
+    let (version, flags) = (magic[3], magic[5]);
+    let tag_end = 10 + syncsafe(&magic[6..10]);
+    // Unsynchronized tags, extended headers and ID3v2.2 frames are read whole
+    if version < 3 || flags & 0xC0 != 0 {
+        return read_range(file, 10, tag_end, out);
+    }
+    let mut pos = 10;
+    while pos + 10 <= tag_end {
+        let mut header = [0; 10];
+        read_at(file, pos, &mut header)?;
+        if header[0] == 0 {
+            // Padding
+            break;
+        }
+        let size = if version == 4 {
+            syncsafe(&header[4..8])
+        } else {
+            u32::from_be_bytes(header[4..8].try_into().unwrap()) as u64
+        };
+        let content = pos + 10;
+        if header[0] == b'T' || &header[0..4] == b"COMM" {
+            read_range(file, content, (content + size).min(tag_end), out)?;
+        }
+        pos = content + size;
+    }
+    Ok(())
+}
+
+/// Appends the `VORBIS_COMMENT` metadata blocks of the FLAC `file`.
+fn read_flac_comments(file: &mut File, len: u64, out: &mut Vec<u8>) -> io::Result<()> {
+    const VORBIS_COMMENT: u8 = 4;
+    let mut pos = 4;
+    while pos + 4 <= len {
+        let mut header = [0; 4];
+        read_at(file, pos, &mut header)?;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        let content = pos + 4;
+        if header[0] & 0x7F == VORBIS_COMMENT {
+            read_range(file, content, content + size, out)?;
+        }
+        if header[0] & 0x80 != 0 {
+            // The last metadata block
+            break;
+        }
+        pos = content + size;
+    }
+    Ok(())
+}
+
+/// Appends the comment header of the Ogg `file`, its second packet, which
+/// starts on the second page and may continue over the following ones.
+fn read_ogg_comments(file: &mut File, out: &mut Vec<u8>) -> io::Result<()> {
+    let mut pos = 0;
+    let mut packet = 0;
+    while packet < 2 {
+        let mut header = [0; 27];
+        read_at(file, pos, &mut header)?;
+        if &header[0..4] != b"OggS" {
+            break;
+        }
+        let mut lacing = vec![0; header[26] as usize];
+        file.read_exact(&mut lacing)?;
+        let mut data_pos = pos + 27 + lacing.len() as u64;
+        for &segment in &lacing {
+            if packet == 1 {
+                read_range(file, data_pos, data_pos + segment as u64, out)?;
+            }
+            data_pos += segment as u64;
+            // A segment shorter than 255 bytes ends its packet
+            if segment < 255 {
+                packet += 1;
+                if packet == 2 {
+                    break;
+                }
+            }
+        }
+        pos = pos + 27 + lacing.len() as u64 + lacing.iter().map(|&s| s as u64).sum::<u64>();
+    }
+    Ok(())
+}
+
+/// Appends the `udta` and `meta` boxes of the movie and of its tracks in
+/// the MP4 `file`, without the sample tables.
+fn read_mp4_metadata(file: &mut File, len: u64, out: &mut Vec<u8>) -> io::Result<()> {
+    let Some((moov_start, moov_end)) = detect::find_box(file, 0, len, b"moov") else {
+        return Ok(());
+    };
+    let mut pos = moov_start;
+    while let Some((start, end, name)) = detect::read_box_header(file, pos, moov_end) {
+        match &name {
+            b"udta" | b"meta" => read_range(file, start, end, out)?,
+            b"trak" => {
+                if let Some((udta_start, udta_end)) = detect::find_box(file, start, end, b"udta") {
+                    read_range(file, udta_start, udta_end, out)?;
+                }
+            }
+            _ => {}
+        }
+        pos = end;
+    }
+    Ok(())
+}
+
+/// Appends the `LIST` chunks (e.g., `LIST/INFO`) of the RIFF `file`.
+fn read_riff_lists(file: &mut File, len: u64, out: &mut Vec<u8>) -> io::Result<()> {
+    let mut pos = 12;
+    while pos + 8 <= len {
+        let mut header = [0; 8];
+        read_at(file, pos, &mut header)?;
+        let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+        let content = pos + 8;
+        if &header[0..4] == b"LIST" {
+            read_range(file, content, content + size, out)?;
+        }
+        // Chunks are padded to an even size
+        pos = content + size + (size & 1);
+    }
+    Ok(())
+}
+
+/// Returns whether `haystack` contains `needle`, ignoring ASCII case.
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// Returns whether `tags`, as read by
+/// [`read_tags`](crate::tags::read_tags), carry the marker, either at the
+/// container level or on one of its streams (as in Ogg files).
+pub(crate) fn is_marked(tags: &[(String, String)]) -> bool {
+    tags.iter().any(|(key, value)| {
+        key.eq_ignore_ascii_case(MARKER_TAG)
+            || key.to_lowercase().ends_with("comment") && value.starts_with(LEGACY_PREFIX)
+    })
+}
+
+/// Returns the speed recorded by the marker in `tags`, if any.
+pub(crate) fn marked_speed(tags: &[(String, String)]) -> Option<f32> {
+    tags.iter().find_map(|(key, value)| {
+        if key.eq_ignore_ascii_case(MARKER_TAG) {
+            value.parse().ok()
+        } else if key.to_lowercase().ends_with("comment") {
+            value
+                .strip_prefix(LEGACY_PREFIX)?
+                .strip_suffix('x')?
+                .parse()
+                .ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::may_be_marked;
+
+    fn may_be_marked_content(name: &str, content: &[u8]) -> bool {
+        let path =
+            std::env::temp_dir().join(format!("absu-marker-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        let marked = may_be_marked(&path);
+        std::fs::remove_file(&path).unwrap();
+        marked
+    }
+
+    #[test]
+    fn searches_only_the_tags() {
+        let frame = |id: &[u8; 4], content: &[u8]| {
+            [
+                id.as_slice(),
+                &(content.len() as u32).to_be_bytes(),
+                &[0, 0],
+                content,
+            ]
+            .concat()
+        };
+        let id3 = |frames: &[u8]| {
+            let size = frames.len() as u32;
+            let syncsafe = [size >> 21, size >> 14, size >> 7, size].map(|b| (b & 0x7F) as u8);
+            [
+                b"ID3\x03\0\0".as_slice(),
+                &syncsafe,
+                frames,
+                &[0xFF, 0xFB, 0, 0],
+            ]
+            .concat()
+        };
+        let marked = frame(b"TXXX", b"\0ABSU_SPEED\x001.5");
+        let picture = frame(b"APIC", b"\0image/png\0\x03\0ABSU_SPEED");
+        assert!(may_be_marked_content("marked.mp3", &id3(&marked)));
+        assert!(!may_be_marked_content("picture.mp3", &id3(&picture)));
+
+        let flac = |kind: u8, block: &[u8]| {
+            let size = (block.len() as u32).to_be_bytes();
+            [
+                b"fLaC".as_slice(),
+                &[0x80 | kind],
+                &size[1..],
+                block,
+                b"ABSU_SPEED",
+            ]
+            .concat()
+        };
+        assert!(may_be_marked_content(
+            "marked.flac",
+            &flac(4, b"ABSU_SPEED=1.5")
+        ));
+        assert!(!may_be_marked_content(
+            "padding.flac",
+            &flac(1, b"ABSU_SPEED=1.5")
+        ));
+
+        let wav = |chunk: &[u8; 4]| {
+            let body = [chunk.as_slice(), &10u32.to_le_bytes(), b"absu:1.5x\0"].concat();
+            let size = (body.len() as u32 + 4).to_le_bytes();
+            [b"RIFF".as_slice(), &size, b"WAVE", &body].concat()
+        };
+        assert!(may_be_marked_content("marked.wav", &wav(b"LIST")));
+        assert!(!may_be_marked_content("data.wav", &wav(b"data")));
+    }
+}
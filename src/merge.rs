@@ -0,0 +1,370 @@
+//! Merge mode: concatenating the files of each folder into a single output.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::time::Duration;
+
+use log::{debug, error};
+
+use crate::guard::ResourceMonitor;
+use crate::par::*;
+use crate::progress::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use crate::run_log::RunLog;
+use crate::semaphore::Semaphore;
+use crate::{
+    EncoderMap, FileReport, FileStatus, PlannedFile, ProcessOptions, ProcessReport, Result, exec,
+    ffmpeg, marker, move_file, prepare_output, remove_temp_file, tempo_filter, update_replaygain,
+};
+
+/// Distinguishes the concat lists of folders merged concurrently.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Concatenates the files of a plan that will be processed, folder by folder,
+/// and speeds up each concatenation once.
+///
+/// Files are joined in natural name order (so `part 2` comes before
+/// `part 10`) with ffmpeg's concat demuxer, so the files of a folder should
+/// share the same format. Each folder's output is named after the folder and
+/// written inside it, e.g. `lectures/lectures.mp3`, unless
+/// [`ProcessOptions::output_dir`] or [`ProcessOptions::name_template`] is set.
+/// The original files are left untouched. Video files, silence-aware speed,
+/// edge trimming and tempo targets are not supported in this mode.
+///
+/// # Arguments
+///
+/// * `files` - The planned files, as returned by [`plan`](crate::plan).
+/// * `options` - Options controlling how files are processed.
+///
+/// # Returns
+///
+/// * `Result<ProcessReport>` - One entry per merged output (with its path as
+///   [`FileReport::path`]) and per skipped file, or an error if processing fails, e.g.
+///   [`Error::FfmpegNotFound`](crate::Error::FfmpegNotFound) if ffmpeg is missing
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{merge_planned_files, plan, AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// let files = plan("path/to/lecture/parts", &options).unwrap();
+/// let report = merge_planned_files(files, &options).unwrap();
+/// println!("{} folders merged", report.processed_count());
+/// ```
+pub fn merge_planned_files(
+    files: Vec<PlannedFile>,
+    options: &ProcessOptions,
+) -> Result<ProcessReport> {
+    ffmpeg::check_ffmpeg(&*options.runner)?;
+    let (files, skipped): (Vec<_>, Vec<_>) = files
+        .into_iter()
+        .partition(|f| f.will_process() && !f.video && !f.archive);
+    let mut folders: BTreeMap<PathBuf, Vec<PlannedFile>> = BTreeMap::new();
+    for file in files {
+        let folder = file
+            .relative_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        folders.entry(folder).or_default().push(file);
+    }
+
+    let pb = if options.progress_bar && std::io::stdout().is_terminal() {
+        ProgressBar::new(folders.len() as u64)
+    } else {
+        ProgressBar::with_draw_target(Some(folders.len() as u64), ProgressDrawTarget::hidden())
+    };
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+            .expect("Internal Error: Failed to set progress bar style")
+            .progress_chars("#>-"),
+    );
+
+    let run_log = options
+        .log_file
+        .as_deref()
+        .map(RunLog::create)
+        .transpose()?;
+    if let Some(temp_dir) = &options.temp_dir {
+        std::fs::create_dir_all(temp_dir)?;
+    }
+    let ffmpeg_limit = options.max_ffmpeg.map(|n| Semaphore::new(n.get()));
+    let resources = options.resource_guard.clone().map(ResourceMonitor::new);
+    let tempo_filter = tempo_filter(options);
+    let encoders = options.encoders.resolve(&*options.runner)?;
+    // Largest folders first, as when processing files one by one
+    let mut folders: Vec<_> = folders.into_iter().collect();
+    folders.sort_by_key(|(_, files)| std::cmp::Reverse(files.iter().map(|f| f.size).sum::<u64>()));
+    let aborted = AtomicBool::new(false);
+    let reports: Vec<FileReport> = folders
+        .into_iter()
+        .par_bridge()
+        .map(|(folder, mut files)| {
+            if !options.control.wait_while_paused()
+                || resources
+                    .as_ref()
+                    .is_some_and(|resources| !resources.wait_for_resources(&options.control))
+            {
+                aborted.store(true, atomic::Ordering::Release);
+            }
+            files.sort_by(|a, b| natural_cmp(&a.path, &b.path));
+            pb.set_message(folder.display().to_string());
+            let _permit = ffmpeg_limit.as_ref().map(Semaphore::acquire);
+            let report = merge_folder(
+                &folder,
+                &files,
+                options,
+                tempo_filter,
+                &encoders,
+                run_log.as_ref(),
+                &aborted,
+            );
+            if options.fail_fast
+                && report.status == FileStatus::Failed
+                && !aborted.swap(true, atomic::Ordering::AcqRel)
+            {
+                error!("Stopping after the failure of {}", report.path.display());
+            }
+            pb.inc(1);
+            report
+        })
+        .collect();
+    pb.finish_with_message("Merging complete!");
+
+    Ok(ProcessReport {
+        files: reports
+            .into_iter()
+            .chain(skipped.iter().map(FileReport::from_planned))
+            .collect(),
+    })
+}
+
+/// Concatenates and speeds up the sorted `files` of `folder` (relative to the
+/// planned folder). The folder is skipped if the run was `aborted`.
+fn merge_folder(
+    folder: &Path,
+    files: &[PlannedFile],
+    options: &ProcessOptions,
+    tempo_filter: &str,
+    encoders: &EncoderMap,
+    run_log: Option<&RunLog>,
+    aborted: &AtomicBool,
+) -> FileReport {
+    let first = &files[0];
+    // The merged output is planned as a file named after the folder, inside it
+    let root = first
+        .path
+        .ancestors()
+        .nth(first.relative_path.components().count())
+        .unwrap_or(Path::new(""));
+    let mut name = folder
+        .file_name()
+        .or(root.file_name())
+        .unwrap_or("merged".as_ref())
+        .to_os_string();
+    if let Some(extension) = first.path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    let relative_path = folder.join(name);
+    let merged = PlannedFile {
+        path: root.join(&relative_path),
+        relative_path,
+        format: first.format,
+        video: false,
+        size: files.iter().map(|f| f.size).sum(),
+        modified: None,
+        archive: false,
+        skip_reason: None,
+    };
+    let mut report = FileReport {
+        status: FileStatus::Failed,
+        ..FileReport::from_planned(&merged)
+    };
+    let path = merged.path.as_path();
+    if aborted.load(atomic::Ordering::Acquire) {
+        report.status = FileStatus::Skipped;
+        return report;
+    }
+
+    report.input_duration = files
+        .iter()
+        .map(|f| ffmpeg::probe_duration(&*options.runner, &f.path))
+        .sum::<Option<Duration>>();
+    let speed = match options.speed_for(report.input_duration, None) {
+        Ok(Some(speed)) => speed,
+        Ok(None) => {
+            debug!(
+                "Skipping folder (already short enough): {}",
+                folder.display()
+            );
+            report.status = FileStatus::Skipped;
+            return report;
+        }
+        Err(e) => {
+            error!("Failed to compute speed for {}: {}", path.display(), e);
+            return report;
+        }
+    };
+    report.speed = Some(speed);
+    let Some((final_path, output_file)) = prepare_output(&merged, options, speed, None) else {
+        return report;
+    };
+
+    let list_dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let list_path = list_dir.join(format!(
+        "abs-concat-{}-{}.txt",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed)
+    ));
+    if let Err(e) = write_concat_list(&list_path, files) {
+        error!("Error writing concat list for {}: {}", folder.display(), e);
+        return report;
+    }
+
+    let mut command = exec::command("ffmpeg");
+    command
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(&list_path)
+        .args([
+            "-filter:a",
+            &format!("{}=tempo={}", tempo_filter, speed),
+            "-vn",
+        ]);
+    let encoded_format = options
+        .output_format
+        .map_or(merged.format, |f| Some(f.audio_format()));
+    if let Some(encoder) = encoded_format.and_then(|f| encoders.get(f)) {
+        command.args(["-c:a", encoder]);
+    }
+    if let Some(sample_rate) = options.sample_rate {
+        command.arg("-ar").arg(sample_rate.to_string());
+    }
+    if let Some(channels) = options.channels {
+        command.arg("-ac").arg(channels.to_string());
+    }
+    marker::add_marker(&mut command, speed, &output_file);
+    if let Some(threads) = options.ffmpeg_threads {
+        command.arg("-threads").arg(threads.to_string());
+    }
+    command
+        .arg(&output_file)
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-nostats",
+            "-progress",
+            "pipe:1",
+        ])
+        .stdin(Stdio::null());
+    if let Some(nice) = options.nice {
+        exec::set_priority(&mut command, nice);
+    }
+    let log_command = run_log.is_some().then(|| format!("{:?}", command));
+    let status = options
+        .runner
+        .run_with_progress(&mut command, options.timeout, &mut |_| {});
+    _ = std::fs::remove_file(&list_path);
+    if let Some(run_log) = run_log
+        && let Some(log_command) = &log_command
+    {
+        run_log.record(path, log_command, &status);
+    }
+
+    match status {
+        Ok((exit_status, _)) if exit_status.success() => {
+            if options.replaygain {
+                update_replaygain(&*options.runner, &output_file);
+            }
+            report.output_size = std::fs::metadata(&output_file).map(|m| m.len()).ok();
+            report.output_duration = ffmpeg::probe_duration(&*options.runner, &output_file);
+            if let Err(e) = move_file(&output_file, &final_path, options.io_limit.as_ref()) {
+                error!(
+                    "Error renaming file from {} to {}: {}",
+                    output_file.display(),
+                    final_path.display(),
+                    e
+                );
+                return report;
+            }
+            report.output_path = Some(final_path);
+            report.status = FileStatus::Processed;
+        }
+        Ok((exit_status, stderr)) => {
+            error!(
+                "ffmpeg failed merging {}. Exit code: {:?}\n{}",
+                folder.display(),
+                exit_status.code(),
+                stderr.trim_end()
+            );
+            remove_temp_file(&output_file);
+            report.stderr = Some(stderr).filter(|s| !s.trim().is_empty());
+        }
+        Err(e) => {
+            error!("Error executing ffmpeg for {}: {}", folder.display(), e);
+            remove_temp_file(&output_file);
+        }
+    }
+    report
+}
+
+/// Writes a concat demuxer list of `files` to `list_path`.
+fn write_concat_list(list_path: &Path, files: &[PlannedFile]) -> std::io::Result<()> {
+    let mut list = Vec::new();
+    for file in files {
+        let path = std::path::absolute(&file.path)?;
+        list.extend_from_slice(b"file '");
+        // Single quotes are escaped by closing the quote, adding an escaped
+        // quote and reopening it
+        for &byte in path.as_os_str().as_encoded_bytes() {
+            match byte {
+                b'\'' => list.extend_from_slice(b"'\\''"),
+                _ => list.push(byte),
+            }
+        }
+        list.extend_from_slice(b"'\n");
+    }
+    std::fs::File::create(list_path)?.write_all(&list)
+}
+
+/// Compares paths so that runs of digits are ordered by their numeric value.
+fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    let (a, b) = (
+        a.as_os_str().as_encoded_bytes(),
+        b.as_os_str().as_encoded_bytes(),
+    );
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].is_ascii_digit() && b[j].is_ascii_digit() {
+            let digits = |s: &[u8], start: usize| {
+                start + s[start..].iter().take_while(|c| c.is_ascii_digit()).count()
+            };
+            let (end_a, end_b) = (digits(a, i), digits(b, j));
+            let trim =
+                |s: &[u8]| -> Vec<u8> { s.iter().copied().skip_while(|&c| c == b'0').collect() };
+            let (number_a, number_b) = (trim(&a[i..end_a]), trim(&b[j..end_b]));
+            let ordering = number_a
+                .len()
+                .cmp(&number_b.len())
+                .then_with(|| number_a.cmp(&number_b));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            (i, j) = (end_a, end_b);
+        } else {
+            if a[i] != b[j] {
+                return a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    (a.len() - i).cmp(&(b.len() - j))
+}
@@ -0,0 +1,66 @@
+//! Output file names built from a template such as `{stem}_{speed}x.{ext}`.
+
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+/// Renders `template` for the file at `relative_path` (relative to the
+/// processed folder) sped up by `speed`, or for its `part`-th segment.
+///
+/// The supported placeholders are `{stem}`, `{ext}`, `{speed}`, `{dir}` (the
+/// relative directory of the file, empty at the top level) and, for segments,
+/// `{part}` (the part number, padded to three digits); `{{` and `}}` produce
+/// literal braces. Empty, `.`, `..` and root components of the result
+/// are dropped, so the output always stays below the folder it is resolved
+/// against.
+///
+/// # Returns
+///
+/// * `Result<PathBuf, String>` - The rendered relative path, or a message describing an invalid
+///   template.
+pub(crate) fn render_name(
+    template: &str,
+    relative_path: &Path,
+    speed: f32,
+    part: Option<usize>,
+) -> Result<PathBuf, String> {
+    let mut name = OsString::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        name.push(&rest[..pos]);
+        let tail = &rest[pos..];
+        if let Some(tail) = tail.strip_prefix("{{") {
+            name.push("{");
+            rest = tail;
+        } else if let Some(tail) = tail.strip_prefix("}}") {
+            name.push("}");
+            rest = tail;
+        } else if let Some(tail) = tail.strip_prefix('{')
+            && let Some((placeholder, tail)) = tail.split_once('}')
+        {
+            match placeholder {
+                "stem" => name.push(relative_path.file_stem().unwrap_or_default()),
+                "ext" => name.push(relative_path.extension().unwrap_or_default()),
+                "speed" => name.push(speed.to_string()),
+                "dir" => name.push(relative_path.parent().unwrap_or(Path::new(""))),
+                "part" => match part {
+                    Some(part) => name.push(format!("{:03}", part)),
+                    None => return Err("{part} is only available with segments".to_string()),
+                },
+                _ => return Err(format!("unknown placeholder {{{}}}", placeholder)),
+            }
+            rest = tail;
+        } else {
+            return Err(format!("unmatched brace in {:?}", template));
+        }
+    }
+    name.push(rest);
+
+    let path: PathBuf = Path::new(&name)
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    if path.as_os_str().is_empty() {
+        return Err(format!("{:?} renders an empty file name", template));
+    }
+    Ok(path)
+}
@@ -0,0 +1,123 @@
+//! Notifications sent when a run finishes: a desktop notification and a
+//! webhook receiving the JSON report.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use audio_batch_speedup::ProcessReport;
+use log::{info, warn};
+
+use crate::format_duration;
+
+/// Shows a desktop notification summarizing `report`. Failures are logged.
+pub(crate) fn notify_desktop(report: &ProcessReport, elapsed: Duration) {
+    let title = if report.failed_count() == 0 {
+        "Audio batch speedup finished"
+    } else {
+        "Audio batch speedup finished with failures"
+    };
+    let body = format!(
+        "{} processed, {} skipped, {} failed in {}",
+        report.processed_count(),
+        report.skipped_count(),
+        report.failed_count(),
+        format_duration(elapsed)
+    );
+    match notification_command(title, &body)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!(
+            "Desktop notification failed. Exit code: {:?}",
+            status.code()
+        ),
+        Err(e) => warn!("Error showing desktop notification: {}", e),
+    }
+}
+
+/// Returns the command showing a desktop notification: `notify-send` on
+/// Linux and other Unix systems.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn notification_command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name=abs", title, body]);
+    command
+}
+
+/// Returns the command showing a desktop notification: AppleScript on macOS.
+#[cfg(target_os = "macos")]
+fn notification_command(title: &str, body: &str) -> Command {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification {} with title {}",
+        quote(body),
+        quote(title)
+    ));
+    command
+}
+
+/// Returns the command showing a desktop notification: a balloon tip from
+/// PowerShell on Windows.
+#[cfg(windows)]
+fn notification_command(title: &str, body: &str) -> Command {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command"])
+        .arg(format!(
+            "Add-Type -AssemblyName System.Windows.Forms; \
+         $n = New-Object System.Windows.Forms.NotifyIcon; \
+         $n.Icon = [System.Drawing.SystemIcons]::Information; $n.Visible = $true; \
+         $n.ShowBalloonTip(10000, {}, {}, 'Info'); Start-Sleep -Seconds 10; $n.Dispose()",
+            quote(title),
+            quote(body)
+        ));
+    command
+}
+
+/// Returns a command that fails, as desktop notifications are not supported
+/// on this platform.
+#[cfg(not(any(unix, windows)))]
+fn notification_command(_title: &str, _body: &str) -> Command {
+    Command::new("notify-send")
+}
+
+/// POSTs `json` to `url` with `curl`.
+pub(crate) fn post_webhook(url: &str, json: &str) -> Result<()> {
+    let mut child = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail",
+            "--max-time",
+            "30",
+            "--header",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+        ])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(json.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "curl exited with {:?}: {}",
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    info!("Sent the report to {}", url);
+    Ok(())
+}
@@ -0,0 +1,471 @@
+//! Options controlling how files are processed.
+
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    AudioFormat, EncoderMap, IoLimit, ResourceGuard, RunControl, Runner, SilenceOptions, TagFilter,
+    TrimOptions,
+};
+
+/// The time-stretching algorithm used to change the speed of audio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Quality {
+    /// ffmpeg's `atempo` filter, which is fast and always available.
+    #[default]
+    Standard,
+    /// ffmpeg's `rubberband` filter, which sounds noticeably better on music
+    /// but is slower. If ffmpeg was built without it, `atempo` is used
+    /// instead.
+    High,
+}
+
+/// The order files are handed to the workers in. Every order is
+/// deterministic but [`Random`](Self::Random), which is drawn again on each
+/// run, and ties are broken by path. The files of the final report follow the
+/// same order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Order {
+    /// Largest files first, so the run doesn't end with a single worker
+    /// grinding through a long file while the others idle. The size is used
+    /// as a cheap estimate of the duration.
+    #[default]
+    Size,
+    /// By path, in alphabetical order.
+    Name,
+    /// Longest files first, as probed with ffprobe before starting. Files
+    /// whose duration cannot be probed come last.
+    Duration,
+    /// In a random order.
+    Random,
+}
+
+/// A container to convert outputs to, instead of keeping the format of each
+/// file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum OutputFormat {
+    /// MPEG-4 audiobook (AAC audio), which keeps chapters and cover art.
+    M4b,
+}
+
+impl OutputFormat {
+    /// Returns the file extension of the format, e.g. `m4b`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::M4b => "m4b",
+        }
+    }
+
+    /// Returns the audio format the container is encoded in.
+    pub(crate) fn audio_format(self) -> AudioFormat {
+        match self {
+            Self::M4b => AudioFormat::AAC,
+        }
+    }
+}
+
+/// A bundle of options for a common use case.
+///
+/// # Example
+///
+/// ```
+/// use audio_batch_speedup::{AudioFormat, OutputFormat, Preset, ProcessOptions};
+///
+/// let mut options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// Preset::Audiobook.apply(&mut options);
+/// assert_eq!(options.output_format, Some(OutputFormat::M4b));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Preset {
+    /// Audiobooks: rescaled chapters, mono audio at 64 kb/s and m4b output.
+    /// Tags and cover art are kept, as by default.
+    Audiobook,
+}
+
+impl Preset {
+    /// Applies the preset to `options`. Options that are already set keep
+    /// their value, so explicit choices take precedence over the preset.
+    pub fn apply(self, options: &mut ProcessOptions) {
+        match self {
+            Self::Audiobook => {
+                options.rescale_chapters = true;
+                options.channels.get_or_insert(NonZeroU32::MIN);
+                options
+                    .bitrate
+                    .get_or_insert(NonZeroU32::new(64_000).expect("64000 is not zero"));
+                options.output_format.get_or_insert(OutputFormat::M4b);
+            }
+        }
+    }
+}
+
+/// Options for a batch run.
+///
+/// # Example
+///
+/// ```
+/// use audio_batch_speedup::{AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions {
+///     nice: Some(10),
+///     ..ProcessOptions::new(1.5, AudioFormat::OGG)
+/// };
+/// assert_eq!(options.speed, 1.5);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessOptions {
+    /// Speed multiplier (e.g., 1.5 for 1.5x speed).
+    pub speed: f32,
+    /// Which audio formats to process.
+    pub formats: AudioFormat,
+    /// Scheduling priority for spawned ffmpeg processes, as a Unix nice level
+    /// (-20 is the highest priority, 19 the lowest). On Windows it is mapped to
+    /// the closest priority class. `None` leaves the priority unchanged.
+    pub nice: Option<i32>,
+    /// Maximum number of ffmpeg processes running at the same time, independent
+    /// of how many threads are used for walking and probing files. Since ffmpeg
+    /// is itself multithreaded, a value below the number of CPU cores avoids
+    /// oversubscription. `None` means one ffmpeg process per worker thread.
+    pub max_ffmpeg: Option<NonZeroUsize>,
+    /// Files smaller than this many bytes are skipped.
+    pub min_size: Option<u64>,
+    /// Files larger than this many bytes are skipped.
+    pub max_size: Option<u64>,
+    /// Files last modified before this time are skipped.
+    pub modified_after: Option<SystemTime>,
+    /// If set, each file is sped up so that its output lasts this long,
+    /// instead of using [`speed`](Self::speed). Files that are already
+    /// shorter are skipped.
+    pub target_duration: Option<Duration>,
+    /// If set, each file's tempo is detected and the file is sped up (or
+    /// slowed down) to this many beats per minute, instead of using
+    /// [`speed`](Self::speed).
+    pub target_bpm: Option<f32>,
+    /// Experimental: if set, each file's speech rate is estimated from the
+    /// density of its syllables and the file is sped up (or slowed down) to
+    /// this many words per minute, instead of using [`speed`](Self::speed).
+    /// Takes precedence over [`target_bpm`](Self::target_bpm).
+    pub target_wpm: Option<f32>,
+    /// Lower bound for speeds computed per file (e.g., by
+    /// [`target_bpm`](Self::target_bpm) or [`target_wpm`](Self::target_wpm)).
+    pub min_speed: Option<f32>,
+    /// Upper bound for speeds computed per file (e.g., by
+    /// [`target_duration`](Self::target_duration),
+    /// [`target_bpm`](Self::target_bpm) or [`target_wpm`](Self::target_wpm)).
+    pub max_speed: Option<f32>,
+    /// The time-stretching algorithm.
+    pub quality: Quality,
+    /// If set, silent segments are detected and sped up with their own speed,
+    /// while the rest of the audio uses the regular speed.
+    pub silence: Option<SilenceOptions>,
+    /// If set, the silence at the start and end of each file is trimmed, in
+    /// the same pass as the speed change. Video files are not trimmed.
+    pub trim_edges: Option<TrimOptions>,
+    /// If set, the audio is resampled to this sample rate, in Hz.
+    pub sample_rate: Option<NonZeroU32>,
+    /// If set, the audio is remixed to this number of channels (e.g., 1 to
+    /// downmix voice recordings to mono).
+    pub channels: Option<NonZeroU32>,
+    /// If set, the audio is encoded at this bitrate, in bits per second.
+    /// Takes precedence over [`match_bitrate`](Self::match_bitrate).
+    pub bitrate: Option<NonZeroU32>,
+    /// Encode lossy files at the bitrate of the original (e.g., keep a
+    /// 64 kb/s Opus podcast at 64 kb/s) instead of the encoder's default,
+    /// which may be higher or lower. The bitrate is probed with ffprobe.
+    pub match_bitrate: bool,
+    /// If set, outputs are converted to this format and get its extension.
+    /// The original files are then kept, unless an output replaces one of
+    /// them.
+    pub output_format: Option<OutputFormat>,
+    /// The encoder of each output format. Formats without one get the best
+    /// encoder of the local ffmpeg build, see [`EncoderMap`].
+    pub encoders: EncoderMap,
+    /// If set, processed files are written below this folder, at the same
+    /// path relative to the processed folder (or as given by
+    /// [`name_template`](Self::name_template)), instead of replacing the
+    /// originals.
+    pub output_dir: Option<PathBuf>,
+    /// If set, the name of each processed file is built from this template
+    /// instead of replacing the original. The placeholders `{stem}`, `{ext}`,
+    /// `{speed}` and `{dir}` (the directory relative to the processed folder)
+    /// are supported. The name is resolved against
+    /// [`output_dir`](Self::output_dir) if set, and the original file's
+    /// folder otherwise.
+    pub name_template: Option<String>,
+    /// If set, each output is split into parts of this length (of the sped-up
+    /// audio), named after [`name_template`](Self::name_template), which
+    /// must then contain `{part}`. Without a template, parts are named
+    /// `{stem}_{part}.{ext}`. Unless [`output_dir`](Self::output_dir) is set,
+    /// the parts replace the original file. Sidecars are not rescaled and
+    /// [`dedup`](Self::dedup) is ignored for segmented outputs.
+    pub segment: Option<Duration>,
+    /// Rescan processed files and rewrite their ReplayGain track gain and
+    /// peak tags, which speeding up invalidates.
+    pub replaygain: bool,
+    /// Hash the files before processing, process each unique content once,
+    /// and copy the result over the byte-identical duplicates. Hard links of
+    /// the same file are processed once regardless, and linked again.
+    pub dedup: bool,
+    /// The order files are handed to the workers in, and listed in the
+    /// report.
+    pub order: Order,
+    /// Restore the original modification and access times on processed files.
+    pub preserve_times: bool,
+    /// Copy the permissions and extended attributes (including ACLs on
+    /// Linux) of each original file onto its output before it is moved into
+    /// place.
+    pub preserve_attrs: bool,
+    /// Drop embedded cover art instead of copying it to the output. Cover art
+    /// is preserved by default for MP3, FLAC, AAC and ALAC files.
+    pub strip_art: bool,
+    /// Rescale the timestamps of `.lrc`, `.srt` and `.cue` files sharing the
+    /// audio file's name, so lyrics, subtitles and cue sheets stay in sync.
+    pub rescale_sidecars: bool,
+    /// Rescale the chapter marks of processed files, which ffmpeg otherwise
+    /// copies with their original times. Not applied to segmented outputs.
+    pub rescale_chapters: bool,
+    /// Measure the integrated loudness and true peak of every file before and
+    /// after processing with ffmpeg's `ebur128` filter, and record them in
+    /// the report, e.g. to spot clipping introduced by processing. Not
+    /// applied to segmented outputs.
+    pub analyze_loudness: bool,
+    /// Only process files whose tags match all of these filters, e.g. to speed
+    /// up only the podcasts of a mixed library with `genre=Podcast`. Tags are
+    /// read with ffprobe.
+    pub tag_filters: Vec<TagFilter>,
+    /// Ask ffprobe for the format of files whose magic bytes and extension
+    /// are both unrecognized or disagree, e.g. audio without an extension in
+    /// game data folders. Slows down planning of folders with many such
+    /// files.
+    pub probe_formats: bool,
+    /// Also process video files (mp4, m4v, mkv, webm, mov), speeding up both
+    /// their audio and video streams. Silence-aware speed is not applied to
+    /// videos.
+    pub include_video: bool,
+    /// Also process the audio files inside zip and tar archives (`.zip`,
+    /// `.tar`, `.tar.gz`, `.tgz`, `.tar.bz2`, `.tar.xz`, ...). Each archive is
+    /// unpacked to the temporary folder, its matching files are processed,
+    /// and it is packed again in place of the original, or under
+    /// [`output_dir`](Self::output_dir). Needs `unzip` and `zip`, or a `tar`
+    /// that handles zips (e.g., on Windows and macOS). Not applied when
+    /// merging.
+    pub archives: bool,
+    /// Leave out the files and folders excluded by gitignore-style
+    /// [`IGNORE_FILE_NAME`](crate::IGNORE_FILE_NAME) files in the processed
+    /// folder and its subfolders, e.g. with lines such as `Music/` or
+    /// `*.wav`. On by default.
+    pub ignore_files: bool,
+    /// If set, each original file is copied to this folder, at the same path
+    /// relative to the processed folder, before being replaced. Use
+    /// [`restore_backups`](crate::restore_backups) to restore them.
+    pub backup_dir: Option<PathBuf>,
+    /// Stop at the first failed file: files that have not been started yet
+    /// are reported as skipped, while the ones in progress are finished.
+    pub fail_fast: bool,
+    /// If set, ffmpeg is killed when it works on a single file (or merged
+    /// folder) for longer than this, and the file is reported as failed.
+    pub timeout: Option<Duration>,
+    /// If set, temporary outputs are written to this folder (e.g., a fast local
+    /// disk) instead of next to the files being processed, and moved into
+    /// place once finished.
+    pub temp_dir: Option<PathBuf>,
+    /// Copy each file to [`temp_dir`](Self::temp_dir) (or the system's
+    /// temporary folder) before processing it, and write its output there
+    /// too, so ffmpeg only reads and writes local files. The output is copied
+    /// back when finished. Useful for network shares, where ffmpeg's small
+    /// reads and seeks are slow; workers copy files while others encode,
+    /// especially with [`max_ffmpeg`](Self::max_ffmpeg) below the number of
+    /// workers. Not applied when merging.
+    pub stage_local: bool,
+    /// If set, the copies made by the run (staging with
+    /// [`stage_local`](Self::stage_local), backups, outputs moved from
+    /// [`temp_dir`](Self::temp_dir) to another file system and copies over
+    /// duplicates) share this bandwidth, so a run on a shared file server
+    /// does not starve other users. See also
+    /// [`ffmpeg_threads`](Self::ffmpeg_threads).
+    pub io_limit: Option<IoLimit>,
+    /// If set, the number of threads each ffmpeg process encodes with, e.g. 1
+    /// to leave cores to other users of a shared machine.
+    pub ffmpeg_threads: Option<NonZeroU32>,
+    /// If set, no new file is started while the battery is low or the CPU is
+    /// hot, until they recover, see [`ResourceGuard`].
+    pub resource_guard: Option<ResourceGuard>,
+    /// If set, the path of every file processed successfully is appended to
+    /// this journal as soon as it is done, and [`plan`](crate::plan) skips
+    /// the files already listed in it. Planning and processing again with the
+    /// same journal resumes an interrupted run. Not used when merging.
+    pub journal: Option<PathBuf>,
+    /// If set, this shell command is run with the path of each file as its
+    /// argument before the file is processed. The file is skipped if the
+    /// command fails. Not used when merging.
+    pub pre_hook: Option<String>,
+    /// If set, this shell command is run after each file, with the path of
+    /// the file, its outcome (`processed`, `skipped` or `failed`) and the path
+    /// of its output (empty unless processed) as arguments. Not used when
+    /// merging.
+    pub post_hook: Option<String>,
+    /// If set, a detailed log is appended to this file, recording for every
+    /// file the exact ffmpeg command, its exit status and its standard error,
+    /// regardless of the log level.
+    pub log_file: Option<PathBuf>,
+    /// Draw a progress bar on the terminal. Even when enabled, the bar is
+    /// hidden if standard output is not a terminal (e.g., in cron jobs), or
+    /// if the crate is built without the `progress` feature.
+    pub progress_bar: bool,
+    /// Skip files carrying the `ABSU_SPEED` tag written into every processed
+    /// file (e.g., `ABSU_SPEED=1.5`), so re-running on the same folder does
+    /// not speed them up twice. Only files whose tags (e.g., the ID3 tag of
+    /// an MP3, read without its cover art) contain the tag's name are probed
+    /// for it with ffprobe.
+    pub skip_marked: bool,
+    /// Write a hidden sidecar next to each processed file (`.song.mp3.absu`
+    /// for `song.mp3`) recording the duration of its original. With
+    /// [`skip_marked`](Self::skip_marked), files noticeably shorter than
+    /// their recorded original are skipped even if their marker was lost,
+    /// whether or not the run skipping them records durations itself.
+    pub record_durations: bool,
+    /// Replace read-only originals, clearing their read-only attribute first.
+    /// Otherwise, [`plan`](crate::plan) skips the read-only files that would
+    /// be replaced, rather than letting them fail once encoded.
+    pub clear_readonly: bool,
+    /// Number of times the files that changed since they were planned (e.g.,
+    /// a recorder is still writing them), or that another program had open,
+    /// are retried at the end of the run. Each retry waits twice as long as
+    /// the one before, starting at two seconds, so three retries wait 14
+    /// seconds in all. Files still changing after the last retry are skipped,
+    /// and files still in use fail. Defaults to no retries.
+    pub busy_retries: u32,
+    /// Runs the ffmpeg and ffprobe commands. Defaults to spawning them with
+    /// [`SystemRunner`](crate::SystemRunner).
+    pub runner: Runner,
+    /// Pauses, resumes or stops the run from another thread, e.g. on a key
+    /// press.
+    pub control: RunControl,
+}
+
+impl ProcessOptions {
+    /// Creates options with the given speed and formats, and defaults for
+    /// everything else.
+    pub fn new(speed: f32, formats: AudioFormat) -> Self {
+        Self {
+            speed,
+            formats,
+            nice: None,
+            max_ffmpeg: None,
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            target_duration: None,
+            target_bpm: None,
+            target_wpm: None,
+            min_speed: None,
+            max_speed: None,
+            quality: Quality::Standard,
+            silence: None,
+            trim_edges: None,
+            sample_rate: None,
+            channels: None,
+            bitrate: None,
+            match_bitrate: true,
+            output_format: None,
+            encoders: EncoderMap::new(),
+            output_dir: None,
+            name_template: None,
+            segment: None,
+            replaygain: false,
+            dedup: false,
+            order: Order::Size,
+            preserve_times: false,
+            preserve_attrs: false,
+            strip_art: false,
+            rescale_sidecars: false,
+            rescale_chapters: false,
+            analyze_loudness: false,
+            tag_filters: Vec::new(),
+            probe_formats: false,
+            include_video: false,
+            archives: false,
+            ignore_files: true,
+            backup_dir: None,
+            fail_fast: false,
+            timeout: None,
+            temp_dir: None,
+            stage_local: false,
+            io_limit: None,
+            ffmpeg_threads: None,
+            resource_guard: None,
+            journal: None,
+            pre_hook: None,
+            post_hook: None,
+            log_file: None,
+            progress_bar: true,
+            skip_marked: true,
+            record_durations: false,
+            clear_readonly: false,
+            busy_retries: 0,
+            runner: Runner::default(),
+            control: RunControl::default(),
+        }
+    }
+
+    /// Returns whether processed files replace their originals, rather than
+    /// being written next to them or into [`output_dir`](Self::output_dir).
+    pub(crate) fn replaces_originals(&self) -> bool {
+        self.output_dir.is_none()
+            && (self.segment.is_some()
+                || (self.name_template.is_none() && self.output_format.is_none()))
+    }
+
+    /// Returns the folder temporary files are written to instead of next to
+    /// the outputs, if any.
+    pub(crate) fn local_temp_dir(&self) -> Option<PathBuf> {
+        self.temp_dir
+            .clone()
+            .or_else(|| self.stage_local.then(std::env::temp_dir))
+    }
+
+    /// Computes the speed to apply to a file whose duration is
+    /// `input_duration` and whose detected `rate` is its speech rate in words
+    /// per minute with [`target_wpm`](Self::target_wpm), or its tempo in
+    /// beats per minute with [`target_bpm`](Self::target_bpm).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(speed))` - The speed to apply.
+    /// * `Ok(None)` - The file does not need to be sped up.
+    /// * `Err(message)` - The speed could not be determined.
+    pub(crate) fn speed_for(
+        &self,
+        input_duration: Option<Duration>,
+        rate: Option<f32>,
+    ) -> Result<Option<f32>, String> {
+        let speed = if let Some(target) = self.target_duration {
+            let input = input_duration.ok_or("could not determine the duration of the file")?;
+            let speed = input.as_secs_f32() / target.as_secs_f32();
+            if speed <= 1.0 {
+                return Ok(None);
+            }
+            speed
+        } else if let Some(target) = self.target_wpm {
+            let wpm = rate.ok_or("could not estimate the speech rate of the file")?;
+            target / wpm
+        } else if let Some(target) = self.target_bpm {
+            let bpm = rate.ok_or("could not detect the tempo of the file")?;
+            target / bpm
+        } else {
+            return Ok(Some(self.speed));
+        };
+        let speed = self.max_speed.map_or(speed, |max| speed.min(max));
+        let speed = self.min_speed.map_or(speed, |min| speed.max(min));
+        Ok(Some(speed))
+    }
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self::new(1.0, AudioFormat::ALL)
+    }
+}
@@ -0,0 +1,56 @@
+//! Records of the duration of the originals of processed files, kept in
+//! sidecars so a file is not sped up twice even when its marker tag is lost
+//! (e.g., by a tag editor or a format that cannot carry it).
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Suffix of the hidden sidecar recording the original duration of a
+/// processed file: `song.mp3` gets `.song.mp3.absu`.
+pub(crate) const ORIGIN_SUFFIX: &str = ".absu";
+
+/// Fraction by which a file must be shorter than its recorded original to
+/// count as already sped up, allowing for the padding of encoders.
+const TOLERANCE: f64 = 0.02;
+
+/// Returns the path of the sidecar of `path`.
+fn origin_path(path: &Path) -> PathBuf {
+    let mut name = OsStr::new(".").to_os_string();
+    name.push(path.file_name().unwrap_or_default());
+    name.push(ORIGIN_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Returns whether `name` is the name of a sidecar written by
+/// [`record_origin`].
+pub(crate) fn is_origin_file(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(ORIGIN_SUFFIX))
+}
+
+/// Reads the original duration recorded for `path`, if any.
+pub(crate) fn recorded_duration(path: &Path) -> Option<Duration> {
+    let content = std::fs::read_to_string(origin_path(path)).ok()?;
+    let secs: f64 = content.trim().parse().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Records `original` as the duration of the original of the processed file
+/// at `path`. A longer duration recorded by an earlier run is kept, as the
+/// file it was recorded for was then already the original.
+pub(crate) fn record_origin(path: &Path, original: Duration) -> std::io::Result<()> {
+    if recorded_duration(path).is_some_and(|recorded| recorded >= original) {
+        return Ok(());
+    }
+    std::fs::write(
+        origin_path(path),
+        format!("{:.3}\n", original.as_secs_f64()),
+    )
+}
+
+/// Returns whether a file lasting `duration` is shorter than its `recorded`
+/// original, beyond what encoding alone would explain.
+pub(crate) fn is_shortened(duration: Duration, recorded: Duration) -> bool {
+    duration.as_secs_f64() < recorded.as_secs_f64() * (1.0 - TOLERANCE)
+}
@@ -0,0 +1,63 @@
+//! Parallel iteration with rayon, or a sequential fallback with the same
+//! method names when the `parallel` feature is disabled.
+
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::prelude::*;
+
+/// Returns the number of files processed at the same time.
+#[cfg(feature = "parallel")]
+pub(crate) fn current_num_threads() -> usize {
+    rayon::current_num_threads()
+}
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) use sequential::*;
+
+/// Returns the number of files processed at the same time.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn current_num_threads() -> usize {
+    1
+}
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    use std::iter::FlatMap;
+
+    /// Stands in for `rayon::iter::IntoParallelIterator`.
+    pub(crate) trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Self::IntoIter {
+            self.into_iter()
+        }
+    }
+
+    impl<T: IntoIterator> IntoParallelIterator for T {}
+
+    /// Stands in for `rayon::iter::IntoParallelRefIterator`.
+    pub(crate) trait IntoParallelRefIterator<T> {
+        fn par_iter(&self) -> std::slice::Iter<'_, T>;
+    }
+
+    impl<T> IntoParallelRefIterator<T> for [T] {
+        fn par_iter(&self) -> std::slice::Iter<'_, T> {
+            self.iter()
+        }
+    }
+
+    /// Stands in for `rayon::iter::ParallelBridge` and the methods of
+    /// `rayon::iter::ParallelIterator` that `Iterator` lacks.
+    pub(crate) trait ParallelIterator: Iterator + Sized {
+        fn par_bridge(self) -> Self {
+            self
+        }
+
+        fn flat_map_iter<U, F>(self, f: F) -> FlatMap<Self, U, F>
+        where
+            U: IntoIterator,
+            F: FnMut(Self::Item) -> U,
+        {
+            self.flat_map(f)
+        }
+    }
+
+    impl<I: Iterator> ParallelIterator for I {}
+}
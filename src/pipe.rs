@@ -0,0 +1,175 @@
+//! Speeding up a single stream, e.g. from standard input to standard output,
+//! without touching the disk.
+
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use crate::{AudioFormat, Error, ProcessOptions, Result, exec, tempo_filter};
+
+/// Reads audio of `input_format` from `input`, speeds it up by
+/// [`ProcessOptions::speed`] and writes it to `output` as `output_format`.
+///
+/// The stream is piped through ffmpeg as it is read, so only containers that
+/// can be written without seeking back are produced: AAC is written as raw
+/// ADTS and ALAC as fragmented MP4. Per-file options (target durations,
+/// silence detection, segments, ...) do not apply, and ffmpeg is always
+/// spawned directly, since [`ProcessOptions::runner`] cannot stream.
+///
+/// # Arguments
+///
+/// * `input` - The encoded audio, read to its end.
+/// * `output` - Receives the processed audio.
+/// * `input_format` - The single format of `input`.
+/// * `output_format` - The single format to encode `output` in.
+/// * `options` - The speed, quality, encoders, sample rate, channels, bitrate
+///   and priority are used. Only encoders set in [`ProcessOptions::encoders`]
+///   replace the defaults, as the local build is not probed.
+///
+/// # Returns
+///
+/// * `Result<()>` - `Ok` once the whole stream is written, or an error if ffmpeg failed or a
+///   format is not a single one.
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{AudioFormat, ProcessOptions, process_stream};
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+/// process_stream(
+///     std::io::stdin(),
+///     std::io::stdout(),
+///     AudioFormat::MP3,
+///     AudioFormat::OGG,
+///     &options,
+/// )
+/// .unwrap();
+/// ```
+pub fn process_stream(
+    mut input: impl Read + Send,
+    mut output: impl Write,
+    input_format: AudioFormat,
+    output_format: AudioFormat,
+    options: &ProcessOptions,
+) -> Result<()> {
+    for format in [input_format, output_format] {
+        if format.bits().count_ones() != 1 {
+            return Err(Error::InvalidOptions(
+                "streams need a single input and output format".to_string(),
+            ));
+        }
+    }
+    let (muxer, default_codec) = muxer(output_format);
+    let codec = options.encoders.get(output_format).unwrap_or(default_codec);
+    let mut command = exec::command("ffmpeg");
+    command
+        .args(["-f", demuxer(input_format), "-i", "pipe:0", "-vn"])
+        .arg("-filter:a")
+        .arg(format!("{}=tempo={}", tempo_filter(options), options.speed))
+        .args(["-c:a", codec]);
+    if let Some(sample_rate) = options.sample_rate {
+        command.arg("-ar").arg(sample_rate.to_string());
+    }
+    if let Some(channels) = options.channels {
+        command.arg("-ac").arg(channels.to_string());
+    }
+    if let Some(bitrate) = options.bitrate {
+        command.arg("-b:a").arg(bitrate.to_string());
+    }
+    if let Some(threads) = options.ffmpeg_threads {
+        command.arg("-threads").arg(threads.to_string());
+    }
+    if output_format == AudioFormat::ALAC {
+        command.args(["-movflags", "frag_keyframe+empty_moov"]);
+    }
+    command
+        .args(["-f", muxer, "pipe:1", "-loglevel", "error", "-nostats"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(nice) = options.nice {
+        exec::set_priority(&mut command, nice);
+    }
+
+    let mut child = command.spawn().map_err(Error::from_spawn)?;
+    let mut stdin = child.stdin.take().expect("Internal Error: stdin is piped");
+    let mut stdout = child
+        .stdout
+        .take()
+        .expect("Internal Error: stdout is piped");
+    let mut stderr = child
+        .stderr
+        .take()
+        .expect("Internal Error: stderr is piped");
+    let (fed, log, copied) = std::thread::scope(|scope| {
+        let feeder = scope.spawn(move || {
+            let fed = io::copy(&mut input, &mut stdin);
+            // Closing ffmpeg's input ends the stream
+            drop(stdin);
+            fed
+        });
+        let reader = scope.spawn(move || {
+            let mut log = String::new();
+            _ = stderr.read_to_string(&mut log);
+            log
+        });
+        let copied = io::copy(&mut stdout, &mut output).and_then(|_| output.flush());
+        // If `output` failed, ffmpeg must not block on a full pipe
+        drop(stdout);
+        let fed = feeder
+            .join()
+            .expect("Internal Error: stream feeder panicked");
+        let log = reader
+            .join()
+            .expect("Internal Error: stderr reader panicked");
+        (fed, log, copied)
+    });
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::FfmpegFailed {
+            path: PathBuf::from("-"),
+            stderr: log,
+        });
+    }
+    copied?;
+    // ffmpeg may stop reading once it has seen the end of the audio
+    if let Err(e) = fed
+        && e.kind() != io::ErrorKind::BrokenPipe
+    {
+        return Err(e.into());
+    }
+    log::debug!(
+        "Streamed {} as {}",
+        input_format.name(),
+        output_format.name()
+    );
+    Ok(())
+}
+
+/// Returns the ffmpeg demuxer reading `format`.
+fn demuxer(format: AudioFormat) -> &'static str {
+    match format {
+        AudioFormat::OGG | AudioFormat::OPUS => "ogg",
+        AudioFormat::MP3 => "mp3",
+        AudioFormat::WAV => "wav",
+        AudioFormat::FLAC => "flac",
+        AudioFormat::WMA => "asf",
+        // AAC and ALAC usually come in MP4 containers
+        _ => "mov",
+    }
+}
+
+/// Returns the ffmpeg muxer and encoder writing `format` to a pipe.
+fn muxer(format: AudioFormat) -> (&'static str, &'static str) {
+    match format {
+        AudioFormat::OGG => ("ogg", "libvorbis"),
+        AudioFormat::OPUS => ("opus", "libopus"),
+        AudioFormat::MP3 => ("mp3", "libmp3lame"),
+        AudioFormat::WAV => ("wav", "pcm_s16le"),
+        AudioFormat::FLAC => ("flac", "flac"),
+        AudioFormat::AAC => ("adts", "aac"),
+        AudioFormat::ALAC => ("mp4", "alac"),
+        _ => ("asf", "wmav2"),
+    }
+}
@@ -0,0 +1,360 @@
+//! The planning phase: enumerating files and deciding what to process.
+
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::Metadata;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use walkdir::WalkDir;
+
+use crate::ignore::{IGNORE_FILE_NAME, IgnoreFiles};
+use crate::par::*;
+use crate::{
+    AudioFormat, ProcessOptions, Result, archive, clean, detect, detect_audio_format, ffmpeg,
+    history, journal, manifest, marker, origin, tags,
+};
+
+/// Why a file will not be processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    /// The audio format could not be detected.
+    UnknownFormat,
+    /// The format was detected but is not among the selected formats.
+    FormatNotSelected,
+    /// The file is smaller than [`ProcessOptions::min_size`].
+    TooSmall,
+    /// The file is larger than [`ProcessOptions::max_size`].
+    TooLarge,
+    /// The file was last modified before [`ProcessOptions::modified_after`].
+    TooOld,
+    /// The file carries the marker of an earlier run, see
+    /// [`ProcessOptions::skip_marked`].
+    AlreadyProcessed,
+    /// The file is shorter than the original recorded by an earlier run, see
+    /// [`ProcessOptions::record_durations`].
+    Shortened,
+    /// The file is listed in [`ProcessOptions::journal`] as completed by an
+    /// interrupted run.
+    Completed,
+    /// The file's tags do not match [`ProcessOptions::tag_filters`].
+    TagsNotMatched,
+    /// The file is a temporary file left behind by an interrupted run. Use
+    /// [`clean_temp_files`](crate::clean_temp_files) to remove it.
+    TempFile,
+    /// The file is read-only and would be replaced. Set
+    /// [`ProcessOptions::clear_readonly`] to replace it anyway.
+    ReadOnly,
+    /// The file was left out of the random sample chosen by [`sample`].
+    NotSampled,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UnknownFormat => "format not detected",
+            Self::FormatNotSelected => "format not selected",
+            Self::TooSmall => "smaller than the minimum size",
+            Self::TooLarge => "larger than the maximum size",
+            Self::TooOld => "modified before the cutoff date",
+            Self::AlreadyProcessed => "already sped up",
+            Self::Shortened => "shorter than its recorded original",
+            Self::Completed => "completed by an interrupted run",
+            Self::TagsNotMatched => "tags do not match the filters",
+            Self::TempFile => "temporary file of an interrupted run",
+            Self::ReadOnly => "read-only",
+            Self::NotSampled => "not in the sample",
+        })
+    }
+}
+
+/// A file found while planning a batch run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedFile {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Path of the file relative to the planned folder.
+    pub relative_path: PathBuf,
+    /// The detected audio format, if any.
+    pub format: Option<AudioFormat>,
+    /// Whether the file will be handled as a video.
+    pub video: bool,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// When the file was last modified, if known. Together with
+    /// [`size`](Self::size), it tells whether the file changed after it was
+    /// planned.
+    pub modified: Option<SystemTime>,
+    /// Whether the file is an archive whose audio files will be processed,
+    /// with [`ProcessOptions::archives`].
+    pub archive: bool,
+    /// Why the file will be skipped, or `None` if it will be processed.
+    pub skip_reason: Option<SkipReason>,
+}
+
+impl PlannedFile {
+    /// Returns whether the file will be processed.
+    pub fn will_process(&self) -> bool {
+        self.skip_reason.is_none()
+    }
+}
+
+/// Walks `folder` recursively and determines, for every file, whether and how
+/// it will be processed with the given options. No file is modified.
+///
+/// # Arguments
+///
+/// * `folder` - Path to the folder containing audio files
+/// * `options` - Options controlling which files are processed.
+///
+/// # Returns
+///
+/// * `Result<Vec<PlannedFile>>` - One entry per file found, in no particular order, or an error
+///   if `folder` cannot be read.
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{plan, AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions::new(1.5, AudioFormat::OGG);
+/// for file in plan("path/to/audio/files", &options).unwrap() {
+///     match file.skip_reason {
+///         None => println!("{} ({} bytes)", file.path.display(), file.size),
+///         Some(reason) => println!("{} skipped: {}", file.path.display(), reason),
+///     }
+/// }
+/// ```
+pub fn plan(folder: impl AsRef<Path>, options: &ProcessOptions) -> Result<Vec<PlannedFile>> {
+    plan_entries(walk(folder.as_ref(), options)?.collect(), options)
+}
+
+/// Walks `folder` recursively in name order, yielding the path of every file
+/// to plan, its path relative to `folder` and its metadata. Ignored files,
+/// and the files the runs keep for themselves, are left out.
+///
+/// # Returns
+///
+/// * `Result<impl Iterator>` - The files, found lazily as the iterator is advanced, or an error
+///   if `folder` cannot be read.
+pub(crate) fn walk<'a>(
+    folder: &'a Path,
+    options: &'a ProcessOptions,
+) -> Result<impl Iterator<Item = (PathBuf, PathBuf, Metadata)> + Send + 'a> {
+    std::fs::read_dir(folder)?;
+    let mut ignore_files = options.ignore_files.then(|| IgnoreFiles::new(folder));
+    Ok(WalkDir::new(folder)
+        .sort_by_file_name()
+        .into_iter()
+        // Excluded folders are not walked at all
+        .filter_entry(move |e| {
+            !ignore_files
+                .as_mut()
+                .is_some_and(|ignore| ignore.is_ignored(e.path(), e.file_type().is_dir()))
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| options.journal.as_deref() != Some(e.path()))
+        .filter(|e| {
+            e.file_name() != history::HISTORY_NAME
+                && e.file_name() != IGNORE_FILE_NAME
+                && e.file_name() != manifest::MANIFEST_NAME
+                && !origin::is_origin_file(e.file_name())
+        })
+        .filter_map(move |e| {
+            let metadata = e.metadata().ok()?;
+            let path = e.into_path();
+            let relative_path = path.strip_prefix(folder).unwrap_or(&path).to_path_buf();
+            Some((path, relative_path, metadata))
+        }))
+}
+
+/// Narrows a plan down to `count` files chosen at random among those it
+/// will process, e.g. to check the quality of some options on a few files
+/// before processing a whole library. The other files are marked as skipped
+/// with [`SkipReason::NotSampled`].
+///
+/// # Arguments
+///
+/// * `files` - The planned files, as returned by [`plan`].
+/// * `count` - The number of files to keep.
+///
+/// # Example
+///
+/// ```
+/// use audio_batch_speedup::{PlannedFile, SkipReason, sample};
+///
+/// let mut files: Vec<PlannedFile> = (0..10)
+///     .map(|i| PlannedFile {
+///         path: format!("{}.ogg", i).into(),
+///         relative_path: format!("{}.ogg", i).into(),
+///         format: None,
+///         video: false,
+///         size: 0,
+///         modified: None,
+///         archive: false,
+///         skip_reason: None,
+///     })
+///     .collect();
+/// sample(&mut files, 3);
+/// assert_eq!(files.iter().filter(|f| f.will_process()).count(), 3);
+/// assert_eq!(
+///     files.iter().filter(|f| f.skip_reason == Some(SkipReason::NotSampled)).count(),
+///     7
+/// );
+/// ```
+pub fn sample(files: &mut [PlannedFile], count: usize) {
+    let mut candidates: Vec<_> = files.iter_mut().filter(|f| f.will_process()).collect();
+    shuffle(&mut candidates, |f| &f.path);
+    for file in candidates.into_iter().skip(count) {
+        file.skip_reason = Some(SkipReason::NotSampled);
+    }
+}
+
+/// Shuffles `items`, by sorting them on randomly keyed hashes of their
+/// paths.
+pub(crate) fn shuffle<T>(items: &mut [T], path: impl Fn(&T) -> &Path) {
+    let random = RandomState::new();
+    items.sort_by_cached_key(|item| random.hash_one(path(item)));
+}
+
+/// Plans `entries`, given as the path of each file, its path relative to
+/// the processed folder and its metadata.
+pub(crate) fn plan_entries(
+    entries: Vec<(PathBuf, PathBuf, Metadata)>,
+    options: &ProcessOptions,
+) -> Result<Vec<PlannedFile>> {
+    let completed = completed_files(options)?;
+    Ok(entries
+        .into_par_iter()
+        .map(|(path, relative_path, metadata)| {
+            plan_entry(path, relative_path, &metadata, &completed, options)
+        })
+        .collect())
+}
+
+/// Returns the files listed in [`ProcessOptions::journal`] as completed by
+/// an interrupted run, if any.
+pub(crate) fn completed_files(options: &ProcessOptions) -> Result<HashSet<PathBuf>> {
+    Ok(match &options.journal {
+        Some(path) => journal::read_journal(path)?,
+        None => HashSet::new(),
+    })
+}
+
+/// Plans a single entry of [`plan_entries`], skipping it without reading
+/// it if it is left over by a run, too old or `completed`.
+pub(crate) fn plan_entry(
+    path: PathBuf,
+    relative_path: PathBuf,
+    metadata: &Metadata,
+    completed: &HashSet<PathBuf>,
+    options: &ProcessOptions,
+) -> PlannedFile {
+    let too_old = options
+        .modified_after
+        .is_some_and(|cutoff| metadata.modified().is_ok_and(|time| time < cutoff));
+    let skip_reason = if clean::is_orphaned_temp_file(&path) {
+        Some(SkipReason::TempFile)
+    } else if too_old {
+        Some(SkipReason::TooOld)
+    } else if completed.contains(&relative_path) {
+        Some(SkipReason::Completed)
+    } else {
+        return plan_file(path, relative_path, metadata, options);
+    };
+    PlannedFile {
+        path,
+        relative_path,
+        format: None,
+        video: false,
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+        archive: false,
+        skip_reason,
+    }
+}
+
+/// Plans a single file with the given `metadata`.
+pub(crate) fn plan_file(
+    path: PathBuf,
+    relative_path: PathBuf,
+    metadata: &Metadata,
+    options: &ProcessOptions,
+) -> PlannedFile {
+    let size = metadata.len();
+    let mut planned = PlannedFile {
+        path,
+        relative_path,
+        format: None,
+        video: false,
+        size,
+        modified: metadata.modified().ok(),
+        archive: false,
+        skip_reason: None,
+    };
+    if options.min_size.is_some_and(|min| size < min) {
+        planned.skip_reason = Some(SkipReason::TooSmall);
+        return planned;
+    }
+    if options.max_size.is_some_and(|max| size > max) {
+        planned.skip_reason = Some(SkipReason::TooLarge);
+        return planned;
+    }
+    if options.archives && archive::is_archive(&planned.path) {
+        planned.archive = true;
+    } else if options.include_video && detect::is_video_file(&planned.path) {
+        planned.video = true;
+    } else {
+        planned.format = if options.probe_formats {
+            detect::detect_audio_format_probing(&*options.runner, &planned.path)
+        } else {
+            detect_audio_format(&planned.path)
+        };
+        planned.skip_reason = match planned.format {
+            None => Some(SkipReason::UnknownFormat),
+            Some(format) if !options.formats.contains(format) => {
+                Some(SkipReason::FormatNotSelected)
+            }
+            Some(_) => None,
+        };
+    }
+    if planned.will_process()
+        && options.replaces_originals()
+        && !options.clear_readonly
+        && metadata.permissions().readonly()
+    {
+        planned.skip_reason = Some(SkipReason::ReadOnly);
+        return planned;
+    }
+    // Only files that may carry the marker are probed for it, since most do not
+    if planned.will_process()
+        && !planned.archive
+        && (!options.tag_filters.is_empty()
+            || options.skip_marked && marker::may_be_marked(&planned.path))
+    {
+        let tags = tags::read_tags(&*options.runner, &planned.path);
+        if options.skip_marked && marker::is_marked(&tags) {
+            planned.skip_reason = Some(SkipReason::AlreadyProcessed);
+        } else if !options
+            .tag_filters
+            .iter()
+            .all(|filter| filter.matches(&tags))
+        {
+            planned.skip_reason = Some(SkipReason::TagsNotMatched);
+        }
+    }
+    // Only files with a record are probed, since most have none
+    if planned.will_process()
+        && !planned.archive
+        && options.skip_marked
+        && let Some(recorded) = origin::recorded_duration(&planned.path)
+        && ffmpeg::probe_duration(&*options.runner, &planned.path)
+            .is_some_and(|duration| origin::is_shortened(duration, recorded))
+    {
+        planned.skip_reason = Some(SkipReason::Shortened);
+    }
+    planned
+}
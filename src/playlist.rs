@@ -0,0 +1,215 @@
+//! Processing the files referenced by an `.m3u`/`.m3u8` playlist.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use crate::{PlannedFile, ProcessOptions, ProcessReport, Result, plan};
+
+/// Returns whether `path` has the extension of an M3U playlist, `.m3u` or
+/// `.m3u8`.
+pub fn is_playlist(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("m3u") || extension.eq_ignore_ascii_case("m3u8")
+        })
+}
+
+/// Plans the files referenced by the M3U playlist at `playlist`, like
+/// [`plan`](crate::plan) plans the files of a folder.
+///
+/// Comment lines (starting with `#`) are ignored. Relative entries are
+/// resolved against the folder of the playlist, and `file://` URLs are
+/// accepted. Other URLs and missing files are skipped with a warning. Files
+/// outside the folder of the playlist are named by their file name in
+/// [`ProcessOptions::output_dir`].
+///
+/// # Arguments
+///
+/// * `playlist` - The path to the playlist.
+/// * `options` - The options the files will be processed with.
+///
+/// # Returns
+///
+/// * `Result<Vec<PlannedFile>>` - One entry per distinct file of the playlist, in playlist order.
+pub fn plan_playlist(
+    playlist: impl AsRef<Path>,
+    options: &ProcessOptions,
+) -> Result<Vec<PlannedFile>> {
+    let playlist = playlist.as_ref();
+    let folder = playlist_folder(playlist);
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for line in read_playlist(playlist)?.lines() {
+        let Some(path) = resolve_entry(folder, line) else {
+            if is_remote(line) {
+                log::warn!("Skipping playlist entry {}: not a local file", line.trim());
+            }
+            continue;
+        };
+        if !seen.insert(canonical(&path)) {
+            continue;
+        }
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => {
+                log::warn!("Skipping playlist entry {}: not a file", path.display());
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Skipping playlist entry {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let relative_path = relative_to(&path, folder)
+            .unwrap_or_else(|| Path::new(path.file_name().unwrap_or_default()))
+            .to_path_buf();
+        entries.push((path, relative_path, metadata));
+    }
+    plan::plan_entries(entries, options)
+}
+
+/// Rewrites the playlist at `playlist` so that its entries point at the
+/// outputs recorded in `report`, e.g. after processing with
+/// [`ProcessOptions::output_dir`] or [`ProcessOptions::name_template`].
+/// Entries of files written in place or not processed are kept as they are.
+///
+/// Outputs under the folder of the playlist are written as relative paths,
+/// others as absolute paths. The playlist is replaced atomically.
+///
+/// # Arguments
+///
+/// * `playlist` - The path to the playlist the files were planned from.
+/// * `report` - The report of processing the files of the playlist.
+///
+/// # Returns
+///
+/// * `Result<usize>` - The number of rewritten entries.
+pub fn rewrite_playlist(playlist: impl AsRef<Path>, report: &ProcessReport) -> Result<usize> {
+    let playlist = playlist.as_ref();
+    let folder = playlist_folder(playlist);
+    let outputs: HashMap<PathBuf, &Path> = report
+        .files
+        .iter()
+        .filter_map(|file| {
+            let output = file.output_path.as_deref()?;
+            (output != file.path).then(|| (canonical(&file.path), output))
+        })
+        .collect();
+    let content = read_playlist(playlist)?;
+    let newline = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut rewritten = 0;
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        let output = resolve_entry(folder, line).and_then(|path| outputs.get(&canonical(&path)));
+        match output {
+            Some(output) => {
+                let output = relative_to(output, folder).unwrap_or(output);
+                lines.push(output.display().to_string());
+                rewritten += 1;
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+    if rewritten > 0 {
+        let mut temp = playlist.as_os_str().to_owned();
+        temp.push(".tmp");
+        std::fs::write(&temp, lines.join(newline) + newline)?;
+        std::fs::rename(&temp, playlist)?;
+    }
+    Ok(rewritten)
+}
+
+/// Returns the folder relative entries of `playlist` are resolved against.
+fn playlist_folder(playlist: &Path) -> &Path {
+    playlist.parent().unwrap_or(Path::new(""))
+}
+
+/// Returns the canonical form of `path`, so that entries spelled differently
+/// are recognized as the same file, or `path` itself if it does not exist.
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Returns `path` relative to `folder`, or `None` if it is not inside
+/// `folder`.
+fn relative_to<'a>(path: &'a Path, folder: &Path) -> Option<&'a Path> {
+    let relative = if folder.as_os_str().is_empty() {
+        // Absolute paths would otherwise be "inside" the current folder
+        path.is_relative().then_some(path)?
+    } else {
+        path.strip_prefix(folder).ok()?
+    };
+    relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then_some(relative)
+}
+
+/// Reads the playlist at `playlist`, without a leading byte order mark.
+/// Invalid UTF-8, as found in `.m3u` files in a legacy encoding, is replaced.
+fn read_playlist(playlist: &Path) -> Result<String> {
+    let content = std::fs::read(playlist)?;
+    let content = String::from_utf8_lossy(&content);
+    Ok(content
+        .strip_prefix('\u{feff}')
+        .unwrap_or(&content)
+        .to_string())
+}
+
+/// Resolves the playlist line `line` to the path of the file it references,
+/// or `None` for comments, blank lines and URLs other than `file://`.
+fn resolve_entry(folder: &Path, line: &str) -> Option<PathBuf> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let path = match line.strip_prefix("file://") {
+        // `file:///path` on Unix, `file:///C:/path` on Windows
+        Some(url) => {
+            let path = percent_decode(url.strip_prefix("localhost").unwrap_or(url));
+            match path.strip_prefix('/') {
+                Some(windows) if cfg!(windows) => PathBuf::from(windows),
+                _ => PathBuf::from(path),
+            }
+        }
+        None if is_remote(line) => return None,
+        None => PathBuf::from(line),
+    };
+    Some(folder.join(path))
+}
+
+/// Returns whether the playlist line `line` is a URL other than `file://`.
+fn is_remote(line: &str) -> bool {
+    let line = line.trim();
+    line.contains("://") && !line.starts_with("file://")
+}
+
+/// Decodes the `%XX` escapes of a URL path. Malformed escapes are kept.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
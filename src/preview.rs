@@ -0,0 +1,158 @@
+//! Short before/after clips, to compare the original and processed audio
+//! without processing whole files.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::par::*;
+use crate::{
+    FileReport, FileStatus, PlannedFile, ProcessOptions, ProcessReport, Result, exec, ffmpeg,
+    process_planned_files,
+};
+
+/// Cuts a clip of `length` from the middle of each file that `files` will
+/// process, and processes the clip with `options`. The clips of the originals
+/// are written to `original` in `preview_dir`, and the processed clips to
+/// `processed`, both at the relative paths of their files, so they can be
+/// listened to side by side. No file of the plan is modified.
+///
+/// The clips of the originals are copied without re-encoding, so they may
+/// start and end a little off. With
+/// [`ProcessOptions::target_duration`], each clip is sped up as its whole
+/// file would be. Archives are not previewed.
+///
+/// # Arguments
+///
+/// * `files` - The planned files, as returned by [`plan`](crate::plan).
+/// * `options` - The options to preview.
+/// * `length` - The length of the clips cut from the originals.
+/// * `preview_dir` - The folder to write the clips to.
+///
+/// # Returns
+///
+/// * `Result<ProcessReport>` - The report of the processed clips, with the paths of the clips
+///   of the originals (or of the files whose clip could not be cut), or [`Error::FfmpegNotFound`](crate::Error::FfmpegNotFound) if ffmpeg
+///   is missing.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use audio_batch_speedup::{AudioFormat, ProcessOptions, plan, render_previews, sample};
+///
+/// let options = ProcessOptions::new(1.8, AudioFormat::ALL);
+/// let mut files = plan("path/to/audio/files", &options).unwrap();
+/// sample(&mut files, 5);
+/// render_previews(&files, &options, Duration::from_secs(20), "preview").unwrap();
+/// ```
+pub fn render_previews(
+    files: &[PlannedFile],
+    options: &ProcessOptions,
+    length: Duration,
+    preview_dir: impl AsRef<Path>,
+) -> Result<ProcessReport> {
+    let preview_dir = preview_dir.as_ref();
+    ffmpeg::check_ffmpeg(&*options.runner)?;
+    Ok(ProcessReport {
+        files: files
+            .par_iter()
+            .filter(|f| f.will_process() && !f.archive)
+            .flat_map_iter(|file| render_preview(file, options, length, preview_dir))
+            .collect(),
+    })
+}
+
+/// Cuts and processes the clip of a single file for [`render_previews`].
+fn render_preview(
+    file: &PlannedFile,
+    options: &ProcessOptions,
+    length: Duration,
+    preview_dir: &Path,
+) -> Vec<FileReport> {
+    let failed = |message: String| {
+        vec![FileReport {
+            status: FileStatus::Failed,
+            stderr: Some(message),
+            ..FileReport::from_planned(file)
+        }]
+    };
+    let duration = ffmpeg::probe_duration(&*options.runner, &file.path);
+    let speed = if options.target_duration.is_some() {
+        match options.speed_for(duration, None) {
+            Ok(Some(speed)) => speed,
+            Ok(None) => {
+                return vec![FileReport {
+                    status: FileStatus::Skipped,
+                    ..FileReport::from_planned(file)
+                }];
+            }
+            Err(e) => return failed(e),
+        }
+    } else {
+        options.speed
+    };
+    let start = duration.map_or(Duration::ZERO, |d| d.saturating_sub(length) / 2);
+
+    let clip = preview_dir.join("original").join(&file.relative_path);
+    if let Some(parent) = clip.parent()
+        && let Err(e) = std::fs::create_dir_all(parent)
+    {
+        return failed(format!("cannot create {}: {}", parent.display(), e));
+    }
+    let output = options.runner.output(
+        exec::command("ffmpeg")
+            .arg("-ss")
+            .arg(format!("{:.3}", start.as_secs_f64()))
+            .arg("-t")
+            .arg(format!("{:.3}", length.as_secs_f64()))
+            .arg("-i")
+            .arg(&file.path)
+            .args(["-c", "copy", "-loglevel", "error", "-nostats"])
+            .arg(&clip)
+            .arg("-y")
+            .stdin(Stdio::null()),
+    );
+    match output {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => return failed(String::from_utf8_lossy(&output.stderr).into_owned()),
+        Err(e) => return failed(format!("failed to run ffmpeg: {}", e)),
+    }
+    let metadata = match std::fs::metadata(&clip) {
+        Ok(metadata) => metadata,
+        Err(e) => return failed(format!("cannot read {}: {}", clip.display(), e)),
+    };
+
+    let clip_options = ProcessOptions {
+        speed,
+        target_duration: None,
+        output_dir: Some(preview_dir.join("processed")),
+        name_template: None,
+        segment: None,
+        backup_dir: None,
+        journal: None,
+        pre_hook: None,
+        post_hook: None,
+        archives: false,
+        stage_local: false,
+        record_durations: false,
+        dedup: false,
+        progress_bar: false,
+        ..options.clone()
+    };
+    let planned = PlannedFile {
+        path: clip,
+        relative_path: file.relative_path.clone(),
+        format: file.format,
+        video: file.video,
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+        archive: false,
+        skip_reason: None,
+    };
+    match process_planned_files(vec![planned], &clip_options) {
+        Ok(report) => report.files,
+        Err(e) => failed(e.to_string()),
+    }
+}
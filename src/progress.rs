@@ -0,0 +1,93 @@
+//! Progress bars drawn with indicatif, or stand-ins that draw nothing when
+//! the `progress` feature is disabled.
+
+#[cfg(feature = "progress")]
+pub(crate) use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+#[cfg(not(feature = "progress"))]
+pub(crate) use hidden::*;
+
+#[cfg(not(feature = "progress"))]
+mod hidden {
+    use std::borrow::Cow;
+
+    /// Stands in for `indicatif::ProgressDrawTarget`.
+    pub(crate) struct ProgressDrawTarget;
+
+    impl ProgressDrawTarget {
+        pub(crate) fn hidden() -> Self {
+            Self
+        }
+    }
+
+    /// Stands in for `indicatif::ProgressStyle`.
+    #[derive(Clone)]
+    pub(crate) struct ProgressStyle;
+
+    impl ProgressStyle {
+        pub(crate) fn default_bar() -> Self {
+            Self
+        }
+
+        pub(crate) fn template(self, _template: &str) -> Result<Self, std::convert::Infallible> {
+            Ok(self)
+        }
+
+        pub(crate) fn progress_chars(self, _chars: &str) -> Self {
+            self
+        }
+    }
+
+    /// Stands in for `indicatif::ProgressBar`.
+    #[derive(Clone)]
+    pub(crate) struct ProgressBar;
+
+    impl ProgressBar {
+        pub(crate) fn new(_len: u64) -> Self {
+            Self
+        }
+
+        pub(crate) fn with_draw_target(_len: Option<u64>, _target: ProgressDrawTarget) -> Self {
+            Self
+        }
+
+        pub(crate) fn set_style(&self, _style: ProgressStyle) {}
+
+        pub(crate) fn set_message(&self, _message: impl Into<Cow<'static, str>>) {}
+
+        pub(crate) fn set_position(&self, _position: u64) {}
+
+        pub(crate) fn set_length(&self, _len: u64) {}
+
+        pub(crate) fn inc(&self, _delta: u64) {}
+
+        pub(crate) fn dec(&self, _delta: u64) {}
+
+        pub(crate) fn finish_and_clear(&self) {}
+
+        pub(crate) fn finish_with_message(&self, _message: impl Into<Cow<'static, str>>) {}
+    }
+
+    /// Stands in for `indicatif::MultiProgress`.
+    pub(crate) struct MultiProgress;
+
+    impl MultiProgress {
+        pub(crate) fn new() -> Self {
+            Self
+        }
+
+        pub(crate) fn with_draw_target(_target: ProgressDrawTarget) -> Self {
+            Self
+        }
+
+        pub(crate) fn add(&self, bar: ProgressBar) -> ProgressBar {
+            bar
+        }
+
+        pub(crate) fn insert_from_back(&self, _index: usize, bar: ProgressBar) -> ProgressBar {
+            bar
+        }
+
+        pub(crate) fn remove(&self, _bar: &ProgressBar) {}
+    }
+}
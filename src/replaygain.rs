@@ -0,0 +1,75 @@
+//! Recomputing ReplayGain tags on processed files.
+
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::{CommandRunner, Error, Result, exec, temp_path};
+
+/// Scans `path` with ffmpeg's `replaygain` filter and rewrites its
+/// `REPLAYGAIN_TRACK_GAIN` and `REPLAYGAIN_TRACK_PEAK` tags in place, by
+/// remuxing it without re-encoding.
+///
+/// # Returns
+///
+/// * `Result<()>` - An error if ffmpeg could not be run or failed, or [`Error::Detection`]
+///   if it did not report a gain.
+pub(crate) fn rewrite_replaygain(runner: &dyn CommandRunner, path: &Path) -> Result<()> {
+    let output = runner
+        .output(
+            exec::command("ffmpeg")
+                .args(["-hide_banner", "-nostats", "-i"])
+                .arg(path)
+                .args(["-vn", "-af", "replaygain", "-f", "null", "-"])
+                .stdin(Stdio::null()),
+        )
+        .map_err(Error::from_spawn)?;
+    if !output.status.success() {
+        return Err(Error::FfmpegFailed {
+            path: path.to_path_buf(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    let log = String::from_utf8_lossy(&output.stderr);
+    let (Some(gain), Some(peak)) = (
+        value_after(&log, "track_gain = "),
+        value_after(&log, "track_peak = "),
+    ) else {
+        return Err(Error::Detection {
+            path: path.to_path_buf(),
+            what: "ReplayGain",
+        });
+    };
+    let gain = format!("REPLAYGAIN_TRACK_GAIN={} dB", gain);
+    let peak = format!("REPLAYGAIN_TRACK_PEAK={}", peak);
+
+    let temp = temp_path(path).ok_or_else(|| Error::NotAFile(path.to_path_buf()))?;
+    // Ogg-based formats keep their tags on the audio streams
+    let remux = runner
+        .output(
+            exec::command("ffmpeg")
+                .arg("-i")
+                .arg(path)
+                .args(["-map", "0", "-c", "copy", "-map_metadata", "0"])
+                .args(["-metadata", &gain, "-metadata", &peak])
+                .args(["-metadata:s:a", &gain, "-metadata:s:a", &peak])
+                .arg(&temp)
+                .args(["-y", "-loglevel", "error", "-nostats"])
+                .stdin(Stdio::null()),
+        )
+        .map_err(Error::from_spawn)?;
+    if !remux.status.success() {
+        _ = std::fs::remove_file(&temp);
+        return Err(Error::FfmpegFailed {
+            path: path.to_path_buf(),
+            stderr: String::from_utf8_lossy(&remux.stderr).into_owned(),
+        });
+    }
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Returns the first whitespace-separated word after `key` in `log`.
+fn value_after<'a>(log: &'a str, key: &str) -> Option<&'a str> {
+    let rest = &log[log.find(key)? + key.len()..];
+    rest.split_whitespace().next()
+}
@@ -0,0 +1,294 @@
+//! Structured results of a batch run.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{AudioFormat, Loudness, PlannedFile};
+
+/// The status of a single file after processing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileStatus {
+    /// The file was sped up successfully.
+    Processed,
+    /// The file was not processed (e.g., unsupported or unselected format).
+    Skipped,
+    /// Processing the file failed.
+    Failed,
+}
+
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Processed => "processed",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+        })
+    }
+}
+
+/// Information collected about a single file during a batch run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileReport {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// The detected audio format, if detection succeeded.
+    pub format: Option<AudioFormat>,
+    /// Whether the file was handled as a video.
+    pub video: bool,
+    /// What happened to the file.
+    pub status: FileStatus,
+    /// The speed applied to the file, if it was processed.
+    pub speed: Option<f32>,
+    /// Duration of the original file, if it was probed.
+    pub input_duration: Option<Duration>,
+    /// Duration of the processed file, if it was probed.
+    pub output_duration: Option<Duration>,
+    /// Loudness of the original file, if it was analyzed with
+    /// [`ProcessOptions::analyze_loudness`](crate::ProcessOptions::analyze_loudness).
+    pub input_loudness: Option<Loudness>,
+    /// Loudness of the processed file, if it was analyzed.
+    pub output_loudness: Option<Loudness>,
+    /// Size of the original file in bytes.
+    pub input_size: u64,
+    /// Size of the processed file in bytes, if it was produced.
+    pub output_size: Option<u64>,
+    /// Where the processed file was written, if it was produced. This is
+    /// [`path`](Self::path) itself unless an output directory or name
+    /// template is used.
+    pub output_path: Option<PathBuf>,
+    /// Every part of a segmented output with its duration, in order, the
+    /// first one being [`output_path`](Self::output_path). Empty unless
+    /// [`ProcessOptions::segment`](crate::ProcessOptions::segment) is set.
+    pub output_parts: Vec<(PathBuf, Option<Duration>)>,
+    /// What ffmpeg wrote to its standard error, if the file failed and ffmpeg
+    /// explained why.
+    pub stderr: Option<String>,
+    /// Whether the file failed because another program had it open (e.g., a
+    /// player on Windows). Such files are retried once at the end of the run.
+    pub locked: bool,
+    /// Whether the file was skipped because it changed while the run was
+    /// going (e.g., a recorder was still writing it). Such files are retried
+    /// at the end of the run.
+    pub changing: bool,
+}
+
+impl FileReport {
+    /// Creates a report for a planned file, with no processing results yet.
+    pub(crate) fn from_planned(file: &PlannedFile) -> Self {
+        Self {
+            format: file.format,
+            video: file.video,
+            input_size: file.size,
+            ..Self::new(file.path.clone())
+        }
+    }
+
+    /// Creates a report for `path` with no information collected yet.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            format: None,
+            video: false,
+            status: FileStatus::Skipped,
+            speed: None,
+            input_duration: None,
+            output_duration: None,
+            input_loudness: None,
+            output_loudness: None,
+            input_size: 0,
+            output_size: None,
+            output_path: None,
+            output_parts: Vec::new(),
+            stderr: None,
+            locked: false,
+            changing: false,
+        }
+    }
+}
+
+/// Totals of a batch run for a single format, as returned by
+/// [`ProcessReport::by_format`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FormatSummary {
+    /// The format, or `None` for files whose format was not detected.
+    pub format: Option<AudioFormat>,
+    /// Number of files that were sped up successfully.
+    pub processed: usize,
+    /// Number of files that were skipped.
+    pub skipped: usize,
+    /// Number of files that failed to process.
+    pub failed: usize,
+    /// Total duration of the processed files before processing, counted as in
+    /// [`ProcessReport::total_input_duration`].
+    pub input_duration: Duration,
+    /// Total duration of the processed files after processing.
+    pub output_duration: Duration,
+    /// Total size in bytes of the processed files before processing.
+    pub bytes_before: u64,
+    /// Total size in bytes of the processed files after processing.
+    pub bytes_after: u64,
+}
+
+impl FormatSummary {
+    /// Adds `file` to the totals.
+    fn add(&mut self, file: &FileReport) {
+        match file.status {
+            FileStatus::Processed => self.processed += 1,
+            FileStatus::Skipped => {
+                self.skipped += 1;
+                return;
+            }
+            FileStatus::Failed => {
+                self.failed += 1;
+                return;
+            }
+        }
+        if let (Some(input), Some(output)) = (file.input_duration, file.output_duration) {
+            self.input_duration += input;
+            self.output_duration += output;
+        }
+        self.bytes_before += file.input_size;
+        self.bytes_after += file.output_size.unwrap_or(0);
+    }
+}
+
+/// Summary of a batch run, returned by [`crate::process_audio_files`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessReport {
+    /// One entry per file encountered, in no particular order.
+    pub files: Vec<FileReport>,
+}
+
+impl ProcessReport {
+    fn count(&self, status: FileStatus) -> usize {
+        self.files.iter().filter(|f| f.status == status).count()
+    }
+
+    fn processed(&self) -> impl Iterator<Item = &FileReport> {
+        self.files
+            .iter()
+            .filter(|f| f.status == FileStatus::Processed)
+    }
+
+    /// Number of files that were sped up successfully.
+    pub fn processed_count(&self) -> usize {
+        self.count(FileStatus::Processed)
+    }
+
+    /// Number of files that were skipped.
+    pub fn skipped_count(&self) -> usize {
+        self.count(FileStatus::Skipped)
+    }
+
+    /// Number of files that failed to process.
+    pub fn failed_count(&self) -> usize {
+        self.count(FileStatus::Failed)
+    }
+
+    /// Number of files that failed because another program had them open.
+    pub fn locked_count(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| f.status == FileStatus::Failed && f.locked)
+            .count()
+    }
+
+    /// Number of files that were skipped because they were still changing.
+    pub fn changing_count(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| f.status == FileStatus::Skipped && f.changing)
+            .count()
+    }
+
+    /// Total duration of the processed files before processing.
+    ///
+    /// Only files whose input and output durations are both known are counted.
+    pub fn total_input_duration(&self) -> Duration {
+        self.processed()
+            .filter(|f| f.output_duration.is_some())
+            .filter_map(|f| f.input_duration)
+            .sum()
+    }
+
+    /// Total duration of the processed files after processing.
+    ///
+    /// Only files whose input and output durations are both known are counted.
+    pub fn total_output_duration(&self) -> Duration {
+        self.processed()
+            .filter(|f| f.input_duration.is_some())
+            .filter_map(|f| f.output_duration)
+            .sum()
+    }
+
+    /// Listening time saved by the speed-up.
+    pub fn time_saved(&self) -> Duration {
+        self.total_input_duration()
+            .saturating_sub(self.total_output_duration())
+    }
+
+    /// Total size in bytes of the processed files before processing.
+    pub fn bytes_before(&self) -> u64 {
+        self.processed().map(|f| f.input_size).sum()
+    }
+
+    /// Total size in bytes of the processed files after processing.
+    pub fn bytes_after(&self) -> u64 {
+        self.processed().filter_map(|f| f.output_size).sum()
+    }
+
+    /// Breaks the run down by format, in the order of the [`AudioFormat`]
+    /// flags, with undetected formats last.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<FormatSummary>` - One summary per format seen in the run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use audio_batch_speedup::{AudioFormat, FileReport, FileStatus, ProcessReport};
+    ///
+    /// let mut mp3 = FileReport::new("a.mp3");
+    /// mp3.format = Some(AudioFormat::MP3);
+    /// mp3.status = FileStatus::Processed;
+    /// let mut ogg = FileReport::new("b.ogg");
+    /// ogg.format = Some(AudioFormat::OGG);
+    /// let report = ProcessReport { files: vec![mp3, ogg] };
+    ///
+    /// let summaries = report.by_format();
+    /// assert_eq!(summaries[0].format, Some(AudioFormat::OGG));
+    /// assert_eq!(summaries[0].skipped, 1);
+    /// assert_eq!(summaries[1].format, Some(AudioFormat::MP3));
+    /// assert_eq!(summaries[1].processed, 1);
+    /// ```
+    pub fn by_format(&self) -> Vec<FormatSummary> {
+        let mut summaries: Vec<FormatSummary> = Vec::new();
+        for file in &self.files {
+            let index = match summaries.iter().position(|s| s.format == file.format) {
+                Some(index) => index,
+                None => {
+                    summaries.push(FormatSummary {
+                        format: file.format,
+                        ..FormatSummary::default()
+                    });
+                    summaries.len() - 1
+                }
+            };
+            summaries[index].add(file);
+        }
+        summaries.sort_by_key(|s| (s.format.is_none(), s.format));
+        summaries
+    }
+
+    /// Number of processed files whose output clips, i.e. has a true peak
+    /// above 0 dBTP. Only files analyzed with
+    /// [`ProcessOptions::analyze_loudness`](crate::ProcessOptions::analyze_loudness)
+    /// are counted.
+    pub fn clipping_count(&self) -> usize {
+        self.processed()
+            .filter(|f| f.output_loudness.is_some_and(|l| l.clips()))
+            .count()
+    }
+}
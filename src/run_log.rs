@@ -0,0 +1,68 @@
+//! A detailed per-file log of a batch run, written independently of the
+//! terminal log level.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A log file recording every ffmpeg invocation of a run.
+pub(crate) struct RunLog {
+    file: Mutex<File>,
+}
+
+impl RunLog {
+    /// Opens the log file at `path` for appending, creating it if needed, so
+    /// several runs (e.g., one per speed variant) can share it.
+    pub(crate) fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            file: Mutex::new(File::options().create(true).append(true).open(path)?),
+        })
+    }
+
+    /// Records the ffmpeg `command` (as formatted by its `Debug` impl) run
+    /// for `path`, and its result.
+    pub(crate) fn record(
+        &self,
+        path: &Path,
+        command: &str,
+        result: &std::io::Result<(ExitStatus, String)>,
+    ) {
+        let mut entry = format!(
+            "[{}] {}\n  command: {}\n",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            path.display(),
+            command
+        );
+        match result {
+            Ok((status, stderr)) => {
+                _ = writeln!(entry, "  status: {}", status);
+                for line in stderr.lines() {
+                    _ = writeln!(entry, "  stderr: {}", line);
+                }
+            }
+            Err(e) => _ = writeln!(entry, "  error: {}", e),
+        }
+        self.write(&entry);
+    }
+
+    /// Records a failure for `path` that happened without running ffmpeg.
+    pub(crate) fn record_error(&self, path: &Path, message: &str) {
+        self.write(&format!(
+            "[{}] {}\n  error: {}\n",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            path.display(),
+            message
+        ));
+    }
+
+    fn write(&self, entry: &str) {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(entry.as_bytes()) {
+            log::warn!("Error writing to the log file: {}", e);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! The [`CommandRunner`] abstraction through which ffmpeg and ffprobe are run.
+
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ffmpeg;
+
+/// Runs the ffmpeg and ffprobe commands built while processing files.
+///
+/// The commands are fully configured (program, arguments and standard
+/// input) when they are handed over, so an implementation can inspect them
+/// with [`Command::get_program`] and [`Command::get_args`]. Besides
+/// [`SystemRunner`], which spawns them, implementations can fake the results
+/// to test the processing pipeline without ffmpeg, or pass the work to
+/// another backend.
+///
+/// # Example
+///
+/// A runner that fails every command, as if ffmpeg rejected every file:
+///
+/// ```no_run
+/// # #[cfg(unix)] {
+/// use std::io;
+/// use std::os::unix::process::ExitStatusExt;
+/// use std::process::{Command, ExitStatus, Output};
+/// use std::time::Duration;
+///
+/// use audio_batch_speedup::{AudioFormat, CommandRunner, ProcessOptions, Runner};
+///
+/// struct Failing;
+///
+/// impl CommandRunner for Failing {
+///     fn output(&self, _command: &mut Command) -> io::Result<Output> {
+///         Ok(Output {
+///             status: ExitStatus::from_raw(1 << 8),
+///             stdout: Vec::new(),
+///             stderr: b"Invalid data found when processing input".to_vec(),
+///         })
+///     }
+///
+///     fn run_with_progress(
+///         &self,
+///         _command: &mut Command,
+///         _timeout: Option<Duration>,
+///         _on_progress: &mut dyn FnMut(Duration),
+///     ) -> io::Result<(ExitStatus, String)> {
+///         Ok((ExitStatus::from_raw(1 << 8), "Invalid data".to_string()))
+///     }
+/// }
+///
+/// let options = ProcessOptions {
+///     runner: Runner::new(Failing),
+///     ..ProcessOptions::new(1.5, AudioFormat::ALL)
+/// };
+/// # }
+/// ```
+pub trait CommandRunner: Send + Sync {
+    /// Runs `command` to completion and collects its standard output and
+    /// standard error, like [`Command::output`].
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Output>` - The exit status and output of the command, or an error if it
+    ///   could not be run.
+    fn output(&self, command: &mut Command) -> io::Result<Output>;
+
+    /// Runs an ffmpeg command that was given `-progress pipe:1`, reporting the
+    /// output timestamp to `on_progress` each time ffmpeg emits a progress
+    /// block. If the command is still running after `timeout`, it is killed.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<(ExitStatus, String)>` - The exit status of the command and what it wrote
+    ///   to its standard error, or an error of kind [`io::ErrorKind::TimedOut`] if it was
+    ///   killed.
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)>;
+}
+
+/// The default [`CommandRunner`], which spawns the commands as processes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        command.output()
+    }
+
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        ffmpeg::run_with_progress(command, timeout, on_progress)
+    }
+}
+
+/// Lets the caller keep a handle on a runner given to the options, e.g. to
+/// inspect the commands it received.
+impl<T: CommandRunner + ?Sized> CommandRunner for Arc<T> {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        (**self).output(command)
+    }
+
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        (**self).run_with_progress(command, timeout, on_progress)
+    }
+}
+
+/// A shared [`CommandRunner`], as stored in
+/// [`ProcessOptions::runner`](crate::ProcessOptions::runner). Clones share the
+/// same runner and compare equal.
+#[derive(Clone)]
+pub struct Runner(Arc<dyn CommandRunner>);
+
+impl Runner {
+    /// Wraps `runner` to be shared between options.
+    pub fn new(runner: impl CommandRunner + 'static) -> Self {
+        Self(Arc::new(runner))
+    }
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new(SystemRunner)
+    }
+}
+
+impl Deref for Runner {
+    type Target = dyn CommandRunner;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for Runner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Runner")
+    }
+}
+
+impl PartialEq for Runner {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
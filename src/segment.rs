@@ -0,0 +1,51 @@
+//! Splitting outputs into fixed-length parts with ffmpeg's segment muxer.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::clean::TEMP_PREFIX;
+
+/// Distinguishes the temporary patterns of files processed concurrently.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the ffmpeg output pattern for the temporary parts of a file whose
+/// first part is `first_part`. The pattern is placed in `temp_dir`, or the
+/// same folder if not set, and uses the same extension, but avoids any
+/// character of the original name that ffmpeg could take for a pattern.
+pub(crate) fn temp_pattern(first_part: &Path, temp_dir: Option<&Path>) -> PathBuf {
+    let mut name = format!(
+        "{}segment_{}_{}_%03d",
+        TEMP_PREFIX,
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    );
+    if let Some(extension) = first_part.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    match temp_dir {
+        Some(temp_dir) => temp_dir.join(name),
+        None => first_part.with_file_name(name),
+    }
+}
+
+/// Returns the temporary parts written for `pattern`, in order. Parts are
+/// numbered from 1.
+pub(crate) fn temp_parts(pattern: &Path) -> Vec<PathBuf> {
+    let Some(name) = pattern.file_name().and_then(|n| n.to_str()) else {
+        return Vec::new();
+    };
+    (1..)
+        .map(|i| pattern.with_file_name(name.replace("%03d", &format!("{:03}", i))))
+        .take_while(|part| part.exists())
+        .collect()
+}
+
+/// Removes the temporary parts written for `pattern`.
+pub(crate) fn remove_temp_parts(pattern: &Path) {
+    for part in temp_parts(pattern) {
+        if let Err(e) = std::fs::remove_file(&part) {
+            log::error!("Error removing temp file {}: {}", part.display(), e);
+        }
+    }
+}
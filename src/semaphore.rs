@@ -0,0 +1,48 @@
+//! A minimal counting semaphore used to bound concurrent ffmpeg processes.
+
+use std::sync::{Condvar, Mutex};
+
+/// A counting semaphore.
+pub(crate) struct Semaphore {
+    permits: Mutex<usize>,
+    available: Condvar,
+}
+
+/// A permit acquired from a [`Semaphore`], released when dropped.
+pub(crate) struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with `permits` permits available.
+    pub(crate) fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a permit is available and takes it.
+    pub(crate) fn acquire(&self) -> SemaphorePermit<'_> {
+        let mut permits = self
+            .available
+            .wait_while(
+                self.permits.lock().unwrap_or_else(|e| e.into_inner()),
+                |p| *p == 0,
+            )
+            .unwrap_or_else(|e| e.into_inner());
+        *permits -= 1;
+        SemaphorePermit { semaphore: self }
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        *self
+            .semaphore
+            .permits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) += 1;
+        self.semaphore.available.notify_one();
+    }
+}
@@ -0,0 +1,271 @@
+//! The `serve` subcommand: a local HTTP API that queues speedup jobs, so other
+//! programs (e.g. home automation) can trigger processing.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use audio_batch_speedup::{AudioFormat, ProcessEvent, ProcessOptions, Speed};
+use log::{error, info, warn};
+
+use crate::http::{self, Request};
+use crate::{json_path, json_string};
+
+/// The state of a queued job.
+#[derive(Clone, Copy)]
+enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A speedup job submitted over the API.
+struct Job {
+    folder: PathBuf,
+    speed: f32,
+    formats: AudioFormat,
+    state: JobState,
+    /// Number of files to process, known once the job is planned.
+    total: usize,
+    /// Number of files finished so far.
+    done: usize,
+    processed: usize,
+    skipped: usize,
+    failed: usize,
+    error: Option<String>,
+}
+
+/// The jobs of the server, identified by their index plus one, and the queue
+/// feeding the worker.
+struct Server {
+    jobs: Mutex<Vec<Job>>,
+    queue: Sender<usize>,
+    /// The address the server listens on, which requests must be sent to.
+    listen: SocketAddr,
+    /// The token requests must carry.
+    token: String,
+}
+
+/// Runs the `serve` subcommand, listening on `listen` until killed. Jobs are
+/// processed one after another, each using all CPU cores. Requests must carry
+/// `token`, or a random one logged at start if not given.
+pub(crate) fn serve(listen: SocketAddr, token: Option<String>) -> Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    let listen = listener.local_addr()?;
+    if !listen.ip().is_loopback() {
+        warn!(
+            "The API is served over plain HTTP; anyone who can watch the traffic to {} can read its token.",
+            listen
+        );
+    }
+    let token = token.unwrap_or_else(|| {
+        let token = http::random_token();
+        info!(
+            "Authenticate with the header `Authorization: Bearer {}`.",
+            token
+        );
+        token
+    });
+    info!("Listening on http://{}", listen);
+
+    let (queue, receiver) = mpsc::channel();
+    let server = Arc::new(Server {
+        jobs: Mutex::new(Vec::new()),
+        queue,
+        listen,
+        token,
+    });
+    let worker = Arc::clone(&server);
+    std::thread::spawn(move || {
+        for id in receiver {
+            worker.run_job(id);
+        }
+    });
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || {
+            if let Err(e) = server.handle(stream) {
+                warn!("Error handling request: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Server {
+    /// Locks the job list, even if a thread panicked while holding it.
+    fn jobs(&self) -> std::sync::MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Plans and processes the job `id`, updating its progress as files
+    /// finish.
+    fn run_job(&self, id: usize) {
+        let (folder, options) = {
+            let mut jobs = self.jobs();
+            let job = &mut jobs[id - 1];
+            job.state = JobState::Running;
+            let options = ProcessOptions {
+                progress_bar: false,
+                ..ProcessOptions::new(job.speed, job.formats)
+            };
+            (job.folder.clone(), options)
+        };
+        info!("Starting job {} for folder: {}", id, folder.display());
+
+        let result = audio_batch_speedup::plan(&folder, &options).and_then(|files| {
+            self.jobs()[id - 1].total = files.iter().filter(|f| f.will_process()).count();
+            audio_batch_speedup::process_planned_files_with_events(files, &options, |event| {
+                if let ProcessEvent::FileFinished { .. } | ProcessEvent::FileFailed { .. } = event {
+                    self.jobs()[id - 1].done += 1;
+                }
+            })
+        });
+
+        let mut jobs = self.jobs();
+        let job = &mut jobs[id - 1];
+        match result {
+            Ok(report) => {
+                job.state = JobState::Done;
+                job.processed = report.processed_count();
+                job.skipped = report.skipped_count();
+                job.failed = report.failed_count();
+                info!(
+                    "Finished job {}: {} processed, {} failed.",
+                    id, job.processed, job.failed
+                );
+            }
+            Err(e) => {
+                error!("Job {} failed: {}", id, e);
+                job.state = JobState::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Answers a single HTTP request on `stream`, if it is sent to the
+    /// server's address with its token.
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let request = http::read_request(&stream)?;
+        let (status, response) = if !request.is_same_origin(self.listen) {
+            error_response("403 Forbidden", "unexpected host or origin")
+        } else if !request.has_token(&self.token) {
+            error_response("401 Unauthorized", "missing or wrong token")
+        } else {
+            self.route(&request)
+        };
+        http::write_response(&stream, status, "application/json", response.as_bytes())
+    }
+
+    /// Answers an authenticated request.
+    fn route(&self, request: &Request) -> (&'static str, String) {
+        match (request.method.as_str(), request.path.trim_end_matches('/')) {
+            ("POST", "/jobs") => self.submit(request),
+            ("GET", "/jobs") => {
+                let jobs = self.jobs();
+                let list: Vec<_> = (1..=jobs.len())
+                    .map(|id| job_json(id, &jobs[id - 1]))
+                    .collect();
+                ("200 OK", format!("[{}]", list.join(",")))
+            }
+            ("GET", path) => match path
+                .strip_prefix("/jobs/")
+                .and_then(|id| id.parse::<usize>().ok())
+            {
+                Some(id) if (1..=self.jobs().len()).contains(&id) => {
+                    ("200 OK", job_json(id, &self.jobs()[id - 1]))
+                }
+                _ => error_response("404 Not Found", "no such job"),
+            },
+            _ => error_response("404 Not Found", "unknown endpoint"),
+        }
+    }
+
+    /// Queues a job from the `folder`, `speed` and `formats` parameters.
+    fn submit(&self, request: &Request) -> (&'static str, String) {
+        let param = |name| request.param(name);
+        let Some(folder) = param("folder").map(PathBuf::from) else {
+            return error_response("400 Bad Request", "missing folder");
+        };
+        if !folder.is_dir() {
+            return error_response("400 Bad Request", "folder is not a directory");
+        }
+        let speed = match param("speed").map(str::parse::<Speed>) {
+            Some(Ok(speed)) => speed.factor(),
+            Some(Err(e)) => return error_response("400 Bad Request", &e.to_string()),
+            None => return error_response("400 Bad Request", "missing speed"),
+        };
+        let formats = match param("formats").unwrap_or("all").parse::<AudioFormat>() {
+            Ok(formats) if !formats.is_empty() => formats,
+            Ok(_) => return error_response("400 Bad Request", "no format selected"),
+            Err(e) => return error_response("400 Bad Request", &e.to_string()),
+        };
+
+        let id = {
+            let mut jobs = self.jobs();
+            jobs.push(Job {
+                folder,
+                speed,
+                formats,
+                state: JobState::Queued,
+                total: 0,
+                done: 0,
+                processed: 0,
+                skipped: 0,
+                failed: 0,
+                error: None,
+            });
+            jobs.len()
+        };
+        if self.queue.send(id).is_err() {
+            return error_response("500 Internal Server Error", "the worker has stopped");
+        }
+        ("202 Accepted", format!("{{\"id\":{}}}", id))
+    }
+}
+
+/// Encodes the job `id` as a JSON object.
+fn job_json(id: usize, job: &Job) -> String {
+    let state = match job.state {
+        JobState::Queued => "queued",
+        JobState::Running => "running",
+        JobState::Done => "done",
+        JobState::Failed => "failed",
+    };
+    let percent = match job.state {
+        JobState::Done => 100.0,
+        _ if job.total == 0 => 0.0,
+        _ => job.done as f64 * 100.0 / job.total as f64,
+    };
+    format!(
+        "{{\"id\":{},\"folder\":{},\"speed\":{},\"state\":{},\"total\":{},\"done\":{},\"percent\":{:.1},\"processed\":{},\"skipped\":{},\"failed\":{},\"error\":{}}}",
+        id,
+        json_path(&job.folder),
+        job.speed,
+        json_string(state),
+        job.total,
+        job.done,
+        percent,
+        job.processed,
+        job.skipped,
+        job.failed,
+        job.error.as_deref().map_or("null".to_string(), json_string),
+    )
+}
+
+/// Returns an error status with a JSON body carrying `message`.
+fn error_response(status: &'static str, message: &str) -> (&'static str, String) {
+    (status, format!("{{\"error\":{}}}", json_string(message)))
+}
@@ -0,0 +1,352 @@
+//! Rescaling of timestamped companion files (`.lrc`, `.srt`, `.cue`).
+//!
+//! The files are processed as bytes rather than text, so that sidecars in
+//! legacy encodings (e.g., Shift-JIS or GBK lyrics) are left intact apart
+//! from their ASCII timestamps.
+
+use std::path::Path;
+
+use log::{debug, error, warn};
+
+/// Rescales every sidecar file next to `audio` that shares its file stem,
+/// writing the result next to `output` with the same stem as `output`. Pass
+/// the same path twice to rescale sidecars in place. The `FILE` commands of
+/// CUE sheets that name `audio` are pointed to `output`, and the sidecars of
+/// an `audio` that is kept are never overwritten.
+///
+/// `map_time` converts a timestamp of the original audio, in seconds, into the
+/// corresponding timestamp of the processed audio. Failures are logged and do
+/// not affect the audio file.
+pub(crate) fn rescale_sidecars(audio: &Path, output: &Path, map_time: impl Fn(f64) -> f64) {
+    type Rescale = fn(&[u8], &dyn Fn(f64) -> f64) -> Vec<u8>;
+    let kinds: [(&str, Rescale); 3] = [
+        ("lrc", rescale_lrc),
+        ("srt", rescale_srt),
+        ("cue", rescale_cue),
+    ];
+
+    for (extension, rescale) in kinds {
+        let sidecar = audio.with_extension(extension);
+        if sidecar == audio || !sidecar.is_file() {
+            continue;
+        }
+        let target = output.with_extension(extension);
+        if target == sidecar && output != audio {
+            warn!(
+                "Not rescaling sidecar {}, which belongs to the kept original",
+                sidecar.display()
+            );
+            continue;
+        }
+        let result = std::fs::read(&sidecar).and_then(|content| {
+            let mut content = rescale(&content, &map_time);
+            if extension == "cue"
+                && let (Some(from), Some(to)) = (audio.file_name(), output.file_name())
+                && from != to
+            {
+                content = retarget_cue(&content, from.as_encoded_bytes(), to.as_encoded_bytes());
+            }
+            std::fs::write(&target, content)
+        });
+        match result {
+            Ok(()) => debug!(
+                "Rescaled sidecar {} to {}",
+                sidecar.display(),
+                target.display()
+            ),
+            Err(e) => error!("Error rescaling sidecar {}: {}", sidecar.display(), e),
+        }
+    }
+}
+
+/// Reads a run of ASCII digits starting at `*pos`, advancing past it.
+fn digits(input: &[u8], pos: &mut usize) -> Option<(u64, usize)> {
+    let start = *pos;
+    let mut value: u64 = 0;
+    while let Some(&b) = input.get(*pos).filter(|b| b.is_ascii_digit()) {
+        value = value.checked_mul(10)?.checked_add((b - b'0') as u64)?;
+        *pos += 1;
+    }
+    let len = *pos - start;
+    (len > 0).then_some((value, len))
+}
+
+/// Consumes `byte` at `*pos` if present.
+fn expect(input: &[u8], pos: &mut usize, byte: u8) -> Option<()> {
+    (input.get(*pos) == Some(&byte)).then(|| *pos += 1)
+}
+
+/// Rescales LRC timestamps such as `[01:23.45]` and the `[offset:...]` tag.
+fn rescale_lrc(input: &[u8], map_time: &dyn Fn(f64) -> f64) -> Vec<u8> {
+    /// Parses `mm:ss[.xx]]` at `pos`, returning the time, number of fraction
+    /// digits, and the position after the closing bracket.
+    fn parse(input: &[u8], mut pos: usize) -> Option<(f64, usize, usize)> {
+        let (minutes, _) = digits(input, &mut pos)?;
+        expect(input, &mut pos, b':')?;
+        let (seconds, _) = digits(input, &mut pos)?;
+        let mut fraction = 0.0;
+        let mut precision = 0;
+        if matches!(input.get(pos), Some(b'.' | b':')) {
+            pos += 1;
+            let (value, len) = digits(input, &mut pos)?;
+            fraction = value as f64 / 10f64.powi(len as i32);
+            precision = len;
+        }
+        expect(input, &mut pos, b']')?;
+        Some((
+            minutes as f64 * 60.0 + seconds as f64 + fraction,
+            precision,
+            pos,
+        ))
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut pos = 0;
+    while pos < input.len() {
+        if input[pos] == b'['
+            && let Some((time, precision, end)) = parse(input, pos + 1)
+        {
+            let scale = 10u64.pow(precision as u32);
+            let units = (map_time(time).max(0.0) * scale as f64).round() as u64;
+            let (minutes, seconds) = (units / scale / 60, units / scale % 60);
+            let timestamp = if precision > 0 {
+                format!(
+                    "[{:02}:{:02}.{:0precision$}]",
+                    minutes,
+                    seconds,
+                    units % scale
+                )
+            } else {
+                format!("[{:02}:{:02}]", minutes, seconds)
+            };
+            output.extend_from_slice(timestamp.as_bytes());
+            pos = end;
+        } else if input[pos..].starts_with(b"[offset:") {
+            // The offset is a duration in milliseconds, so scale it by the
+            // speed at the start of the file.
+            let mut end = pos + b"[offset:".len();
+            let sign = match input.get(end) {
+                Some(&b @ (b'+' | b'-')) => {
+                    end += 1;
+                    (b as char).to_string()
+                }
+                _ => String::new(),
+            };
+            match digits(input, &mut end).filter(|_| input.get(end) == Some(&b']')) {
+                Some((millis, _)) => {
+                    let secs = millis as f64 / 1000.0;
+                    let scaled = (map_time(secs) - map_time(0.0)) * 1000.0;
+                    output.extend_from_slice(format!("[offset:{}{:.0}]", sign, scaled).as_bytes());
+                    pos = end + 1;
+                }
+                None => {
+                    output.push(input[pos]);
+                    pos += 1;
+                }
+            }
+        } else {
+            output.push(input[pos]);
+            pos += 1;
+        }
+    }
+    output
+}
+
+/// Rescales SRT timestamps such as `00:01:23,456` on `-->` lines.
+fn rescale_srt(input: &[u8], map_time: &dyn Fn(f64) -> f64) -> Vec<u8> {
+    /// Parses `hh:mm:ss,mmm` at `pos`, returning the time, the millisecond
+    /// separator used, and the position after the timestamp.
+    fn parse(input: &[u8], mut pos: usize) -> Option<(f64, u8, usize)> {
+        let (hours, _) = digits(input, &mut pos)?;
+        expect(input, &mut pos, b':')?;
+        let (minutes, _) = digits(input, &mut pos)?;
+        expect(input, &mut pos, b':')?;
+        let (seconds, _) = digits(input, &mut pos)?;
+        let separator = *input.get(pos).filter(|b| matches!(b, b',' | b'.'))?;
+        pos += 1;
+        let (millis, len) = digits(input, &mut pos)?;
+        let fraction = millis as f64 / 10f64.powi(len as i32);
+        let time = hours as f64 * 3600.0 + minutes as f64 * 60.0 + seconds as f64 + fraction;
+        Some((time, separator, pos))
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    for line in input.split_inclusive(|&b| b == b'\n') {
+        if !line.windows(3).any(|w| w == b"-->") {
+            output.extend_from_slice(line);
+            continue;
+        }
+        let mut pos = 0;
+        while pos < line.len() {
+            let at_boundary = pos == 0 || !line[pos - 1].is_ascii_digit();
+            if at_boundary && let Some((time, separator, end)) = parse(line, pos) {
+                let millis = (map_time(time).max(0.0) * 1000.0).round() as u64;
+                output.extend_from_slice(
+                    format!(
+                        "{:02}:{:02}:{:02}{}{:03}",
+                        millis / 3_600_000,
+                        millis / 60_000 % 60,
+                        millis / 1000 % 60,
+                        separator as char,
+                        millis % 1000
+                    )
+                    .as_bytes(),
+                );
+                pos = end;
+            } else {
+                output.push(line[pos]);
+                pos += 1;
+            }
+        }
+    }
+    output
+}
+
+/// Rescales CUE sheet `INDEX`, `PREGAP` and `POSTGAP` times (`mm:ss:ff`, with
+/// 75 frames per second).
+fn rescale_cue(input: &[u8], map_time: &dyn Fn(f64) -> f64) -> Vec<u8> {
+    const FRAMES_PER_SECOND: u64 = 75;
+
+    /// Parses `mm:ss:ff` spanning exactly `token`.
+    fn parse(token: &[u8]) -> Option<f64> {
+        let mut pos = 0;
+        let (minutes, _) = digits(token, &mut pos)?;
+        expect(token, &mut pos, b':')?;
+        let (seconds, _) = digits(token, &mut pos)?;
+        expect(token, &mut pos, b':')?;
+        let (frames, _) = digits(token, &mut pos)?;
+        (pos == token.len()).then(|| {
+            minutes as f64 * 60.0 + seconds as f64 + frames as f64 / FRAMES_PER_SECOND as f64
+        })
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    for line in input.split_inclusive(|&b| b == b'\n') {
+        let trimmed = line.trim_ascii_start();
+        let is_time_command = [&b"INDEX "[..], b"PREGAP ", b"POSTGAP "]
+            .iter()
+            .any(|command| trimmed.starts_with(command));
+        let content_end = line.len() - (line.len() - line.trim_ascii_end().len());
+        let token_start = line[..content_end]
+            .iter()
+            .rposition(|b| b.is_ascii_whitespace())
+            .map_or(0, |i| i + 1);
+        let time = is_time_command
+            .then(|| parse(&line[token_start..content_end]))
+            .flatten();
+        let Some(time) = time else {
+            output.extend_from_slice(line);
+            continue;
+        };
+        let is_gap = !trimmed.starts_with(b"INDEX ");
+        let mapped = if is_gap {
+            // Gaps are durations rather than positions in the file.
+            map_time(time) - map_time(0.0)
+        } else {
+            map_time(time)
+        };
+        let frames = (mapped.max(0.0) * FRAMES_PER_SECOND as f64).round() as u64;
+        output.extend_from_slice(&line[..token_start]);
+        output.extend_from_slice(
+            format!(
+                "{:02}:{:02}:{:02}",
+                frames / FRAMES_PER_SECOND / 60,
+                frames / FRAMES_PER_SECOND % 60,
+                frames % FRAMES_PER_SECOND
+            )
+            .as_bytes(),
+        );
+        output.extend_from_slice(&line[content_end..]);
+    }
+    output
+}
+
+/// Points the `FILE` commands of a CUE sheet that name `from`, quoted or not,
+/// to `to`.
+fn retarget_cue(input: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    for line in input.split_inclusive(|&b| b == b'\n') {
+        let name = line
+            .trim_ascii_start()
+            .strip_prefix(b"FILE ")
+            .map(<[u8]>::trim_ascii_start);
+        let span = name.and_then(|name| {
+            let start = line.len() - name.len();
+            match name.strip_prefix(b"\"") {
+                Some(quoted) => {
+                    let len = quoted.iter().position(|&b| b == b'"')?;
+                    Some((start + 1, start + 1 + len))
+                }
+                None => {
+                    let len = name
+                        .iter()
+                        .position(u8::is_ascii_whitespace)
+                        .unwrap_or(name.len());
+                    Some((start, start + len))
+                }
+            }
+        });
+        match span {
+            Some((start, end)) if &line[start..end] == from => {
+                let quoted = line[start - 1] == b'"';
+                output.extend_from_slice(&line[..start - usize::from(quoted)]);
+                output.push(b'"');
+                output.extend_from_slice(to);
+                output.push(b'"');
+                output.extend_from_slice(&line[end + usize::from(quoted)..]);
+            }
+            _ => output.extend_from_slice(line),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rescale_cue, rescale_lrc, rescale_srt, retarget_cue};
+
+    fn faster(time: f64) -> f64 {
+        time / 1.5
+    }
+
+    #[test]
+    fn rescales_lrc_timestamps() {
+        let lrc = b"[ar:Someone]\n[offset:+300]\n[00:03.00][01:30.00]Chorus\n[00:06]Verse\n";
+        assert_eq!(
+            rescale_lrc(lrc, &faster),
+            b"[ar:Someone]\n[offset:+200]\n[00:02.00][01:00.00]Chorus\n[00:04]Verse\n"
+        );
+        // Unterminated and malformed tags are left alone
+        assert_eq!(
+            rescale_lrc(b"[00:03.00 [0a:03]", &faster),
+            b"[00:03.00 [0a:03]"
+        );
+    }
+
+    #[test]
+    fn rescales_srt_timestamps() {
+        let srt = b"1\n00:00:03,000 --> 01:30:00,500\nAt 00:00:03,000\n";
+        assert_eq!(
+            rescale_srt(srt, &faster),
+            b"1\n00:00:02,000 --> 01:00:00,333\nAt 00:00:03,000\n"
+        );
+    }
+
+    #[test]
+    fn rescales_cue_times_to_whole_frames() {
+        let cue = b"FILE \"a.flac\" WAVE\n  TRACK 01 AUDIO\n    PREGAP 00:03:00\n    INDEX 01 00:00:74\n    INDEX 02 02:00:02\n";
+        assert_eq!(
+            rescale_cue(cue, &faster),
+            b"FILE \"a.flac\" WAVE\n  TRACK 01 AUDIO\n    PREGAP 00:02:00\n    INDEX 01 00:00:49\n    INDEX 02 01:20:01\n"
+        );
+    }
+
+    #[test]
+    fn retargets_cue_files_naming_the_audio() {
+        let cue = b"FILE \"a.flac\" WAVE\nFILE a.flac WAVE\nFILE \"b.flac\" WAVE\n";
+        assert_eq!(
+            retarget_cue(cue, b"a.flac", b"a.m4a"),
+            b"FILE \"a.m4a\" WAVE\nFILE \"a.m4a\" WAVE\nFILE \"b.flac\" WAVE\n"
+        );
+    }
+}
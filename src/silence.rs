@@ -0,0 +1,247 @@
+//! Silence detection for silence-aware variable speed.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::{CommandRunner, Error, Result, exec};
+
+/// Options for speeding up silent segments differently from speech.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SilenceOptions {
+    /// Speed multiplier applied to silent segments.
+    pub speed: f32,
+    /// Audio quieter than this level (in dB) is considered silence.
+    pub threshold_db: f32,
+    /// Minimum length of a quiet stretch for it to count as silence.
+    pub min_duration: Duration,
+}
+
+impl SilenceOptions {
+    /// Creates silence options with the given speed, a -30 dB threshold and a
+    /// minimum silence length of half a second.
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            threshold_db: -30.0,
+            min_duration: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Options for trimming the dead air at the start and end of each file,
+/// separately from [`SilenceOptions`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrimOptions {
+    /// Audio quieter than this level (in dB) is considered dead air.
+    pub threshold_db: f32,
+    /// Length of the silence kept before the first and after the last sound.
+    pub padding: Duration,
+}
+
+impl TrimOptions {
+    /// Creates trim options with a -50 dB threshold and a padding of a fifth
+    /// of a second.
+    pub fn new() -> Self {
+        Self {
+            threshold_db: -50.0,
+            padding: Duration::from_millis(200),
+        }
+    }
+}
+
+impl Default for TrimOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A silent segment of a file, in seconds from the start of the input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Silence {
+    pub(crate) start: f64,
+    pub(crate) end: f64,
+}
+
+/// Runs ffmpeg's `silencedetect` filter over `path` and returns the silent
+/// segments quieter than `threshold_db` and longer than `min_duration`.
+///
+/// # Returns
+///
+/// * `Result<Vec<Silence>>` - The silent segments in order, or an error if ffmpeg could not be
+///   run or failed.
+pub(crate) fn detect_silence(
+    runner: &dyn CommandRunner,
+    path: &Path,
+    threshold_db: f32,
+    min_duration: Duration,
+) -> Result<Vec<Silence>> {
+    let output = runner
+        .output(
+            exec::command("ffmpeg")
+                .arg("-i")
+                .arg(path)
+                .args([
+                    "-af",
+                    &format!(
+                        "silencedetect=noise={}dB:d={}",
+                        threshold_db,
+                        min_duration.as_secs_f64()
+                    ),
+                    "-f",
+                    "null",
+                    "-",
+                    "-hide_banner",
+                    "-nostats",
+                ])
+                .stdin(Stdio::null()),
+        )
+        .map_err(Error::from_spawn)?;
+    if !output.status.success() {
+        return Err(Error::FfmpegFailed {
+            path: path.to_path_buf(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(parse_silencedetect(&String::from_utf8_lossy(
+        &output.stderr,
+    )))
+}
+
+/// Parses the `silence_start` / `silence_end` lines logged by `silencedetect`.
+fn parse_silencedetect(log: &str) -> Vec<Silence> {
+    fn value_after<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+        let rest = &line[line.find(key)? + key.len()..];
+        rest.split_whitespace().next()
+    }
+
+    let mut silences = Vec::new();
+    let mut start = None;
+    for line in log.lines() {
+        if let Some(value) = value_after(line, "silence_start:") {
+            start = value.parse::<f64>().ok().map(|s| s.max(0.0));
+        } else if let Some(value) = value_after(line, "silence_end:")
+            && let Some(start) = start.take()
+            && let Ok(end) = value.parse::<f64>()
+        {
+            silences.push(Silence { start, end });
+        }
+    }
+    // A file that ends in silence has a start without a matching end.
+    if let Some(start) = start {
+        silences.push(Silence {
+            start,
+            end: f64::INFINITY,
+        });
+    }
+    silences
+}
+
+/// The part of a file kept by edge trimming, in seconds from the start of the
+/// input. `end` is infinite when the end of the file is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Trim {
+    pub(crate) start: f64,
+    pub(crate) end: f64,
+}
+
+/// Finds the part of a file to keep from its silent segments, so that at most
+/// `padding` of silence remains before the first and after the last sound.
+/// A silence counts as trailing if ffmpeg left it open, or if it reaches
+/// `duration`.
+///
+/// # Returns
+///
+/// * `Option<Trim>` - The part to keep, or `None` if there is nothing to trim or the whole file is
+///   silent.
+pub(crate) fn edge_trim(
+    silences: &[Silence],
+    duration: Option<f64>,
+    padding: Duration,
+) -> Option<Trim> {
+    // silencedetect reports the silence of the first frames at zero or a
+    // little after
+    const EDGE: f64 = 0.05;
+    let padding = padding.as_secs_f64();
+    let reaches_end =
+        |s: &Silence| !s.end.is_finite() || duration.is_some_and(|d| s.end >= d - EDGE);
+    let leading = silences.first().filter(|s| s.start <= EDGE);
+    if leading.is_some_and(reaches_end) {
+        return None;
+    }
+    let start = leading.map_or(0.0, |s| (s.end - padding).max(0.0));
+    let end = silences
+        .last()
+        .filter(|s| reaches_end(s))
+        .map_or(f64::INFINITY, |s| s.start + padding);
+    (start > 0.0 || end.is_finite()).then_some(Trim { start, end })
+}
+
+/// Builds an audio filter that plays speech at `speed` and the given silent
+/// segments at `silence_speed`, by switching the tempo of `tempo_filter`
+/// (`atempo` or `rubberband`, which both take a `tempo` option and command) at
+/// segment boundaries. With `trim`, only that part of the input is kept.
+pub(crate) fn variable_tempo_filter(
+    tempo_filter: &str,
+    speed: f32,
+    silence_speed: f32,
+    silences: &[Silence],
+    trim: Option<Trim>,
+) -> String {
+    let commands: Vec<String> = silences
+        .iter()
+        .flat_map(|s| {
+            let mut commands = vec![format!(
+                "{} {} tempo {}",
+                s.start, tempo_filter, silence_speed
+            )];
+            if s.end.is_finite() {
+                commands.push(format!("{} {} tempo {}", s.end, tempo_filter, speed));
+            }
+            commands
+        })
+        .collect();
+    let mut filters = Vec::new();
+    if !commands.is_empty() {
+        filters.push(format!("asendcmd=c='{}'", commands.join(";")));
+    }
+    // The trim comes after asendcmd, whose command times are those of the
+    // input, and does not buffer, so the tempo changes stay in place.
+    if let Some(trim) = trim {
+        let mut atrim = format!("atrim=start={:.3}", trim.start);
+        if trim.end.is_finite() {
+            atrim.push_str(&format!(":end={:.3}", trim.end));
+        }
+        filters.push(atrim);
+        filters.push("asetpts=PTS-STARTPTS".to_string());
+    }
+    filters.push(format!("{}=tempo={}", tempo_filter, speed));
+    filters.join(",")
+}
+
+/// Maps a timestamp `t` (in seconds) of the input to the corresponding
+/// timestamp of the output of [`variable_tempo_filter`].
+pub(crate) fn map_time(
+    t: f64,
+    speed: f32,
+    silence_speed: f32,
+    silences: &[Silence],
+    trim: Option<Trim>,
+) -> f64 {
+    match trim {
+        Some(trim) => {
+            let map = |t: f64| map_time(t, speed, silence_speed, silences, None);
+            map(t.clamp(trim.start, trim.end)) - map(trim.start)
+        }
+        None => map_untrimmed(t, speed, silence_speed, silences),
+    }
+}
+
+/// [`map_time`] for a filter that keeps the whole input.
+fn map_untrimmed(t: f64, speed: f32, silence_speed: f32, silences: &[Silence]) -> f64 {
+    let silent: f64 = silences
+        .iter()
+        .map(|s| (t.min(s.end) - s.start).max(0.0))
+        .sum();
+    (t - silent).max(0.0) / speed as f64 + silent / silence_speed as f64
+}
@@ -0,0 +1,113 @@
+//! `tracing` spans of a run, one per batch with one per file inside it, or
+//! stand-ins that record nothing when the `tracing` feature is disabled.
+
+#[cfg(feature = "tracing")]
+pub(crate) use traced::*;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use untraced::*;
+
+#[cfg(feature = "tracing")]
+mod traced {
+    use std::time::Duration;
+
+    use tracing::field::{Empty, display};
+
+    use crate::{AudioFormat, FileReport, FileStatus, PlannedFile, ProcessOptions};
+
+    /// The span of a batch run, or of a file within one.
+    pub(crate) struct Span(tracing::Span);
+
+    impl Span {
+        /// Opens the span of a batch run with `options`, `streaming` if the
+        /// files are processed while the folder is walked.
+        pub(crate) fn batch(options: &ProcessOptions, streaming: bool) -> Self {
+            Self(tracing::info_span!(
+                "batch",
+                speed = options.speed,
+                streaming,
+                files = Empty,
+                processed = Empty,
+                skipped = Empty,
+                failed = Empty,
+            ))
+        }
+
+        /// Opens the span of `file` within this batch. Files run on worker
+        /// threads, so the batch is set as their parent explicitly.
+        pub(crate) fn file(&self, file: &PlannedFile) -> Self {
+            Self(tracing::info_span!(
+                parent: &self.0,
+                "file",
+                path = %file.path.display(),
+                format = file.format.map(AudioFormat::name),
+                speed = Empty,
+                input_duration_secs = Empty,
+                output_duration_secs = Empty,
+                outcome = Empty,
+                elapsed_secs = Empty,
+            ))
+        }
+
+        /// Enters the span on the current thread until the guard is dropped.
+        pub(crate) fn enter(&self) -> tracing::span::Entered<'_> {
+            self.0.enter()
+        }
+
+        /// Records the outcome of a file that took `elapsed` on its span.
+        pub(crate) fn record_file(&self, report: &FileReport, elapsed: Duration) {
+            self.0.record("speed", report.speed);
+            self.0.record(
+                "input_duration_secs",
+                report.input_duration.map(|d| d.as_secs_f64()),
+            );
+            self.0.record(
+                "output_duration_secs",
+                report.output_duration.map(|d| d.as_secs_f64()),
+            );
+            self.0.record("outcome", display(report.status));
+            self.0.record("elapsed_secs", elapsed.as_secs_f64());
+        }
+
+        /// Records the counts of `reports`, the files of the batch handed to
+        /// ffmpeg, on its span.
+        pub(crate) fn record_batch(&self, reports: &[FileReport]) {
+            let count = |status| reports.iter().filter(|r| r.status == status).count();
+            self.0.record("files", reports.len());
+            self.0.record("processed", count(FileStatus::Processed));
+            self.0.record("skipped", count(FileStatus::Skipped));
+            self.0.record("failed", count(FileStatus::Failed));
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod untraced {
+    use std::time::Duration;
+
+    use crate::{FileReport, PlannedFile, ProcessOptions};
+
+    /// Stands in for the span of a batch run or of a file.
+    pub(crate) struct Span;
+
+    /// Stands in for `tracing::span::Entered`.
+    pub(crate) struct Entered;
+
+    impl Span {
+        pub(crate) fn batch(_options: &ProcessOptions, _streaming: bool) -> Self {
+            Self
+        }
+
+        pub(crate) fn file(&self, _file: &PlannedFile) -> Self {
+            Self
+        }
+
+        pub(crate) fn enter(&self) -> Entered {
+            Entered
+        }
+
+        pub(crate) fn record_file(&self, _report: &FileReport, _elapsed: Duration) {}
+
+        pub(crate) fn record_batch(&self, _reports: &[FileReport]) {}
+    }
+}
@@ -0,0 +1,120 @@
+//! The [`Speed`] type and the formats it can be parsed from.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Error, Result};
+
+/// A playback speed, as a multiplier of the original speed (`1.5` plays 50%
+/// faster).
+///
+/// Besides a plain number, a speed can be parsed from a fraction (`3/2`), a
+/// percentage of the original speed (`150%`), or a relative change
+/// (`+25%`, `-10%`).
+///
+/// # Example
+///
+/// ```
+/// use audio_batch_speedup::Speed;
+///
+/// let speed: Speed = "3/2".parse().unwrap();
+/// assert_eq!(speed.factor(), 1.5);
+/// assert_eq!("150%".parse::<Speed>().unwrap(), speed);
+/// assert_eq!("+25%".parse::<Speed>().unwrap().factor(), 1.25);
+/// assert!("0".parse::<Speed>().is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Speed(f32);
+
+impl Speed {
+    /// Creates a speed from a multiplier.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Speed>` - [`Error::InvalidSpeed`] if `factor` is not a positive, finite number.
+    pub fn new(factor: f32) -> Result<Self> {
+        if !factor.is_finite() {
+            return Err(Error::InvalidSpeed(format!("{} is not a number", factor)));
+        }
+        if factor <= 0.0 {
+            return Err(Error::InvalidSpeed(format!(
+                "{} is not greater than zero",
+                factor
+            )));
+        }
+        Ok(Self(factor))
+    }
+
+    /// Creates a speed from a percentage of the original speed, e.g. `150.0`
+    /// for 1.5x.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Speed>` - [`Error::InvalidSpeed`] if `percent` is not positive.
+    pub fn from_percent(percent: f32) -> Result<Self> {
+        Self::new(percent / 100.0)
+    }
+
+    /// Returns the speed as a multiplier, as used in
+    /// [`ProcessOptions::speed`](crate::ProcessOptions::speed).
+    pub fn factor(self) -> f32 {
+        self.0
+    }
+
+    /// Returns the speed as a percentage of the original speed.
+    pub fn percent(self) -> f32 {
+        self.0 * 100.0
+    }
+}
+
+impl From<Speed> for f32 {
+    fn from(speed: Speed) -> Self {
+        speed.factor()
+    }
+}
+
+impl TryFrom<f32> for Speed {
+    type Error = Error;
+
+    fn try_from(factor: f32) -> Result<Self> {
+        Self::new(factor)
+    }
+}
+
+impl FromStr for Speed {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let number = |n: &str| {
+            n.trim()
+                .parse::<f32>()
+                .map_err(|_| Error::InvalidSpeed(format!("'{}' is not a number", n.trim())))
+        };
+        if let Some(percent) = s.strip_suffix('%') {
+            // A sign makes it a change relative to the original speed
+            return match percent.trim_start().strip_prefix('+') {
+                Some(change) => Self::from_percent(100.0 + number(change)?),
+                None if percent.trim_start().starts_with('-') => {
+                    Self::from_percent(100.0 + number(percent)?)
+                }
+                None => Self::from_percent(number(percent)?),
+            }
+            .map_err(|_| Error::InvalidSpeed(format!("'{}' is not a positive speed", s)));
+        }
+        if let Some((numerator, denominator)) = s.split_once('/') {
+            let denominator = number(denominator)?;
+            if denominator == 0.0 {
+                return Err(Error::InvalidSpeed(format!("'{}' divides by zero", s)));
+            }
+            return Self::new(number(numerator)? / denominator);
+        }
+        Self::new(number(s)?)
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x", self.0)
+    }
+}
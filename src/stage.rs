@@ -0,0 +1,36 @@
+//! Local copies of files on slow storage, processed instead of the originals
+//! with [`ProcessOptions::stage_local`](crate::ProcessOptions::stage_local).
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{IoLimit, remove_temp_file, throttle};
+
+/// A local copy of a file, removed when dropped.
+pub(crate) struct StagedFile(PathBuf);
+
+impl StagedFile {
+    /// Copies `path` to `staged` within `io_limit`.
+    pub(crate) fn copy(
+        path: &Path,
+        staged: PathBuf,
+        io_limit: Option<&IoLimit>,
+    ) -> io::Result<Self> {
+        if let Err(e) = throttle::copy_file(path, &staged, io_limit) {
+            remove_temp_file(&staged);
+            return Err(e);
+        }
+        Ok(Self(staged))
+    }
+
+    /// Returns the path of the copy.
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        remove_temp_file(&self.0);
+    }
+}
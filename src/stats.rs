@@ -0,0 +1,61 @@
+//! Statistics about the audio files in a tree.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::par::*;
+use crate::{AudioFormat, ProcessOptions, Result, ffmpeg, plan};
+
+/// Totals for a group of files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormatStats {
+    /// Number of files.
+    pub count: usize,
+    /// Total size in bytes.
+    pub size: u64,
+    /// Total duration of the files whose duration could be probed.
+    pub duration: Duration,
+}
+
+/// Statistics about the audio files in a tree, as returned by [`tree_stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    /// Totals per detected format.
+    pub per_format: BTreeMap<AudioFormat, FormatStats>,
+}
+
+impl TreeStats {
+    /// Totals over all formats.
+    pub fn total(&self) -> FormatStats {
+        self.per_format
+            .values()
+            .fold(FormatStats::default(), |acc, s| FormatStats {
+                count: acc.count + s.count,
+                size: acc.size + s.size,
+                duration: acc.duration + s.duration,
+            })
+    }
+}
+
+/// Collects per-format counts, sizes and durations of the files under
+/// `folder` that match `options`.
+pub fn tree_stats(folder: impl AsRef<Path>, options: &ProcessOptions) -> Result<TreeStats> {
+    let files: Vec<_> = plan(folder, options)?
+        .into_par_iter()
+        .filter(|f| f.will_process())
+        .filter_map(|f| {
+            let duration = ffmpeg::probe_duration(&*options.runner, &f.path).unwrap_or_default();
+            Some((f.format?, f.size, duration))
+        })
+        .collect();
+
+    let mut stats = TreeStats::default();
+    for (format, size, duration) in files {
+        let entry = stats.per_format.entry(format).or_default();
+        entry.count += 1;
+        entry.size += size;
+        entry.duration += duration;
+    }
+    Ok(stats)
+}
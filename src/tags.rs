@@ -0,0 +1,123 @@
+//! Reading the metadata tags of files, and filtering files by them.
+
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+
+use crate::{CommandRunner, Error, Result, exec};
+
+/// A condition on a metadata tag, e.g. `genre=Podcast` or `genre!=Music`,
+/// used in [`ProcessOptions::tag_filters`](crate::ProcessOptions::tag_filters).
+///
+/// A file matches if it has a tag named [`key`](Self::key), at the container
+/// level or on one of its streams, whose value is one of
+/// [`values`](Self::values), or, if the filter is [`negated`](Self::negated),
+/// if it has no such tag. Names and values are compared ignoring case.
+///
+/// # Example
+///
+/// ```
+/// use audio_batch_speedup::TagFilter;
+///
+/// let filter: TagFilter = "genre=Podcast|Audiobook".parse().unwrap();
+/// assert_eq!(filter.key, "genre");
+/// assert_eq!(filter.values, ["Podcast", "Audiobook"]);
+/// assert!(!filter.negated);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagFilter {
+    /// Name of the tag, e.g. `genre`, `artist` or `album`.
+    pub key: String,
+    /// The accepted values of the tag.
+    pub values: Vec<String>,
+    /// Whether the filter accepts the files without any of the values.
+    pub negated: bool,
+}
+
+impl TagFilter {
+    /// Returns whether `tags`, as read by [`read_tags`], satisfy the filter.
+    pub(crate) fn matches(&self, tags: &[(String, String)]) -> bool {
+        self.negated
+            != tags.iter().any(|(key, value)| {
+                key.eq_ignore_ascii_case(&self.key)
+                    && self
+                        .values
+                        .iter()
+                        .any(|accepted| accepted.to_lowercase() == value.to_lowercase())
+            })
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = Error;
+
+    /// Parses `key=value` or `key!=value`, where alternative values are
+    /// separated by `|`.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((key, values)) = s.split_once('=') else {
+            return Err(Error::InvalidOptions(format!(
+                "tag filter '{}' is not of the form key=value or key!=value",
+                s
+            )));
+        };
+        let (key, negated) = match key.strip_suffix('!') {
+            Some(key) => (key.trim(), true),
+            None => (key.trim(), false),
+        };
+        if key.is_empty() {
+            return Err(Error::InvalidOptions(format!(
+                "tag filter '{}' has no tag name",
+                s
+            )));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            values: values.split('|').map(|v| v.trim().to_string()).collect(),
+            negated,
+        })
+    }
+}
+
+impl fmt::Display for TagFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = if self.negated { "!=" } else { "=" };
+        write!(f, "{}{}{}", self.key, operator, self.values.join("|"))
+    }
+}
+
+/// Reads the tags of `path` with ffprobe, at the container level and on its
+/// streams (as in Ogg files).
+///
+/// # Returns
+///
+/// * `Vec<(String, String)>` - The names and values of the tags, empty if they could not be read.
+pub(crate) fn read_tags(runner: &dyn CommandRunner, path: &Path) -> Vec<(String, String)> {
+    let Ok(output) = runner.output(
+        exec::command("ffprobe")
+            .args([
+                "-v",
+                "error",
+                "-show_entries",
+                "format_tags:stream_tags",
+                "-of",
+                "default=noprint_wrappers=1",
+            ])
+            .arg(path)
+            .stdin(Stdio::null()),
+    ) else {
+        return Vec::new();
+    };
+    if !output.status.success() {
+        return Vec::new();
+    }
+    // Lines look like "TAG:genre=Podcast"
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let key = key.strip_prefix("TAG:").unwrap_or(key);
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,112 @@
+//! Limiting the disk bandwidth used by file copies, so runs on shared storage
+//! leave room for other users.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::num::NonZeroU64;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Size of the chunks throttled copies are made in.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A limit on the bytes per second copied by a run, shared by all its
+/// workers, set with [`ProcessOptions::io_limit`](crate::ProcessOptions::io_limit).
+///
+/// The limit applies to the copies made by the run itself: staging files
+/// locally, backups, outputs moved across file systems and copies over
+/// duplicates. What ffmpeg reads and writes is not limited.
+///
+/// # Example
+///
+/// ```
+/// use std::num::NonZeroU64;
+///
+/// use audio_batch_speedup::{AudioFormat, IoLimit, ProcessOptions};
+///
+/// let limit = IoLimit::new(NonZeroU64::new(50_000_000).unwrap());
+/// assert_eq!(limit.bytes_per_sec(), 50_000_000);
+/// let options = ProcessOptions {
+///     io_limit: Some(limit),
+///     ..ProcessOptions::new(1.5, AudioFormat::ALL)
+/// };
+/// ```
+#[derive(Clone)]
+pub struct IoLimit(Arc<Bucket>);
+
+/// The state shared by the clones of an [`IoLimit`].
+struct Bucket {
+    bytes_per_sec: NonZeroU64,
+    /// When the next chunk may start, once the earlier ones have had their
+    /// share of the bandwidth.
+    next: Mutex<Instant>,
+}
+
+impl IoLimit {
+    /// Creates a limit of `bytes_per_sec`.
+    pub fn new(bytes_per_sec: NonZeroU64) -> Self {
+        Self(Arc::new(Bucket {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        }))
+    }
+
+    /// Returns the number of bytes per second allowed.
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.0.bytes_per_sec.get()
+    }
+
+    /// Waits until `bytes` more bytes can be transferred without exceeding the
+    /// limit, counting them as transferred.
+    fn consume(&self, bytes: usize) {
+        let share = Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec() as f64);
+        let start = {
+            let mut next = self.0.next.lock().unwrap_or_else(|e| e.into_inner());
+            let start = (*next).max(Instant::now());
+            *next = start + share;
+            start
+        };
+        std::thread::sleep(start.saturating_duration_since(Instant::now()));
+    }
+}
+
+impl fmt::Debug for IoLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IoLimit")
+            .field(&self.bytes_per_sec())
+            .finish()
+    }
+}
+
+impl PartialEq for IoLimit {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Copies `from` to `to` like [`std::fs::copy`], within `limit` if set.
+pub(crate) fn copy_file(from: &Path, to: &Path, limit: Option<&IoLimit>) -> io::Result<u64> {
+    let Some(limit) = limit else {
+        return std::fs::copy(from, to);
+    };
+    let mut input = File::open(from)?;
+    let permissions = input.metadata()?.permissions();
+    let mut output = File::create(to)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut copied = 0;
+    loop {
+        let read = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        limit.consume(read);
+        output.write_all(&buffer[..read])?;
+        copied += read as u64;
+    }
+    output.set_permissions(permissions)?;
+    Ok(copied)
+}
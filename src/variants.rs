@@ -0,0 +1,92 @@
+//! Producing several speed variants of every file in one run.
+
+use std::path::Path;
+
+use crate::{
+    Error, FileReport, PlannedFile, ProcessOptions, ProcessReport, Result, naming,
+    process_planned_files,
+};
+
+/// Processes the files of a plan once per speed in `speeds`, writing one
+/// output per speed instead of replacing the originals.
+///
+/// Outputs are named after [`ProcessOptions::name_template`], which must
+/// contain `{speed}` when several speeds are given. Without a template, they
+/// are named `{stem}_{speed}x.{ext}` (with `_{part}` before the extension
+/// when segmenting), next to the original or below
+/// [`ProcessOptions::output_dir`] if set. [`ProcessOptions::speed`] is
+/// ignored.
+///
+/// # Arguments
+///
+/// * `files` - The planned files.
+/// * `options` - Options controlling how files are processed.
+/// * `speeds` - The speeds to produce.
+///
+/// # Returns
+///
+/// * `Result<ProcessReport>` - A report with one entry per processed file and speed, and one
+///   per skipped file.
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{plan, process_speed_variants, AudioFormat, ProcessOptions};
+///
+/// let options = ProcessOptions {
+///     output_dir: Some("path/to/variants".into()),
+///     ..ProcessOptions::new(1.0, AudioFormat::MP3)
+/// };
+/// let files = plan("path/to/episodes", &options)?;
+/// process_speed_variants(files, &options, &[1.25, 1.5, 2.0])?;
+/// # Ok::<(), audio_batch_speedup::Error>(())
+/// ```
+pub fn process_speed_variants(
+    files: Vec<PlannedFile>,
+    options: &ProcessOptions,
+    speeds: &[f32],
+) -> Result<ProcessReport> {
+    let template = match &options.name_template {
+        Some(template) => template.clone(),
+        None => {
+            let dir = if options.output_dir.is_some() {
+                "{dir}/"
+            } else {
+                ""
+            };
+            let part = if options.segment.is_some() {
+                "_{part}"
+            } else {
+                ""
+            };
+            format!("{}{{stem}}_{{speed}}x{}.{{ext}}", dir, part)
+        }
+    };
+    if speeds.len() > 1 {
+        let render = |speed| naming::render_name(&template, Path::new("file.ext"), speed, Some(1));
+        if render(1.0) == render(2.0) {
+            return Err(Error::InvalidOptions(
+                "invalid name template: {speed} is required with several speeds".to_string(),
+            ));
+        }
+    }
+
+    let (files, skipped): (Vec<_>, Vec<_>) = files.into_iter().partition(|f| f.will_process());
+    let mut report = ProcessReport {
+        files: skipped.iter().map(FileReport::from_planned).collect(),
+    };
+    for &speed in speeds {
+        let variant = ProcessOptions {
+            speed,
+            name_template: Some(template.clone()),
+            ..options.clone()
+        };
+        let variant_report = process_planned_files(files.clone(), &variant)?;
+        let failed = variant_report.failed_count() > 0;
+        report.files.extend(variant_report.files);
+        if failed && options.fail_fast {
+            break;
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,248 @@
+//! Checking that processed files can still be decoded, and that they match
+//! their originals.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::par::*;
+use crate::{AudioFormat, CommandRunner, ProcessOptions, Result, exec, ffmpeg, marker, plan, tags};
+
+/// The result of verifying a single file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedFile {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// The detected audio format.
+    pub format: Option<AudioFormat>,
+    /// Duration of the file, if it could be probed.
+    pub duration: Option<Duration>,
+    /// A description of the problem, or `None` if the file decoded cleanly.
+    pub error: Option<String>,
+}
+
+impl VerifiedFile {
+    /// Returns whether the file decoded without errors.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Fully decodes every file under `folder` that would be processed with
+/// `options`, reporting files that ffmpeg cannot decode cleanly.
+///
+/// # Returns
+///
+/// * `Result<Vec<VerifiedFile>>` - One entry per checked file, or
+///   [`Error::FfmpegNotFound`](crate::Error::FfmpegNotFound) if ffmpeg is missing.
+pub fn verify_files(
+    folder: impl AsRef<Path>,
+    options: &ProcessOptions,
+) -> Result<Vec<VerifiedFile>> {
+    ffmpeg::check_ffmpeg(&*options.runner)?;
+    Ok(plan(folder, options)?
+        .into_par_iter()
+        .filter(|f| f.will_process())
+        .map(|f| VerifiedFile {
+            duration: ffmpeg::probe_duration(&*options.runner, &f.path),
+            error: decode_error(&*options.runner, &f.path),
+            path: f.path,
+            format: f.format,
+        })
+        .collect())
+}
+
+/// Decodes `path` with ffmpeg, returning a description of any error.
+fn decode_error(runner: &dyn CommandRunner, path: &Path) -> Option<String> {
+    let output = runner.output(
+        exec::command("ffmpeg")
+            .args(["-v", "error", "-nostdin", "-i"])
+            .arg(path)
+            .args(["-f", "null", "-"])
+            .stdin(Stdio::null()),
+    );
+    match output {
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if !output.status.success() {
+                Some(format!(
+                    "ffmpeg exited with {:?}: {}",
+                    output.status.code(),
+                    stderr
+                ))
+            } else if !stderr.is_empty() {
+                Some(stderr)
+            } else {
+                None
+            }
+        }
+        Err(e) => Some(format!("failed to run ffmpeg: {}", e)),
+    }
+}
+
+/// How far the duration ratio of a processed file and its original may be
+/// from the expected speed, relative to it.
+const SPEED_TOLERANCE: f32 = 0.02;
+
+/// Tags that processing is expected to change, compared ignoring case.
+const CHANGED_TAGS: [&str; 8] = [
+    "absu_speed",
+    "encoder",
+    "duration",
+    "creation_time",
+    "major_brand",
+    "minor_version",
+    "compatible_brands",
+    "handler_name",
+];
+
+/// Compares every file under `folder` that would be processed with
+/// `options` against its original at the same relative path under
+/// `originals` (e.g. a backup made with
+/// [`ProcessOptions::backup_dir`]), and reports suspicious files: those that
+/// do not decode cleanly, have no original, lost tags of the original, or
+/// whose duration does not match the speed they were processed at.
+///
+/// Originals converted to another format are found by their name without
+/// extension.
+///
+/// # Arguments
+///
+/// * `folder` - The processed folder.
+/// * `originals` - The folder holding the originals.
+/// * `speed` - The expected speed, or `None` to use the speed recorded in
+///   each processed file, if any. Without either, processed files only must
+///   not be longer than their originals.
+/// * `options` - Options selecting the files to check.
+///
+/// # Returns
+///
+/// * `Result<Vec<VerifiedFile>>` - One entry per checked file, with every problem found in
+///   [`VerifiedFile::error`], or [`Error::FfmpegNotFound`](crate::Error::FfmpegNotFound) if
+///   ffmpeg is missing.
+///
+/// # Example
+///
+/// ```no_run
+/// use audio_batch_speedup::{AudioFormat, ProcessOptions, verify_against_originals};
+///
+/// let options = ProcessOptions::new(1.0, AudioFormat::ALL);
+/// let files = verify_against_originals("audiobooks", "backup", None, &options).unwrap();
+/// for file in files.iter().filter(|f| !f.is_ok()) {
+///     println!("{}: {}", file.path.display(), file.error.as_deref().unwrap_or_default());
+/// }
+/// ```
+pub fn verify_against_originals(
+    folder: impl AsRef<Path>,
+    originals: impl AsRef<Path>,
+    speed: Option<f32>,
+    options: &ProcessOptions,
+) -> Result<Vec<VerifiedFile>> {
+    let originals = originals.as_ref();
+    ffmpeg::check_ffmpeg(&*options.runner)?;
+    // Processed files carry the marker, and must not be left out for it
+    let options = ProcessOptions {
+        skip_marked: false,
+        ..options.clone()
+    };
+    let runner = &*options.runner;
+    Ok(plan(folder, &options)?
+        .into_par_iter()
+        .filter(|f| f.will_process())
+        .map(|f| {
+            let duration = ffmpeg::probe_duration(runner, &f.path);
+            let mut problems: Vec<String> = decode_error(runner, &f.path).into_iter().collect();
+            match find_original(originals, &f.relative_path) {
+                None => problems.push("no original found".to_string()),
+                Some(original) => {
+                    let tags = tags::read_tags(runner, &f.path);
+                    let original_tags = tags::read_tags(runner, &original);
+                    let speed = speed.or_else(|| marker::marked_speed(&tags));
+                    let original_duration = ffmpeg::probe_duration(runner, &original);
+                    problems.extend(duration_problem(original_duration, duration, speed));
+                    let lost = lost_tags(&original_tags, &tags);
+                    if !lost.is_empty() {
+                        problems.push(format!("tags lost or changed: {}", lost.join(", ")));
+                    }
+                }
+            }
+            VerifiedFile {
+                path: f.path,
+                format: f.format,
+                duration,
+                error: (!problems.is_empty()).then(|| problems.join("; ")),
+            }
+        })
+        .collect())
+}
+
+/// Returns the original of the file at `relative_path` under `originals`:
+/// the file at the same path, or else the only one with the same name
+/// without extension.
+fn find_original(originals: &Path, relative_path: &Path) -> Option<PathBuf> {
+    let path = originals.join(relative_path);
+    if path.is_file() {
+        return Some(path);
+    }
+    let stem = path.file_stem()?;
+    let mut candidates = std::fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|candidate| candidate.is_file() && candidate.file_stem() == Some(stem));
+    let original = candidates.next()?;
+    candidates.next().is_none().then_some(original)
+}
+
+/// Describes how the duration of a processed file does not match the one of
+/// its original sped up by `speed`, if it does not.
+fn duration_problem(
+    original: Option<Duration>,
+    processed: Option<Duration>,
+    speed: Option<f32>,
+) -> Option<String> {
+    let (Some(original), Some(processed)) = (original, processed) else {
+        return Some("duration could not be probed".to_string());
+    };
+    if processed.is_zero() {
+        return Some("processed file is empty".to_string());
+    }
+    let ratio = (original.as_secs_f64() / processed.as_secs_f64()) as f32;
+    match speed {
+        Some(speed) if (ratio - speed).abs() > speed * SPEED_TOLERANCE => Some(format!(
+            "sped up {:.3}x instead of {}x ({:.1}s -> {:.1}s)",
+            ratio,
+            speed,
+            original.as_secs_f64(),
+            processed.as_secs_f64()
+        )),
+        None if ratio < 1.0 - SPEED_TOLERANCE => Some(format!(
+            "longer than the original ({:.1}s -> {:.1}s)",
+            original.as_secs_f64(),
+            processed.as_secs_f64()
+        )),
+        _ => None,
+    }
+}
+
+/// Returns the keys of the tags of `original` missing from `processed` or
+/// with another value there, except those processing is expected to change.
+fn lost_tags<'a>(original: &'a [(String, String)], processed: &[(String, String)]) -> Vec<&'a str> {
+    let mut lost = Vec::new();
+    for (key, value) in original {
+        let lowercase = key.to_lowercase();
+        if CHANGED_TAGS.contains(&lowercase.as_str())
+            || lowercase.starts_with("replaygain_")
+            || lost.contains(&key.as_str())
+        {
+            continue;
+        }
+        if !processed
+            .iter()
+            .any(|(k, v)| k.eq_ignore_ascii_case(key) && v == value)
+        {
+            lost.push(key.as_str());
+        }
+    }
+    lost
+}
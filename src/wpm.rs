@@ -0,0 +1,145 @@
+//! Speech rate estimation for normalizing recordings to a target number of
+//! words per minute.
+//!
+//! The audio is decoded to mono at a low sample rate and reduced to a
+//! smoothed loudness envelope. Each syllable is a peak of that envelope above
+//! the level of the pauses, separated from the previous one by a dip, and
+//! words are counted from syllables with an average number of syllables per
+//! word. The estimate is rough, but consistent enough to tell slow speakers
+//! from fast ones.
+
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::{CommandRunner, Error, Result, exec};
+
+/// Sample rate the audio is decoded at for analysis.
+const SAMPLE_RATE: usize = 8000;
+/// Number of samples per loudness frame (10 ms).
+const HOP: usize = 80;
+/// Only this many seconds from the start of the file are analyzed.
+const MAX_ANALYZED_SECS: u32 = 300;
+/// Files with less audio than this many seconds are not analyzed.
+const MIN_ANALYZED_SECS: f64 = 20.0;
+/// Number of frames the loudness envelope is smoothed over.
+const SMOOTHING: usize = 5;
+/// Frames quieter than this many decibels below the loudest speech are
+/// pauses.
+const SILENCE_DB: f64 = 25.0;
+/// How far the loudness must dip between two peaks for them to count as two
+/// syllables, in decibels.
+const MIN_DIP_DB: f64 = 2.0;
+/// Average number of syllables per word in speech.
+const SYLLABLES_PER_WORD: f64 = 1.5;
+/// Fewer syllables than this do not make a reliable estimate.
+const MIN_SYLLABLES: usize = 20;
+
+/// Estimates the speech rate of the audio file at `path`.
+///
+/// # Returns
+///
+/// * `Result<Option<f32>>` - The speech rate in words per minute, `None` if the file is
+///   too short or has no discernible speech, or an error if ffmpeg could not be run or
+///   failed.
+pub(crate) fn detect_wpm(runner: &dyn CommandRunner, path: &Path) -> Result<Option<f32>> {
+    let output = runner
+        .output(
+            exec::command("ffmpeg")
+                .arg("-i")
+                .arg(path)
+                .args([
+                    "-t",
+                    &MAX_ANALYZED_SECS.to_string(),
+                    "-vn",
+                    // Keep the band of the voice, so hum and hiss do not
+                    // fill the pauses
+                    "-af",
+                    "highpass=f=200,lowpass=f=3000",
+                    "-ac",
+                    "1",
+                    "-ar",
+                    &SAMPLE_RATE.to_string(),
+                    "-f",
+                    "f32le",
+                    "-",
+                    "-hide_banner",
+                    "-loglevel",
+                    "error",
+                    "-nostats",
+                ])
+                .stdin(Stdio::null()),
+        )
+        .map_err(Error::from_spawn)?;
+    if !output.status.success() {
+        return Err(Error::FfmpegFailed {
+            path: path.to_path_buf(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    // Loudness of each frame in decibels
+    let levels: Vec<f64> = output
+        .stdout
+        .chunks_exact(HOP * 4)
+        .map(|frame| {
+            let energy: f64 = frame
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .map(|sample| sample * sample)
+                .sum();
+            10.0 * (energy / HOP as f64 + 1e-10).log10()
+        })
+        .collect();
+    Ok(wpm_from_levels(&levels))
+}
+
+/// Estimates the speech rate from per-frame loudness levels.
+fn wpm_from_levels(levels: &[f64]) -> Option<f32> {
+    let frame_rate = SAMPLE_RATE as f64 / HOP as f64;
+    let minutes = levels.len() as f64 / frame_rate / 60.0;
+    if minutes * 60.0 < MIN_ANALYZED_SECS {
+        return None;
+    }
+    let envelope: Vec<f64> = levels
+        .windows(SMOOTHING)
+        .map(|w| w.iter().sum::<f64>() / SMOOTHING as f64)
+        .collect();
+    // The loudest frames but a few, so clicks do not raise the threshold
+    let mut sorted = envelope.clone();
+    sorted.sort_by(f64::total_cmp);
+    let loud = sorted[sorted.len() * 99 / 100];
+    let threshold = loud - SILENCE_DB;
+
+    let mut syllables = 0;
+    // The last counted peak, and the lowest level since
+    let mut last_peak: Option<f64> = None;
+    let mut dip = f64::INFINITY;
+    for w in envelope.windows(3) {
+        let level = w[1];
+        dip = dip.min(level);
+        if level < threshold {
+            last_peak = None;
+            continue;
+        }
+        if !(level > w[0] && level >= w[2]) {
+            continue;
+        }
+        match last_peak {
+            Some(peak) if dip > peak.min(level) - MIN_DIP_DB => {
+                // Still the same syllable
+                if level > peak {
+                    last_peak = Some(level);
+                    dip = level;
+                }
+            }
+            _ => {
+                syllables += 1;
+                last_peak = Some(level);
+                dip = level;
+            }
+        }
+    }
+    if syllables < MIN_SYLLABLES {
+        return None;
+    }
+    Some((syllables as f64 / SYLLABLES_PER_WORD / minutes) as f32)
+}
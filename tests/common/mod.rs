@@ -0,0 +1,132 @@
+//! Setup shared by the integration tests; each of them uses only part of it.
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use audio_batch_speedup::{AudioFormat, CommandRunner, ProcessOptions, Runner};
+
+/// Creates an empty, uniquely named directory under the system temp dir.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "audio-batch-speedup-{}-{}",
+        name,
+        std::process::id()
+    ));
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Creates a test directory holding a single `a.ogg`.
+pub fn dir_with_original(name: &str) -> PathBuf {
+    let dir = test_dir(name);
+    fs::write(dir.join("a.ogg"), b"OggS original").unwrap();
+    dir
+}
+
+/// Returns options to process every format at 1.5x with `runner`, without a
+/// progress bar.
+pub fn options_with(runner: impl CommandRunner + 'static) -> ProcessOptions {
+    ProcessOptions {
+        progress_bar: false,
+        runner: Runner::new(runner),
+        ..ProcessOptions::new(1.5, AudioFormat::ALL)
+    }
+}
+
+/// Returns an exit status with `code`, as ffmpeg would exit.
+pub fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+    }
+    #[cfg(windows)]
+    {
+        std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+    }
+}
+
+/// Pretends every file lasts ten seconds at 64 kb/s and peaks at +0.5 dBTP,
+/// decodes to 30 seconds of four syllables per second, and either writes
+/// `output` to the output of each ffmpeg run or fails it after writing part
+/// of it, as a real one does with files that are not really audio. The
+/// arguments of the runs are kept.
+pub struct FakeFfmpeg {
+    output: Option<&'static [u8]>,
+    pub runs: Mutex<Vec<Vec<String>>>,
+}
+
+impl FakeFfmpeg {
+    pub fn new(output: Option<&'static [u8]>) -> Self {
+        Self {
+            output,
+            runs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns a fake that writes `OggS faster` to every output.
+    pub fn faster() -> Self {
+        Self::new(Some(b"OggS faster"))
+    }
+}
+
+impl CommandRunner for FakeFfmpeg {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        let probes_duration = command.get_args().any(|arg| arg == "format=duration");
+        let probes_bitrate = command
+            .get_args()
+            .any(|arg| arg == "stream=bit_rate:format=bit_rate");
+        let measures_loudness = command.get_args().any(|arg| arg == "ebur128=peak=true");
+        let decodes = command.get_args().any(|arg| arg == "f32le");
+        Ok(Output {
+            status: exit_status(0),
+            stdout: if probes_duration {
+                b"10.0\n".to_vec()
+            } else if probes_bitrate {
+                b"N/A\n64123\n".to_vec()
+            } else if decodes {
+                // 100 ms bursts every 250 ms, at 8 kHz
+                (0..30 * 8000)
+                    .map(|i| if i % 2000 < 800 { 0.5f32 } else { 0.0 })
+                    .flat_map(f32::to_le_bytes)
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            stderr: if measures_loudness {
+                b"[Parsed_ebur128_0 @ 0x1] Summary:\n\n  Integrated loudness:\n    I:         -16.9 LUFS\n    Threshold: -27.2 LUFS\n\n  True peak:\n    Peak:        0.5 dBFS\n".to_vec()
+            } else {
+                Vec::new()
+            },
+        })
+    }
+
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        _timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        let args: Vec<&OsStr> = command.get_args().collect();
+        self.runs.lock().unwrap().push(
+            args.iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        );
+        // The output is the argument before `-y`
+        let position = args.iter().position(|&arg| arg == "-y").unwrap();
+        let Some(output) = self.output else {
+            fs::write(args[position - 1], b"OggS partial")?;
+            return Ok((exit_status(1), "Invalid data found".to_string()));
+        };
+        fs::write(args[position - 1], output)?;
+        on_progress(Duration::from_secs(5));
+        Ok((exit_status(0), String::new()))
+    }
+}
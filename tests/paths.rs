@@ -0,0 +1,89 @@
+//! Processing must not panic or mangle names on unusual file paths.
+
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+use audio_batch_speedup::{
+    AudioFormat, FileStatus, ProcessReport, find_temp_files, process_audio_files_with_options,
+};
+use common::{FakeFfmpeg, options_with, test_dir};
+
+/// Processes `dir` with an ffmpeg that rejects every file.
+fn process(dir: &Path) -> ProcessReport {
+    process_audio_files_with_options(dir, &options_with(FakeFfmpeg::new(None))).unwrap()
+}
+
+#[test]
+fn exotic_utf8_names() {
+    let dir = test_dir("exotic");
+    let names = [
+        "spaces and 'quotes'.ogg",
+        "日本語のファイル.ogg",
+        "-leading-dash.ogg",
+        "emoji 🎧.ogg",
+    ];
+    for name in names {
+        fs::write(dir.join(name), b"OggS not really audio").unwrap();
+    }
+
+    let report = process(&dir);
+
+    assert_eq!(report.files.len(), names.len());
+    for file in &report.files {
+        assert_eq!(file.format, Some(AudioFormat::OGG));
+        assert!(file.path.exists(), "{} was lost", file.path.display());
+    }
+    // No temporary files are left behind when ffmpeg fails.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), names.len());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = test_dir("non-utf8");
+    let path = dir.join(OsStr::from_bytes(b"caf\xe9 \xff.ogg"));
+    if fs::write(&path, b"OggS not really audio").is_err() {
+        // Some filesystems reject non-UTF-8 names entirely.
+        return;
+    }
+
+    let report = process(&dir);
+
+    assert_eq!(report.files.len(), 1);
+    assert_eq!(report.files[0].path, path);
+    assert_ne!(report.files[0].status, FileStatus::Skipped);
+    assert!(path.exists());
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn user_files_named_temp_are_not_leftovers() {
+    let dir = test_dir("temp-names");
+    for name in [
+        "a.ogg",
+        "temp_a.ogg",
+        "temp_1_a.ogg",
+        "temp_12_3_a.ogg",
+        "temp_segment_12_3_001.ogg",
+    ] {
+        fs::write(dir.join(name), b"OggS not really audio").unwrap();
+    }
+
+    let mut found = find_temp_files(&dir).unwrap();
+    found.sort();
+    assert_eq!(
+        found,
+        [
+            dir.join("temp_12_3_a.ogg"),
+            dir.join("temp_segment_12_3_001.ogg")
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
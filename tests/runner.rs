@@ -0,0 +1,1040 @@
+//! The processing pipeline, driven by a fake ffmpeg through `CommandRunner`.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+use audio_batch_speedup::{
+    AudioFormat, CommandRunner, EncoderMap, Error, FileStatus, HISTORY_NAME, IoLimit, Loudness,
+    MANIFEST_NAME, Order, OutputFormat, ProcessEvent, ProcessOptions, Runner, SkipReason,
+    TrimOptions, plan, plan_playlist, process_audio_files_streaming,
+    process_audio_files_with_options, process_planned_files, read_history, record_history,
+    render_previews, rewrite_playlist, verify_against_originals, verify_manifest, write_manifest,
+};
+use common::{FakeFfmpeg, dir_with_original, exit_status, options_with, test_dir};
+
+/// Like [`FakeFfmpeg`], but appends to the input of the first `writes` runs
+/// while they encode, as a recorder still writing the file would.
+struct Recorder {
+    ffmpeg: FakeFfmpeg,
+    writes: AtomicUsize,
+}
+
+impl CommandRunner for Recorder {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        self.ffmpeg.output(command)
+    }
+
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        if self
+            .writes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            let input = command.get_args().skip_while(|&arg| arg != "-i").nth(1);
+            let mut file = fs::File::options().append(true).open(input.unwrap())?;
+            io::Write::write_all(&mut file, b" more")?;
+        }
+        self.ffmpeg.run_with_progress(command, timeout, on_progress)
+    }
+}
+
+#[test]
+fn replaces_files_with_the_output() {
+    let dir = dir_with_original("runner-success");
+    let runner = FakeFfmpeg::faster();
+    let options = options_with(runner);
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.files.len(), 1);
+    assert_eq!(report.files[0].status, FileStatus::Processed);
+    assert_eq!(
+        report.files[0].input_duration,
+        Some(Duration::from_secs(10))
+    );
+    assert_eq!(fs::read(dir.join("a.ogg")).unwrap(), b"OggS faster");
+    // The temporary output was renamed over the original.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keeps_files_when_ffmpeg_fails() {
+    let dir = dir_with_original("runner-failure");
+    let runner = Arc::new(FakeFfmpeg::new(None));
+    let options = ProcessOptions {
+        speed: 2.0,
+        ..options_with(Arc::clone(&runner))
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.failed_count(), 1);
+    assert_eq!(
+        report.files[0].stderr.as_deref(),
+        Some("Invalid data found")
+    );
+    assert_eq!(fs::read(dir.join("a.ogg")).unwrap(), b"OggS original");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    let runs = runner.runs.lock().unwrap();
+    assert_eq!(runs.len(), 1);
+    assert!(runs[0].iter().any(|arg| arg.contains("tempo=2")));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// An ffmpeg that is not installed.
+struct MissingFfmpeg;
+
+impl CommandRunner for MissingFfmpeg {
+    fn output(&self, _command: &mut Command) -> io::Result<Output> {
+        Err(io::ErrorKind::NotFound.into())
+    }
+
+    fn run_with_progress(
+        &self,
+        _command: &mut Command,
+        _timeout: Option<Duration>,
+        _on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        Err(io::ErrorKind::NotFound.into())
+    }
+}
+
+#[test]
+fn fails_once_when_ffmpeg_is_missing() {
+    let dir = dir_with_original("runner-missing");
+    let options = ProcessOptions {
+        speed: 2.0,
+        ..options_with(MissingFfmpeg)
+    };
+
+    let result = process_audio_files_with_options(&dir, &options);
+
+    assert!(matches!(result, Err(Error::FfmpegNotFound)));
+    assert_eq!(fs::read(dir.join("a.ogg")).unwrap(), b"OggS original");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn processes_playlist_entries_into_output_dir() {
+    let dir = test_dir("runner-playlist");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.ogg"), b"OggS original").unwrap();
+    fs::write(dir.join("sub").join("b.ogg"), b"OggS original").unwrap();
+    fs::write(dir.join("unlisted.ogg"), b"OggS unlisted").unwrap();
+    let playlist = dir.join("list.m3u8");
+    fs::write(
+        &playlist,
+        "#EXTM3U\n#EXTINF:10,A\na.ogg\nsub/b.ogg\nmissing.ogg\n",
+    )
+    .unwrap();
+    let options = ProcessOptions {
+        output_dir: Some(dir.join("out")),
+        ..options_with(FakeFfmpeg::faster())
+    };
+
+    let files = plan_playlist(&playlist, &options).unwrap();
+    assert_eq!(files.len(), 2);
+    let report = process_planned_files(files, &options).unwrap();
+    assert_eq!(report.processed_count(), 2);
+    assert_eq!(rewrite_playlist(&playlist, &report).unwrap(), 2);
+
+    assert_eq!(
+        fs::read(dir.join("out").join("sub").join("b.ogg")).unwrap(),
+        b"OggS faster"
+    );
+    assert!(!dir.join("out").join("unlisted.ogg").exists());
+    let sub_b = PathBuf::from("out").join("sub").join("b.ogg");
+    assert_eq!(
+        fs::read_to_string(&playlist).unwrap(),
+        format!(
+            "#EXTM3U\n#EXTINF:10,A\n{}\n{}\nmissing.ogg\n",
+            PathBuf::from("out").join("a.ogg").display(),
+            sub_b.display()
+        )
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Reports the tags found in the content of the probed file, counting the
+/// probes.
+struct TagProber {
+    probes: AtomicUsize,
+}
+
+impl CommandRunner for TagProber {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        let probes_tags = command
+            .get_args()
+            .any(|arg| arg == "format_tags:stream_tags");
+        let mut stdout = Vec::new();
+        if probes_tags {
+            self.probes.fetch_add(1, Ordering::AcqRel);
+            let content = fs::read(command.get_args().last().unwrap())?;
+            if content.windows(14).any(|w| w == b"ABSU_SPEED=1.5") {
+                stdout.extend_from_slice(b"TAG:ABSU_SPEED=1.5\n");
+            }
+        }
+        Ok(Output {
+            status: exit_status(0),
+            stdout,
+            stderr: Vec::new(),
+        })
+    }
+
+    fn run_with_progress(
+        &self,
+        _command: &mut Command,
+        _timeout: Option<Duration>,
+        _on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        unreachable!("planning runs no encodes")
+    }
+}
+
+#[test]
+fn skips_marked_files_probing_only_those_that_may_be() {
+    let dir = test_dir("runner-marked");
+    // Ogg files whose comment headers, on their second pages, hold the tags
+    let ogg = |comment: &[u8]| {
+        let page = |packet: &[u8]| {
+            [
+                b"OggS".as_slice(),
+                &[0; 22],
+                &[1, packet.len() as u8],
+                packet,
+            ]
+            .concat()
+        };
+        [page(b"\x01vorbis"), page(comment)].concat()
+    };
+    fs::write(dir.join("a.ogg"), ogg(b"\x03vorbis title=ABSU")).unwrap();
+    fs::write(dir.join("b.ogg"), ogg(b"\x03vorbis ABSU_SPEED=1.5")).unwrap();
+    // Appended data is not searched
+    let mut appended = ogg(b"\x03vorbis title=Chapter");
+    appended.extend_from_slice(b"ABSU_SPEED=1.5");
+    fs::write(dir.join("c.ogg"), appended).unwrap();
+    let prober = Arc::new(TagProber {
+        probes: AtomicUsize::new(0),
+    });
+    let options = ProcessOptions {
+        runner: Runner::new(Arc::clone(&prober)),
+        ..ProcessOptions::new(1.5, AudioFormat::ALL)
+    };
+
+    let mut files = plan(&dir, &options).unwrap();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    assert_eq!(files[0].skip_reason, None);
+    assert_eq!(files[1].skip_reason, Some(SkipReason::AlreadyProcessed));
+    assert_eq!(files[2].skip_reason, None);
+    assert_eq!(prober.probes.load(Ordering::Acquire), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn marks_outputs_in_a_tag_of_their_own() {
+    let dir = dir_with_original("runner-marker");
+    let runner = Arc::new(FakeFfmpeg::faster());
+    let options = options_with(Arc::clone(&runner));
+
+    process_audio_files_with_options(&dir, &options).unwrap();
+
+    let runs = runner.runs.lock().unwrap();
+    assert!(runs[0].iter().any(|arg| arg == "ABSU_SPEED=1.5"));
+    assert!(!runs[0].iter().any(|arg| arg.starts_with("comment=")));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn manifest_detects_changed_outputs() {
+    let dir = dir_with_original("runner-manifest");
+    let options = options_with(FakeFfmpeg::faster());
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+    let manifest = std::env::temp_dir().join(format!(
+        "audio-batch-speedup-manifest-{}.tsv",
+        std::process::id()
+    ));
+
+    assert_eq!(write_manifest(&manifest, &dir, &report).unwrap(), 1);
+    assert_eq!(
+        fs::read_to_string(&manifest).unwrap(),
+        "sha256\tduration_secs\tpath\n\
+         3728284e5d0fdd98862b9d33b04af8a07e69464ed143102bdc25b565dc632886\t10.000\ta.ogg\n"
+    );
+    assert!(verify_manifest(&manifest, &dir).unwrap()[0].is_ok());
+    fs::write(dir.join("a.ogg"), b"OggS corrupted").unwrap();
+    let files = verify_manifest(&manifest, &dir).unwrap();
+    assert_eq!(files[0].error.as_deref(), Some("checksum mismatch"));
+    fs::remove_file(&manifest).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn manifest_ignores_invalid_durations() {
+    let dir = dir_with_original("runner-manifest-durations");
+    let manifest = dir.join("manifest.tsv");
+    let digest = "4c6b55ab00c1ce0f37b5ff1b56ae11e14f1ee1d1c10c8d2a6d5c8da8ed2dd6e2";
+    fs::write(
+        &manifest,
+        format!("sha256\tduration_secs\tpath\n{digest}\t-5\ta.ogg\n{digest}\tnan\ta.ogg\n{digest}\tinf\ta.ogg\n"),
+    )
+    .unwrap();
+
+    let files = verify_manifest(&manifest, &dir).unwrap();
+    assert_eq!(files.len(), 3);
+    assert!(files.iter().all(|file| file.duration.is_none()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_loudness_before_and_after() {
+    let dir = dir_with_original("runner-loudness");
+    let options = ProcessOptions {
+        analyze_loudness: true,
+        ..options_with(FakeFfmpeg::faster())
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    let expected = Some(Loudness {
+        integrated_lufs: -16.9,
+        true_peak_dbtp: 0.5,
+    });
+    assert_eq!(report.files[0].input_loudness, expected);
+    assert_eq!(report.files[0].output_loudness, expected);
+    assert_eq!(report.clipping_count(), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keeps_the_bitrate_of_lossy_files() {
+    let dir = dir_with_original("runner-bitrate");
+    let runner = Arc::new(FakeFfmpeg::faster());
+    let options = options_with(Arc::clone(&runner));
+    process_audio_files_with_options(&dir, &options).unwrap();
+    fs::write(dir.join("a.ogg"), b"OggS original").unwrap();
+    let options = ProcessOptions {
+        bitrate: NonZeroU32::new(96_000),
+        ..options
+    };
+    process_audio_files_with_options(&dir, &options).unwrap();
+
+    let runs = runner.runs.lock().unwrap();
+    let bitrate = |run: &[String]| {
+        let position = run.iter().position(|arg| arg == "-b:a").unwrap();
+        run[position + 1].clone()
+    };
+    assert_eq!(bitrate(&runs[0]), "64000");
+    // An explicit bitrate takes precedence
+    assert_eq!(bitrate(&runs[1]), "96000");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn replaces_read_only_files_only_when_asked() {
+    let dir = test_dir("runner-readonly");
+    let path = dir.join("a.ogg");
+    fs::write(&path, b"OggS original").unwrap();
+    let mut permissions = fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&path, permissions).unwrap();
+    let options = options_with(FakeFfmpeg::faster());
+
+    let files = plan(&dir, &options).unwrap();
+    assert_eq!(files[0].skip_reason, Some(SkipReason::ReadOnly));
+
+    let options = ProcessOptions {
+        clear_readonly: true,
+        ..options
+    };
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+    assert_eq!(report.files[0].status, FileStatus::Processed);
+    assert_eq!(fs::read(&path).unwrap(), b"OggS faster");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn keeps_the_permissions_of_the_original() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("runner-attrs");
+    let path = dir.join("a.ogg");
+    fs::write(&path, b"OggS original").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    let options = ProcessOptions {
+        preserve_attrs: true,
+        ..options_with(FakeFfmpeg::faster())
+    };
+
+    process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(fs::read(&path).unwrap(), b"OggS faster");
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn history_records_processed_files() {
+    let dir = test_dir("runner-history");
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub").join("a.ogg"), b"OggS original").unwrap();
+    let options = options_with(FakeFfmpeg::faster());
+    let history = dir.join("history.tsv");
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+    assert_eq!(record_history(&history, &dir, &report).unwrap(), 1);
+    record_history(&history, &dir, &report).unwrap();
+
+    let entries = read_history(&history).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].path, PathBuf::from("sub").join("a.ogg"));
+    assert_eq!(entries[0].status, FileStatus::Processed);
+    assert_eq!(entries[0].speed, Some(1.5));
+    assert_eq!(entries[0].input_duration, Some(Duration::from_secs(10)));
+
+    let mut content = fs::read_to_string(&history).unwrap();
+    content.push_str("2024-01-01T00:00:00Z\tprocessed\t1.5\t-5.000\tinf\tb.ogg\n");
+    fs::write(&history, content).unwrap();
+    assert_eq!(read_history(&history).unwrap().len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn target_wpm_adapts_the_speed_to_the_speech_rate() {
+    let dir = dir_with_original("runner-wpm");
+    // Four syllables per second are 160 words per minute
+    let options = ProcessOptions {
+        target_wpm: Some(240.0),
+        speed: 1.0,
+        ..options_with(FakeFfmpeg::faster())
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+    let speed = report.files[0].speed.unwrap();
+    assert!((speed - 1.5).abs() < 0.02, "speed {}", speed);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn defers_files_that_change_while_encoding() {
+    let dir = dir_with_original("runner-changing");
+    let recorder = |writes| Recorder {
+        ffmpeg: FakeFfmpeg::faster(),
+        writes: AtomicUsize::new(writes),
+    };
+    let options = ProcessOptions {
+        busy_retries: 0,
+        ..options_with(recorder(1))
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+    assert_eq!(report.changing_count(), 1);
+    assert_eq!(fs::read(dir.join("a.ogg")).unwrap(), b"OggS original more");
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    // Once the recorder is done, the retry processes the file
+    let options = ProcessOptions {
+        busy_retries: 1,
+        runner: Runner::new(recorder(1)),
+        ..options
+    };
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+    assert_eq!(report.files.len(), 1);
+    assert_eq!(report.processed_count(), 1);
+    assert_eq!(fs::read(dir.join("a.ogg")).unwrap(), b"OggS faster");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stages_files_in_the_temp_dir() {
+    let dir = dir_with_original("runner-stage");
+    let local = test_dir("runner-stage-local");
+    let runner = Arc::new(FakeFfmpeg::faster());
+    let options = ProcessOptions {
+        stage_local: true,
+        temp_dir: Some(local.clone()),
+        ..options_with(Arc::clone(&runner))
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.processed_count(), 1);
+    assert_eq!(fs::read(dir.join("a.ogg")).unwrap(), b"OggS faster");
+    let runs = runner.runs.lock().unwrap();
+    let input = runs[0]
+        .iter()
+        .skip_while(|&arg| arg != "-i")
+        .nth(1)
+        .unwrap();
+    assert!(PathBuf::from(input).starts_with(&local), "input {}", input);
+    assert_eq!(fs::read_dir(&local).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&local).unwrap();
+}
+
+#[test]
+fn processes_the_files_inside_archives() {
+    let dir = test_dir("runner-archive");
+    let out = test_dir("runner-archive-out");
+    let content = test_dir("runner-archive-content");
+    fs::create_dir(content.join("voice")).unwrap();
+    fs::write(content.join("voice/a.ogg"), b"OggS original").unwrap();
+    fs::write(content.join("readme.txt"), b"hello").unwrap();
+    let archive = dir.join("pack.tar");
+    let status = Command::new("tar")
+        .arg("-cf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&content)
+        .args(["voice", "readme.txt"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let options = ProcessOptions {
+        archives: true,
+        output_dir: Some(out.clone()),
+        ..options_with(FakeFfmpeg::faster())
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.processed_count(), 1);
+    let processed = report
+        .files
+        .iter()
+        .find(|file| file.status == FileStatus::Processed)
+        .unwrap();
+    assert_eq!(processed.path, archive.join("voice/a.ogg"));
+    let entry = |archive: &Path, name: &str| {
+        Command::new("tar")
+            .arg("-xOf")
+            .arg(archive)
+            .arg(name)
+            .output()
+            .unwrap()
+            .stdout
+    };
+    assert_eq!(entry(&out.join("pack.tar"), "voice/a.ogg"), b"OggS faster");
+    assert_eq!(entry(&out.join("pack.tar"), "readme.txt"), b"hello");
+    assert_eq!(entry(&archive, "voice/a.ogg"), b"OggS original");
+    for dir in [dir, out, content] {
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn plan_leaves_out_ignored_files() {
+    let dir = test_dir("runner-ignore");
+    for folder in ["Music/live", "Podcasts/old", "Podcasts/new"] {
+        fs::create_dir_all(dir.join(folder)).unwrap();
+    }
+    for file in [
+        "Music/live/a.ogg",
+        "Podcasts/old/b.ogg",
+        "Podcasts/new/c.ogg",
+        "Podcasts/new/d.wav",
+        "Podcasts/new/keep.wav",
+    ] {
+        fs::write(dir.join(file), b"OggS original").unwrap();
+    }
+    fs::write(
+        dir.join(".speedupignore"),
+        "# music\nMusic/\n*.wav\n!keep.wav\n",
+    )
+    .unwrap();
+    fs::write(dir.join("Podcasts/.speedupignore"), "/old\n").unwrap();
+    // Nor are the files the runs keep for themselves ever planned
+    fs::write(dir.join(HISTORY_NAME), "").unwrap();
+    fs::write(dir.join(MANIFEST_NAME), "").unwrap();
+    let options = ProcessOptions::new(1.5, AudioFormat::ALL);
+
+    let mut planned: Vec<_> = plan(&dir, &options)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.relative_path)
+        .collect();
+    planned.sort();
+
+    assert_eq!(
+        planned,
+        [
+            PathBuf::from("Podcasts/new/c.ogg"),
+            PathBuf::from("Podcasts/new/keep.wav")
+        ]
+    );
+    let options = ProcessOptions {
+        ignore_files: false,
+        ..options
+    };
+    assert_eq!(plan(&dir, &options).unwrap().len(), 5);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Answers ffprobe from the content of the probed file: originals last 15
+/// seconds and processed files 10, and both carry a title, except for files
+/// whose content ends with "untitled".
+struct Prober;
+
+impl CommandRunner for Prober {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        let probes_duration = command.get_args().any(|arg| arg == "format=duration");
+        let probes_tags = command
+            .get_args()
+            .any(|arg| arg == "format_tags:stream_tags");
+        let content = command
+            .get_args()
+            .filter_map(|arg| fs::read(arg).ok())
+            .last()
+            .unwrap_or_default();
+        let processed = content.starts_with(b"OggS faster");
+        let mut stdout = Vec::new();
+        if probes_duration {
+            stdout.extend_from_slice(if processed { b"10.0\n" } else { b"15.0\n" });
+        } else if probes_tags {
+            if !content.ends_with(b"untitled") {
+                stdout.extend_from_slice(b"TAG:title=Chapter 1\n");
+            }
+            if processed {
+                stdout.extend_from_slice(b"TAG:ABSU_SPEED=1.5\n");
+            }
+        }
+        Ok(Output {
+            status: exit_status(0),
+            stdout,
+            stderr: Vec::new(),
+        })
+    }
+
+    fn run_with_progress(
+        &self,
+        _command: &mut Command,
+        _timeout: Option<Duration>,
+        _on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        unreachable!("verifying runs no encodes")
+    }
+}
+
+#[test]
+fn verify_compares_processed_files_with_their_originals() {
+    let dir = test_dir("runner-verify");
+    let originals = test_dir("runner-verify-originals");
+    for name in ["good.ogg", "slow.ogg", "untitled.ogg", "converted.mp3"] {
+        fs::write(originals.join(name), b"OggS original").unwrap();
+    }
+    fs::write(dir.join("good.ogg"), b"OggS faster ok").unwrap();
+    fs::write(dir.join("converted.ogg"), b"OggS faster ok").unwrap();
+    fs::write(dir.join("slow.ogg"), b"OggS slow version").unwrap();
+    fs::write(dir.join("untitled.ogg"), b"OggS faster untitled").unwrap();
+    fs::write(dir.join("orphan.ogg"), b"OggS faster ok").unwrap();
+    let options = ProcessOptions {
+        runner: Runner::new(Arc::new(Prober)),
+        ..ProcessOptions::new(1.0, AudioFormat::ALL)
+    };
+
+    let files = verify_against_originals(&dir, &originals, None, &options).unwrap();
+
+    let error = |name: &str| {
+        files
+            .iter()
+            .find(|file| file.path == dir.join(name))
+            .unwrap()
+            .error
+            .clone()
+    };
+    assert_eq!(error("good.ogg"), None);
+    assert_eq!(error("converted.ogg"), None);
+    assert_eq!(error("orphan.ogg").unwrap(), "no original found");
+    assert_eq!(
+        error("untitled.ogg").unwrap(),
+        "tags lost or changed: title"
+    );
+    // Without a marker, processed files must only not be longer
+    assert_eq!(error("slow.ogg"), None);
+    let files = verify_against_originals(&dir, &originals, Some(1.5), &options).unwrap();
+    let slow = files
+        .iter()
+        .find(|f| f.path == dir.join("slow.ogg"))
+        .unwrap();
+    assert!(slow.error.as_ref().unwrap().contains("instead of 1.5x"));
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&originals).unwrap();
+}
+
+/// Like [`FakeFfmpeg`], but cuts clips by copying the whole input, keeping
+/// the times they start at.
+struct Cutter {
+    ffmpeg: FakeFfmpeg,
+    starts: Mutex<Vec<String>>,
+}
+
+impl CommandRunner for Cutter {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        let args: Vec<&OsStr> = command.get_args().collect();
+        if args.contains(&OsStr::new("copy")) {
+            let input = args.iter().skip_while(|&&arg| arg != "-i").nth(1).unwrap();
+            let position = args.iter().position(|&arg| arg == "-y").unwrap();
+            fs::copy(input, args[position - 1])?;
+            let start = args.iter().skip_while(|&&arg| arg != "-ss").nth(1).unwrap();
+            self.starts
+                .lock()
+                .unwrap()
+                .push(start.to_string_lossy().into_owned());
+        }
+        self.ffmpeg.output(command)
+    }
+
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        self.ffmpeg.run_with_progress(command, timeout, on_progress)
+    }
+}
+
+#[test]
+fn previews_clips_before_and_after() {
+    let dir = test_dir("runner-preview");
+    let preview = test_dir("runner-preview-clips");
+    fs::create_dir(dir.join("book")).unwrap();
+    fs::write(dir.join("book/a.ogg"), b"OggS original").unwrap();
+    let runner = Arc::new(Cutter {
+        ffmpeg: FakeFfmpeg::faster(),
+        starts: Mutex::new(Vec::new()),
+    });
+    let options = options_with(Arc::clone(&runner));
+    let files = plan(&dir, &options).unwrap();
+
+    let report = render_previews(&files, &options, Duration::from_secs(4), &preview).unwrap();
+
+    assert_eq!(report.processed_count(), 1);
+    assert_eq!(
+        fs::read(preview.join("original/book/a.ogg")).unwrap(),
+        b"OggS original"
+    );
+    assert_eq!(
+        fs::read(preview.join("processed/book/a.ogg")).unwrap(),
+        b"OggS faster"
+    );
+    assert_eq!(fs::read(dir.join("book/a.ogg")).unwrap(), b"OggS original");
+    // The clip starts 3 seconds into the 10 second file
+    assert_eq!(*runner.starts.lock().unwrap(), ["3.000"]);
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&preview).unwrap();
+}
+
+#[test]
+fn throttles_copies_and_ffmpeg_threads() {
+    let dir = test_dir("runner-throttle");
+    let local = test_dir("runner-throttle-local");
+    fs::write(
+        dir.join("a.ogg"),
+        [b"OggS original".as_slice(), &[0; 50_000]].concat(),
+    )
+    .unwrap();
+    let runner = Arc::new(FakeFfmpeg::faster());
+    let options = ProcessOptions {
+        stage_local: true,
+        temp_dir: Some(local.clone()),
+        io_limit: Some(IoLimit::new(NonZeroU64::new(100_000).unwrap())),
+        ffmpeg_threads: NonZeroU32::new(1),
+        ..options_with(Arc::clone(&runner))
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.processed_count(), 1);
+    assert_eq!(fs::read(dir.join("a.ogg")).unwrap(), b"OggS faster");
+    let runs = runner.runs.lock().unwrap();
+    assert!(runs[0].windows(2).any(|args| args == ["-threads", "1"]));
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&local).unwrap();
+}
+
+#[test]
+fn reports_files_in_the_processing_order() {
+    let dir = test_dir("runner-order");
+    let out = test_dir("runner-order-out");
+    for (name, size) in [("b.ogg", 30), ("c.ogg", 20), ("a.ogg", 10)] {
+        fs::write(
+            dir.join(name),
+            [b"OggS original".as_slice(), &vec![0; size]].concat(),
+        )
+        .unwrap();
+    }
+    fs::write(dir.join("notes.txt"), b"not audio").unwrap();
+    let names = |order| {
+        let options = ProcessOptions {
+            order,
+            output_dir: Some(out.clone()),
+            formats: AudioFormat::OGG,
+            ..options_with(FakeFfmpeg::faster())
+        };
+        let files = plan(&dir, &options).unwrap();
+        process_planned_files(files, &options)
+            .unwrap()
+            .files
+            .iter()
+            .map(|file| file.path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        names(Order::Name),
+        ["a.ogg", "b.ogg", "c.ogg", "notes.txt"].map(PathBuf::from)
+    );
+    assert_eq!(
+        names(Order::Size),
+        ["b.ogg", "c.ogg", "a.ogg", "notes.txt"].map(PathBuf::from)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+/// Like [`FakeFfmpeg`], but logs dead air at both ends of every file and a
+/// pause in the middle when detecting silence.
+struct DeadAir(FakeFfmpeg);
+
+impl CommandRunner for DeadAir {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        let mut output = self.0.output(command)?;
+        if command
+            .get_args()
+            .any(|arg| arg.to_string_lossy().starts_with("silencedetect="))
+        {
+            output.stderr = b"[silencedetect @ 0x1] silence_start: 0\n\
+                [silencedetect @ 0x1] silence_end: 1.5 | silence_duration: 1.5\n\
+                [silencedetect @ 0x1] silence_start: 4\n\
+                [silencedetect @ 0x1] silence_end: 5 | silence_duration: 1\n\
+                [silencedetect @ 0x1] silence_start: 8.5\n\
+                [silencedetect @ 0x1] silence_end: 10 | silence_duration: 1.5\n"
+                .to_vec();
+        }
+        Ok(output)
+    }
+
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        self.0.run_with_progress(command, timeout, on_progress)
+    }
+}
+
+#[test]
+fn trims_dead_air_at_the_edges() {
+    let dir = dir_with_original("runner-trim");
+    let runner = Arc::new(DeadAir(FakeFfmpeg::faster()));
+    let options = ProcessOptions {
+        trim_edges: Some(TrimOptions::new()),
+        ..options_with(Arc::clone(&runner))
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.processed_count(), 1);
+    let runs = runner.0.runs.lock().unwrap();
+    let filter = runs[0]
+        .windows(2)
+        .find(|args| args[0] == "-filter:a")
+        .map(|args| args[1].clone())
+        .unwrap();
+    // The pause in the middle is kept, and 0.2 seconds of padding at the ends
+    assert_eq!(
+        filter,
+        "atrim=start=1.300:end=8.700,asetpts=PTS-STARTPTS,atempo=tempo=1.5"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn skips_files_shorter_than_their_recorded_original() {
+    let dir = dir_with_original("runner-origin");
+    let options = ProcessOptions {
+        record_durations: true,
+        ..options_with(Arc::new(FakeFfmpeg::new(Some(b"OggS faster ok"))))
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.processed_count(), 1);
+    let record = dir.join(".a.ogg.absu");
+    assert_eq!(fs::read_to_string(&record).unwrap(), "10.000\n");
+    // The fake ffmpeg writes no marker; an unchanged duration is no sign of
+    // processing, and the record itself is never planned
+    let files = plan(&dir, &options).unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].skip_reason, None);
+    fs::write(&record, "15.000\n").unwrap();
+    let files = plan(&dir, &options).unwrap();
+    assert_eq!(files[0].skip_reason, Some(SkipReason::Shortened));
+    let options = ProcessOptions {
+        skip_marked: false,
+        ..options
+    };
+    assert_eq!(plan(&dir, &options).unwrap()[0].skip_reason, None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Like [`FakeFfmpeg`], but lists a few encoders, as a build without
+/// `libopus` would.
+struct Encoders(FakeFfmpeg);
+
+impl CommandRunner for Encoders {
+    fn output(&self, command: &mut Command) -> io::Result<Output> {
+        let mut output = self.0.output(command)?;
+        if command.get_args().any(|arg| arg == "-encoders") {
+            output.stdout = b"Encoders:\n A..... = Audio\n ------\n \
+                A....D aac                  AAC (Advanced Audio Coding)\n \
+                A....D libmp3lame           libmp3lame MP3 (MPEG audio layer 3)\n \
+                A....D libvorbis            libvorbis (codec vorbis)\n \
+                A....D pcm_s16le            PCM signed 16-bit little-endian\n"
+                .to_vec();
+        }
+        Ok(output)
+    }
+
+    fn run_with_progress(
+        &self,
+        command: &mut Command,
+        timeout: Option<Duration>,
+        on_progress: &mut dyn FnMut(Duration),
+    ) -> io::Result<(ExitStatus, String)> {
+        self.0.run_with_progress(command, timeout, on_progress)
+    }
+}
+
+#[test]
+fn chooses_the_encoder_of_each_format() {
+    let dir = test_dir("runner-encoder");
+    fs::write(dir.join("a.mp3"), b"ID3 original mp3").unwrap();
+    fs::write(dir.join("b.ogg"), b"OggS original").unwrap();
+    fs::write(dir.join("c.wav"), b"RIFF\0\0\0\0WAVE original").unwrap();
+    let runner = Arc::new(Encoders(FakeFfmpeg::faster()));
+    let mut encoders = EncoderMap::new();
+    encoders.set(AudioFormat::MP3, "libmp3lame");
+    let options = ProcessOptions {
+        encoders,
+        ..options_with(Arc::clone(&runner))
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+
+    assert_eq!(report.processed_count(), 3);
+    let encoder_of = |name: &str| {
+        let runs = runner.0.runs.lock().unwrap();
+        let run = runs
+            .iter()
+            .find(|run| run.iter().any(|arg| arg.ends_with(name)))
+            .unwrap();
+        run.windows(2)
+            .find(|args| args[0] == "-c:a")
+            .map(|args| args[1].clone())
+    };
+    assert_eq!(encoder_of("a.mp3").as_deref(), Some("libmp3lame"));
+    // Preferred over ffmpeg's choice, since the build has it
+    assert_eq!(encoder_of("b.ogg").as_deref(), Some("libvorbis"));
+    assert_eq!(encoder_of("c.wav"), None);
+
+    // An encoder the build lacks stops the run before any file
+    let mut encoders = EncoderMap::new();
+    encoders.set(AudioFormat::OPUS, "libopus");
+    let options = ProcessOptions {
+        encoders,
+        ..options
+    };
+    let runs = runner.0.runs.lock().unwrap().len();
+    assert!(matches!(
+        process_audio_files_with_options(&dir, &options),
+        Err(Error::InvalidOptions(_))
+    ));
+    assert_eq!(runner.0.runs.lock().unwrap().len(), runs);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn streams_files_while_walking() {
+    let dir = test_dir("runner-stream");
+    let out = test_dir("runner-stream-out");
+    // More files than are buffered ahead of the workers
+    for i in 0..300 {
+        fs::write(dir.join(format!("{:03}.ogg", i)), b"OggS original").unwrap();
+    }
+    fs::write(dir.join("notes.txt"), b"not audio").unwrap();
+    let options = ProcessOptions {
+        output_dir: Some(out.clone()),
+        formats: AudioFormat::OGG,
+        ..options_with(FakeFfmpeg::faster())
+    };
+    let finished = AtomicUsize::new(0);
+
+    let report = process_audio_files_streaming(&dir, &options, |event| {
+        if let ProcessEvent::FileFinished { .. } = event {
+            finished.fetch_add(1, Ordering::Relaxed);
+        }
+    })
+    .unwrap();
+
+    assert_eq!(report.processed_count(), 300);
+    assert_eq!(report.skipped_count(), 1);
+    assert_eq!(finished.into_inner(), 300);
+    assert_eq!(report.files[0].path, dir.join("000.ogg"));
+    assert_eq!(report.files[299].path, dir.join("299.ogg"));
+    assert_eq!(fs::read(out.join("150.ogg")).unwrap(), b"OggS faster");
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn converting_keeps_the_sidecars_of_the_originals() {
+    let dir = dir_with_original("runner-sidecars");
+    let out = test_dir("runner-sidecars-out");
+    let lrc = b"[00:03.00]Line\n";
+    let cue = b"FILE \"a.ogg\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:03:00\n";
+    fs::write(dir.join("a.lrc"), lrc).unwrap();
+    fs::write(dir.join("a.cue"), cue).unwrap();
+    let options = ProcessOptions {
+        rescale_sidecars: true,
+        output_format: Some(OutputFormat::M4b),
+        ..options_with(FakeFfmpeg::faster())
+    };
+
+    let report = process_audio_files_with_options(&dir, &options).unwrap();
+    assert_eq!(report.processed_count(), 1);
+    assert_eq!(fs::read(dir.join("a.lrc")).unwrap(), lrc);
+    assert_eq!(fs::read(dir.join("a.cue")).unwrap(), cue);
+
+    let options = ProcessOptions {
+        output_dir: Some(out.clone()),
+        ..options
+    };
+    process_audio_files_with_options(&dir, &options).unwrap();
+    assert_eq!(fs::read(out.join("a.lrc")).unwrap(), b"[00:02.00]Line\n");
+    assert_eq!(
+        fs::read(out.join("a.cue")).unwrap(),
+        b"FILE \"a.m4b\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:02:00\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}